@@ -1,10 +1,13 @@
-use chess::ChessMove;
-use crate::io::uci::Position;
+use chess::{Board, ChessMove};
+use crate::io::uci::{GoCommand, Position};
 
 pub trait Engine {
-    fn start(&mut self, time: u64);
+    fn start(&mut self, command: GoCommand);
     fn stop(&mut self);
     fn update(&mut self, fen: Position, moves: Vec<ChessMove>);
     fn restart(&mut self);
     fn evaluate(&self)->i32;
+    fn position(&self) -> Board;
+    // result of the finished game from the white point of view: 1 win, 0.5 draw, 0 loss
+    fn learn(&mut self, white_result: f64);
 }
\ No newline at end of file
@@ -0,0 +1,427 @@
+use crate::features::eval_params::EvalParams;
+use chess::{get_king_moves, get_pawn_attacks, BitBoard, Board, Color, File, Piece, Rank, Square, ALL_SQUARES, EMPTY};
+use std::sync::OnceLock;
+
+/// Scale factor of the endgame part of the score, `SCALE_NORMAL` keeps it unchanged.
+pub const SCALE_NORMAL: i32 = 64;
+const SCALE_OPPOSITE_BISHOPS: i32 = 22;
+const SCALE_OPPOSITE_BISHOPS_WITH_PIECES: i32 = 46;
+// the strong side has no pawns and at most a minor piece more: a rook or more against at most a minor
+// piece, or a minor piece more on top of other pieces like a rook and a bishop against a rook
+const SCALE_NO_PAWNS_ROOK_VS_MINOR: i32 = 4;
+const SCALE_NO_PAWNS_MINOR_UP: i32 = 14;
+
+// bonus of a won endgame, larger than any positional score but far from the mate scores
+pub const KNOWN_WIN: i32 = 10_000;
+const PUSH_TO_EDGE: i32 = 20;
+const PUSH_CLOSE: i32 = 10;
+const PUSH_TO_CORNER: i32 = 25;
+const KPK_PAWN_RANK: i32 = 20;
+
+fn count(board: &Board, color: Color, piece: Piece) -> u32 {
+    (board.pieces(piece) & board.color_combined(color)).popcnt()
+}
+
+fn only_king(board: &Board, color: Color) -> bool {
+    board.color_combined(color).popcnt() == 1
+}
+
+fn non_pawn_material(board: &Board, color: Color, params: &EvalParams) -> i32 {
+    params.knight_value.mg * count(board, color, Piece::Knight) as i32
+        + params.bishop_value.mg * count(board, color, Piece::Bishop) as i32
+        + params.rook_value.mg * count(board, color, Piece::Rook) as i32
+        + params.queen_value.mg * count(board, color, Piece::Queen) as i32
+}
+
+fn material_eg(board: &Board, color: Color, params: &EvalParams) -> i32 {
+    params.pawn_value.eg * count(board, color, Piece::Pawn) as i32
+        + params.knight_value.eg * count(board, color, Piece::Knight) as i32
+        + params.bishop_value.eg * count(board, color, Piece::Bishop) as i32
+        + params.rook_value.eg * count(board, color, Piece::Rook) as i32
+        + params.queen_value.eg * count(board, color, Piece::Queen) as i32
+}
+
+fn distance(a: Square, b: Square) -> i32 {
+    let files = (a.get_file().to_index() as i32 - b.get_file().to_index() as i32).abs();
+    let ranks = (a.get_rank().to_index() as i32 - b.get_rank().to_index() as i32).abs();
+    files.max(ranks)
+}
+
+fn manhattan_distance(a: Square, b: Square) -> i32 {
+    (a.get_file().to_index() as i32 - b.get_file().to_index() as i32).abs()
+        + (a.get_rank().to_index() as i32 - b.get_rank().to_index() as i32).abs()
+}
+
+// 0 in the center, 6 in the corners
+fn center_distance(sq: Square) -> i32 {
+    let file = sq.get_file().to_index() as i32;
+    let rank = sq.get_rank().to_index() as i32;
+    (3 - file).max(file - 4) + (3 - rank).max(rank - 4)
+}
+
+fn is_dark(sq: Square) -> bool {
+    (sq.get_file().to_index() + sq.get_rank().to_index()).is_multiple_of(2)
+}
+
+// strong side with mating material against a lone king: drive the king to the edge and come closer
+fn mop_up(board: &Board, strong: Color, params: &EvalParams) -> i32 {
+    let weak_king = board.king_square(!strong);
+    KNOWN_WIN
+        + material_eg(board, strong, params)
+        + PUSH_TO_EDGE * center_distance(weak_king)
+        + PUSH_CLOSE * (7 - distance(board.king_square(strong), weak_king))
+}
+
+// the mate is only possible in a corner of the bishop color
+fn kbnk(board: &Board, strong: Color, params: &EvalParams) -> i32 {
+    let weak_king = board.king_square(!strong);
+    let bishop = (board.pieces(Piece::Bishop) & board.color_combined(strong)).to_square();
+    let corners = if is_dark(bishop) {
+        [Square::A1, Square::H8]
+    } else {
+        [Square::A8, Square::H1]
+    };
+    let corner_distance = corners
+        .iter()
+        .map(|corner| manhattan_distance(weak_king, *corner))
+        .min()
+        .unwrap();
+    KNOWN_WIN + material_eg(board, strong, params) + PUSH_TO_CORNER * (14 - corner_distance)
+        + PUSH_CLOSE * (7 - distance(board.king_square(strong), weak_king))
+}
+
+fn kpk(board: &Board, strong: Color, params: &EvalParams) -> i32 {
+    let pawn = (board.pieces(Piece::Pawn) & board.color_combined(strong)).to_square();
+    if !kpk_is_win(
+        board.king_square(strong),
+        pawn,
+        board.king_square(!strong),
+        board.side_to_move() == strong,
+        strong,
+    ) {
+        return 0;
+    }
+    let rank = match strong {
+        Color::White => pawn.get_rank().to_index() as i32,
+        Color::Black => 7 - pawn.get_rank().to_index() as i32,
+    };
+    KNOWN_WIN + params.pawn_value.eg + KPK_PAWN_RANK * rank
+}
+
+fn has_mating_material(board: &Board, color: Color) -> bool {
+    let bishops = board.pieces(Piece::Bishop) & board.color_combined(color);
+    let bishop_colors = bishops.into_iter().map(is_dark).collect::<Vec<bool>>();
+    count(board, color, Piece::Queen) > 0
+        || count(board, color, Piece::Rook) > 0
+        || (bishop_colors.contains(&true) && bishop_colors.contains(&false))
+        || (!bishop_colors.is_empty() && count(board, color, Piece::Knight) > 0)
+}
+
+/// Score of a known endgame, white relative, with the name of the evaluator.
+pub fn specialized_eval(board: &Board, params: &EvalParams) -> Option<(&'static str, i32)> {
+    for strong in [Color::White, Color::Black] {
+        if !only_king(board, !strong) {
+            continue;
+        }
+        let sign = if strong == Color::White { 1 } else { -1 };
+        let pieces = board.color_combined(strong).popcnt();
+        let pawns = count(board, strong, Piece::Pawn);
+        if pieces == 2 && pawns == 1 {
+            return Some(("KPK", sign * kpk(board, strong, params)));
+        }
+        if pieces == 3 && count(board, strong, Piece::Bishop) == 1 && count(board, strong, Piece::Knight) == 1 {
+            return Some(("KBNK", sign * kbnk(board, strong, params)));
+        }
+        if is_wrong_rook_pawn(board, strong) {
+            return Some(("KBPsK", 0));
+        }
+        if has_mating_material(board, strong) {
+            return Some(("KXK", sign * mop_up(board, strong, params)));
+        }
+    }
+    None
+}
+
+// lone bishop and pawns on the rook file which promote on a square the bishop does not control
+fn is_wrong_rook_pawn(board: &Board, strong: Color) -> bool {
+    let pawns = board.pieces(Piece::Pawn) & board.color_combined(strong);
+    let pieces = board.color_combined(strong) & !board.pieces(Piece::King) & !pawns;
+    if pawns == EMPTY || pieces.popcnt() != 1 || (pieces & board.pieces(Piece::Bishop)) == EMPTY {
+        return false;
+    }
+    let promotion_rank = if strong == Color::White { Rank::Eighth } else { Rank::First };
+    let file = if (pawns & chess::get_file(File::A)) == pawns {
+        File::A
+    } else if (pawns & chess::get_file(File::H)) == pawns {
+        File::H
+    } else {
+        return false;
+    };
+    let promotion = Square::make_square(promotion_rank, file);
+    is_dark(pieces.to_square()) != is_dark(promotion) && distance(board.king_square(!strong), promotion) <= 1
+}
+
+/// Scale factor of the endgame score of a drawish material, `strong` is the side with the advantage.
+pub fn scale_factor(board: &Board, strong: Color, params: &EvalParams) -> i32 {
+    let weak = !strong;
+    let strong_material = non_pawn_material(board, strong, params);
+    let weak_material = non_pawn_material(board, weak, params);
+    if count(board, strong, Piece::Pawn) == 0 && strong_material - weak_material <= params.bishop_value.mg {
+        return if strong_material < params.rook_value.mg {
+            0
+        } else if weak_material <= params.bishop_value.mg {
+            SCALE_NO_PAWNS_ROOK_VS_MINOR
+        } else {
+            SCALE_NO_PAWNS_MINOR_UP
+        };
+    }
+
+    let white_bishops = board.pieces(Piece::Bishop) & board.color_combined(Color::White);
+    let black_bishops = board.pieces(Piece::Bishop) & board.color_combined(Color::Black);
+    if white_bishops.popcnt() == 1
+        && black_bishops.popcnt() == 1
+        && is_dark(white_bishops.to_square()) != is_dark(black_bishops.to_square())
+    {
+        let only_bishops = (board.pieces(Piece::Knight) | board.pieces(Piece::Rook) | board.pieces(Piece::Queen)) == EMPTY;
+        return if only_bishops {
+            SCALE_OPPOSITE_BISHOPS
+        } else {
+            SCALE_OPPOSITE_BISHOPS_WITH_PIECES
+        };
+    }
+    SCALE_NORMAL
+}
+
+// KPK bitbase, white pawn on files a-d, indexed by side to move, black king, white king and pawn
+const KPK_SIZE: usize = 2 * 64 * 64 * 24;
+
+fn kpk_index(white_to_move: bool, black_king: usize, white_king: usize, pawn: usize) -> usize {
+    let pawn_index = (pawn / 8 - 1) * 4 + pawn % 8;
+    (((white_to_move as usize) * 64 + black_king) * 64 + white_king) * 24 + pawn_index
+}
+
+#[derive(Clone, Copy, PartialEq)]
+enum KpkResult {
+    Invalid,
+    Unknown,
+    Draw,
+    Win,
+}
+
+fn square(index: usize) -> Square {
+    ALL_SQUARES[index]
+}
+
+fn king_attacks(sq: usize) -> BitBoard {
+    get_king_moves(square(sq))
+}
+
+fn pawn_attacks(pawn: usize) -> BitBoard {
+    get_pawn_attacks(square(pawn), Color::White, !EMPTY)
+}
+
+fn bit(sq: usize) -> BitBoard {
+    BitBoard::from_square(square(sq))
+}
+
+fn kpk_initial(white_to_move: bool, black_king: usize, white_king: usize, pawn: usize) -> KpkResult {
+    if black_king == white_king
+        || black_king == pawn
+        || white_king == pawn
+        || (king_attacks(white_king) & bit(black_king)) != EMPTY
+        || (white_to_move && (pawn_attacks(pawn) & bit(black_king)) != EMPTY)
+    {
+        return KpkResult::Invalid;
+    }
+    let push = pawn + 8;
+    if white_to_move
+        && pawn / 8 == 6
+        && white_king != push
+        && black_king != push
+        && (distance(square(black_king), square(push)) > 1 || distance(square(white_king), square(push)) == 1)
+    {
+        return KpkResult::Win;
+    }
+    if !white_to_move {
+        let escapes = king_attacks(black_king) & !(king_attacks(white_king) | pawn_attacks(pawn));
+        let captures_pawn = (king_attacks(black_king) & !king_attacks(white_king) & bit(pawn)) != EMPTY;
+        if escapes == EMPTY || captures_pawn {
+            return KpkResult::Draw;
+        }
+    }
+    KpkResult::Unknown
+}
+
+fn kpk_classify(table: &[KpkResult], white_to_move: bool, black_king: usize, white_king: usize, pawn: usize) -> KpkResult {
+    let mut successors = Vec::new();
+    if white_to_move {
+        for sq in king_attacks(white_king) & !king_attacks(black_king) & !bit(pawn) {
+            successors.push(table[kpk_index(false, black_king, sq.to_index(), pawn)]);
+        }
+        let push = pawn + 8;
+        if pawn / 8 < 6 && push != white_king && push != black_king {
+            successors.push(table[kpk_index(false, black_king, white_king, push)]);
+            let double_push = push + 8;
+            if pawn / 8 == 1 && double_push != white_king && double_push != black_king {
+                successors.push(table[kpk_index(false, black_king, white_king, double_push)]);
+            }
+        }
+    } else {
+        for sq in king_attacks(black_king) & !king_attacks(white_king) & !pawn_attacks(pawn) {
+            successors.push(table[kpk_index(true, sq.to_index(), white_king, pawn)]);
+        }
+    }
+    // white looks for a won successor, black for a drawn one
+    let (good, bad) = if white_to_move {
+        (KpkResult::Win, KpkResult::Draw)
+    } else {
+        (KpkResult::Draw, KpkResult::Win)
+    };
+    let successors = successors.into_iter().filter(|result| *result != KpkResult::Invalid);
+    let mut all_bad = true;
+    for result in successors {
+        if result == good {
+            return good;
+        }
+        all_bad &= result == bad;
+    }
+    if all_bad {
+        bad
+    } else {
+        KpkResult::Unknown
+    }
+}
+
+fn generate_kpk() -> Vec<u64> {
+    let mut table = vec![KpkResult::Invalid; KPK_SIZE];
+    let positions = || {
+        [false, true].into_iter().flat_map(|white_to_move| {
+            (0..64).flat_map(move |black_king| {
+                (0..64).flat_map(move |white_king| {
+                    (8..56)
+                        .filter(|pawn| pawn % 8 < 4)
+                        .map(move |pawn| (white_to_move, black_king, white_king, pawn))
+                })
+            })
+        })
+    };
+    for (white_to_move, black_king, white_king, pawn) in positions() {
+        table[kpk_index(white_to_move, black_king, white_king, pawn)] =
+            kpk_initial(white_to_move, black_king, white_king, pawn);
+    }
+
+    let mut changed = true;
+    while changed {
+        changed = false;
+        for (white_to_move, black_king, white_king, pawn) in positions() {
+            let index = kpk_index(white_to_move, black_king, white_king, pawn);
+            if table[index] != KpkResult::Unknown {
+                continue;
+            }
+            let result = kpk_classify(&table, white_to_move, black_king, white_king, pawn);
+            if result != KpkResult::Unknown {
+                table[index] = result;
+                changed = true;
+            }
+        }
+    }
+
+    let mut bits = vec![0u64; KPK_SIZE.div_ceil(64)];
+    for (index, result) in table.iter().enumerate() {
+        if *result == KpkResult::Win {
+            bits[index / 64] |= 1 << (index % 64);
+        }
+    }
+    bits
+}
+
+static KPK_BITBASE: OnceLock<Vec<u64>> = OnceLock::new();
+
+/// True when the side with the pawn wins, the bitbase is generated on the first call.
+pub fn kpk_is_win(strong_king: Square, pawn: Square, weak_king: Square, strong_to_move: bool, strong: Color) -> bool {
+    // seen from the white side, with the pawn on files a-d
+    let flip = |sq: Square| match strong {
+        Color::White => sq.to_index(),
+        Color::Black => sq.to_index() ^ 56,
+    };
+    let mirror = if pawn.get_file().to_index() >= 4 { 7 } else { 0 };
+    let index = kpk_index(
+        strong_to_move,
+        flip(weak_king) ^ mirror,
+        flip(strong_king) ^ mirror,
+        flip(pawn) ^ mirror,
+    );
+    let bits = KPK_BITBASE.get_or_init(generate_kpk);
+    bits[index / 64] & (1 << (index % 64)) != 0
+}
+
+#[cfg(test)]
+mod endgame_tests {
+    use super::*;
+    use crate::features::evaluation::eval_with_params;
+    use std::str::FromStr;
+    use test_case::test_case;
+
+    fn board(fen: &str) -> Board {
+        Board::from_str(fen).unwrap()
+    }
+
+    #[test_case("8/8/8/8/8/8/4P3/k3K3 w - - 0 1", true; "king outside the square")]
+    #[test_case("8/8/8/8/8/8/4p3/K3k3 b - - 0 1", true; "black pawn")]
+    #[test_case("k7/8/8/P7/8/8/8/K7 w - - 0 1", false; "rook pawn with king in the corner")]
+    #[test_case("7k/8/8/7P/8/8/8/7K w - - 0 1", false; "mirrored rook pawn")]
+    #[test_case("8/8/8/8/8/3k4/4P3/K7 b - - 0 1", false; "pawn is captured")]
+    #[test_case("8/8/4k3/8/4K3/4P3/8/8 b - - 0 1", true; "opposition")]
+    #[test_case("4k3/4P3/4K3/8/8/8/8/8 b - - 0 1", false; "stalemate")]
+    #[test_case("4k3/8/4K3/4P3/8/8/8/8 w - - 0 1", true; "king on the sixth rank")]
+    fn kpk_bitbase(fen: &str, win: bool) {
+        let board = board(fen);
+        let (name, score) = specialized_eval(&board, &EvalParams::default()).unwrap();
+        assert_eq!(name, "KPK");
+        assert_eq!(score != 0, win);
+    }
+
+    #[test]
+    fn mop_up_drives_king_to_the_edge() {
+        let params = EvalParams::default();
+        let center = specialized_eval(&board("8/8/8/3k4/8/8/8/R3K3 w - - 0 1"), &params).unwrap();
+        let edge = specialized_eval(&board("3k4/8/8/8/8/8/8/R3K3 w - - 0 1"), &params).unwrap();
+        assert_eq!(center.0, "KXK");
+        assert!(edge.1 > center.1 && center.1 > KNOWN_WIN);
+        let black = specialized_eval(&board("r3k3/8/8/8/8/8/8/3K4 w - - 0 1"), &params).unwrap();
+        assert_eq!(black.1, -edge.1);
+    }
+
+    #[test]
+    fn kbnk_drives_king_to_the_bishop_corner() {
+        let params = EvalParams::default();
+        // dark squared bishop mates in a1 or h8
+        let right_corner = specialized_eval(&board("7k/8/5K2/8/8/8/8/2B1N3 w - - 0 1"), &params).unwrap();
+        let wrong_corner = specialized_eval(&board("k7/8/2K5/8/8/8/8/2B1N3 w - - 0 1"), &params).unwrap();
+        assert_eq!(right_corner.0, "KBNK");
+        assert!(right_corner.1 > wrong_corner.1);
+    }
+
+    #[test]
+    fn drawish_material_is_scaled() {
+        let params = EvalParams::default();
+        let opposite = board("4k3/4b3/8/3PP3/8/8/4B3/4K3 w - - 0 1");
+        let same = board("4k3/5b2/8/3PP3/8/8/4B3/4K3 w - - 0 1");
+        assert_eq!(scale_factor(&opposite, Color::White, &params), SCALE_OPPOSITE_BISHOPS);
+        assert_eq!(scale_factor(&same, Color::White, &params), SCALE_NORMAL);
+        assert!(eval_with_params(&opposite, &params) < eval_with_params(&same, &params));
+
+        let wrong_rook_pawn = board("k7/8/8/P7/8/8/8/2B1K3 w - - 0 1");
+        let right_rook_pawn = board("k7/8/8/P7/8/8/8/3BK3 w - - 0 1");
+        assert_eq!(specialized_eval(&wrong_rook_pawn, &params), Some(("KBPsK", 0)));
+        assert_eq!(specialized_eval(&right_rook_pawn, &params), None);
+        assert!(eval_with_params(&right_rook_pawn, &params) > 0);
+
+        let rook_vs_bishop = board("4k3/8/8/3b4/8/8/8/R3K3 w - - 0 1");
+        assert_eq!(scale_factor(&rook_vs_bishop, Color::White, &params), SCALE_NO_PAWNS_ROOK_VS_MINOR);
+        // the material is compared with the values of the parameters
+        let mut cheap_bishop = EvalParams::default();
+        cheap_bishop.bishop_value.mg = 100;
+        assert_eq!(scale_factor(&rook_vs_bishop, Color::White, &cheap_bishop), SCALE_NORMAL);
+    }
+}
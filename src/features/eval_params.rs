@@ -0,0 +1,360 @@
+use crate::features::evaluation::*;
+use crate::features::king_safety::*;
+use crate::features::mobility::*;
+use crate::features::pawn_structure::*;
+use json::JsonValue;
+use std::cell::RefCell;
+use std::fs;
+
+/// A single tunable value, or a table of them, which can be written to and read from json.
+/// `Score` is stored as `[mg, eg]`.
+pub trait Param {
+    fn to_json(&self) -> JsonValue;
+    fn read_json(&mut self, value: &JsonValue) -> bool;
+    fn weights(&mut self) -> Vec<&mut i32>;
+}
+
+impl Param for i32 {
+    fn to_json(&self) -> JsonValue {
+        JsonValue::from(*self)
+    }
+
+    fn read_json(&mut self, value: &JsonValue) -> bool {
+        match value.as_i32() {
+            Some(value) => {
+                *self = value;
+                true
+            }
+            None => false,
+        }
+    }
+
+    fn weights(&mut self) -> Vec<&mut i32> {
+        vec![self]
+    }
+}
+
+impl Param for Score {
+    fn to_json(&self) -> JsonValue {
+        JsonValue::Array(vec![self.mg.to_json(), self.eg.to_json()])
+    }
+
+    fn read_json(&mut self, value: &JsonValue) -> bool {
+        value.is_array() && value.len() == 2 && self.mg.read_json(&value[0]) && self.eg.read_json(&value[1])
+    }
+
+    fn weights(&mut self) -> Vec<&mut i32> {
+        vec![&mut self.mg, &mut self.eg]
+    }
+}
+
+impl<T: Param, const N: usize> Param for [T; N] {
+    fn to_json(&self) -> JsonValue {
+        JsonValue::Array(self.iter().map(|param| param.to_json()).collect())
+    }
+
+    fn read_json(&mut self, value: &JsonValue) -> bool {
+        value.is_array() && value.len() == N && self.iter_mut().enumerate().all(|(i, param)| param.read_json(&value[i]))
+    }
+
+    fn weights(&mut self) -> Vec<&mut i32> {
+        self.iter_mut().flat_map(|param| param.weights()).collect()
+    }
+}
+
+/// All evaluation weights. The defaults are the compiled-in constants of the evaluation modules.
+#[derive(Clone, Debug, PartialEq)]
+pub struct EvalParams {
+    pub pawn_value: Score,
+    pub knight_value: Score,
+    pub bishop_value: Score,
+    pub rook_value: Score,
+    pub queen_value: Score,
+
+    pub pawn_square_table_mg: [i32; 64],
+    pub pawn_square_table_eg: [i32; 64],
+    pub knight_square_table_mg: [i32; 64],
+    pub knight_square_table_eg: [i32; 64],
+    pub bishop_square_table_mg: [i32; 64],
+    pub bishop_square_table_eg: [i32; 64],
+    pub rook_square_table_mg: [i32; 64],
+    pub rook_square_table_eg: [i32; 64],
+    pub queen_square_table_mg: [i32; 64],
+    pub queen_square_table_eg: [i32; 64],
+    pub king_square_table_mg: [i32; 64],
+    pub king_square_table_eg: [i32; 64],
+
+    pub doubled_pawn: Score,
+    pub isolated_pawn: Score,
+    pub backward_pawn: Score,
+    pub connected_pawn: [Score; 8],
+    pub passed_pawn: [Score; 8],
+    pub passed_pawn_free_path: [i32; 8],
+    pub passed_pawn_enemy_king_distance: i32,
+    pub passed_pawn_own_king_distance: i32,
+
+    pub shield_pawn_close: i32,
+    pub shield_pawn_far: i32,
+    pub shield_pawn_missing: i32,
+    pub pawn_storm: [i32; 5],
+    pub open_file: i32,
+    pub half_open_file: i32,
+    pub minor_attack_weight: i32,
+    pub rook_attack_weight: i32,
+    pub queen_attack_weight: i32,
+    pub safety_table: [i32; 100],
+
+    pub knight_mobility: Score,
+    pub bishop_mobility: Score,
+    pub rook_mobility: Score,
+    pub queen_mobility: Score,
+    pub rook_on_open_file: Score,
+    pub rook_on_half_open_file: Score,
+    pub rook_on_seventh: Score,
+    pub bishop_pair: Score,
+    pub knight_outpost: Score,
+    pub trapped_bishop: Score,
+    pub trapped_rook: Score,
+}
+
+impl Default for EvalParams {
+    fn default() -> Self {
+        EvalParams {
+            pawn_value: PAWN_VALUE,
+            knight_value: KNIGHT_VALUE,
+            bishop_value: BISHOP_VALUE,
+            rook_value: ROOK_VALUE,
+            queen_value: QUEEN_VALUE,
+
+            pawn_square_table_mg: PAWN_SQUARE_TABLE_MG,
+            pawn_square_table_eg: PAWN_SQUARE_TABLE_EG,
+            knight_square_table_mg: KNIGHT_SQUARE_TABLE_MG,
+            knight_square_table_eg: KNIGHT_SQUARE_TABLE_EG,
+            bishop_square_table_mg: BISHOP_SQUARE_TABLE_MG,
+            bishop_square_table_eg: BISHOP_SQUARE_TABLE_EG,
+            rook_square_table_mg: ROOK_SQUARE_TABLE_MG,
+            rook_square_table_eg: ROOK_SQUARE_TABLE_EG,
+            queen_square_table_mg: QUEEN_SQUARE_TABLE_MG,
+            queen_square_table_eg: QUEEN_SQUARE_TABLE_EG,
+            king_square_table_mg: KING_SQUARE_TABLE_MG,
+            king_square_table_eg: KING_SQUARE_TABLE_EG,
+
+            doubled_pawn: DOUBLED_PAWN,
+            isolated_pawn: ISOLATED_PAWN,
+            backward_pawn: BACKWARD_PAWN,
+            connected_pawn: CONNECTED_PAWN,
+            passed_pawn: PASSED_PAWN,
+            passed_pawn_free_path: PASSED_PAWN_FREE_PATH,
+            passed_pawn_enemy_king_distance: PASSED_PAWN_ENEMY_KING_DISTANCE,
+            passed_pawn_own_king_distance: PASSED_PAWN_OWN_KING_DISTANCE,
+
+            shield_pawn_close: SHIELD_PAWN_CLOSE,
+            shield_pawn_far: SHIELD_PAWN_FAR,
+            shield_pawn_missing: SHIELD_PAWN_MISSING,
+            pawn_storm: PAWN_STORM,
+            open_file: OPEN_FILE,
+            half_open_file: HALF_OPEN_FILE,
+            minor_attack_weight: MINOR_ATTACK_WEIGHT,
+            rook_attack_weight: ROOK_ATTACK_WEIGHT,
+            queen_attack_weight: QUEEN_ATTACK_WEIGHT,
+            safety_table: SAFETY_TABLE,
+
+            knight_mobility: KNIGHT_MOBILITY,
+            bishop_mobility: BISHOP_MOBILITY,
+            rook_mobility: ROOK_MOBILITY,
+            queen_mobility: QUEEN_MOBILITY,
+            rook_on_open_file: ROOK_ON_OPEN_FILE,
+            rook_on_half_open_file: ROOK_ON_HALF_OPEN_FILE,
+            rook_on_seventh: ROOK_ON_SEVENTH,
+            bishop_pair: BISHOP_PAIR,
+            knight_outpost: KNIGHT_OUTPOST,
+            trapped_bishop: TRAPPED_BISHOP,
+            trapped_rook: TRAPPED_ROOK,
+        }
+    }
+}
+
+impl EvalParams {
+    /// Calls `f` with the json name and value of every parameter.
+    pub fn visit(&mut self, f: &mut dyn FnMut(&'static str, &mut dyn Param)) {
+        f("pawn_value", &mut self.pawn_value);
+        f("knight_value", &mut self.knight_value);
+        f("bishop_value", &mut self.bishop_value);
+        f("rook_value", &mut self.rook_value);
+        f("queen_value", &mut self.queen_value);
+
+        f("pawn_square_table_mg", &mut self.pawn_square_table_mg);
+        f("pawn_square_table_eg", &mut self.pawn_square_table_eg);
+        f("knight_square_table_mg", &mut self.knight_square_table_mg);
+        f("knight_square_table_eg", &mut self.knight_square_table_eg);
+        f("bishop_square_table_mg", &mut self.bishop_square_table_mg);
+        f("bishop_square_table_eg", &mut self.bishop_square_table_eg);
+        f("rook_square_table_mg", &mut self.rook_square_table_mg);
+        f("rook_square_table_eg", &mut self.rook_square_table_eg);
+        f("queen_square_table_mg", &mut self.queen_square_table_mg);
+        f("queen_square_table_eg", &mut self.queen_square_table_eg);
+        f("king_square_table_mg", &mut self.king_square_table_mg);
+        f("king_square_table_eg", &mut self.king_square_table_eg);
+
+        f("doubled_pawn", &mut self.doubled_pawn);
+        f("isolated_pawn", &mut self.isolated_pawn);
+        f("backward_pawn", &mut self.backward_pawn);
+        f("connected_pawn", &mut self.connected_pawn);
+        f("passed_pawn", &mut self.passed_pawn);
+        f("passed_pawn_free_path", &mut self.passed_pawn_free_path);
+        f("passed_pawn_enemy_king_distance", &mut self.passed_pawn_enemy_king_distance);
+        f("passed_pawn_own_king_distance", &mut self.passed_pawn_own_king_distance);
+
+        f("shield_pawn_close", &mut self.shield_pawn_close);
+        f("shield_pawn_far", &mut self.shield_pawn_far);
+        f("shield_pawn_missing", &mut self.shield_pawn_missing);
+        f("pawn_storm", &mut self.pawn_storm);
+        f("open_file", &mut self.open_file);
+        f("half_open_file", &mut self.half_open_file);
+        f("minor_attack_weight", &mut self.minor_attack_weight);
+        f("rook_attack_weight", &mut self.rook_attack_weight);
+        f("queen_attack_weight", &mut self.queen_attack_weight);
+        f("safety_table", &mut self.safety_table);
+
+        f("knight_mobility", &mut self.knight_mobility);
+        f("bishop_mobility", &mut self.bishop_mobility);
+        f("rook_mobility", &mut self.rook_mobility);
+        f("queen_mobility", &mut self.queen_mobility);
+        f("rook_on_open_file", &mut self.rook_on_open_file);
+        f("rook_on_half_open_file", &mut self.rook_on_half_open_file);
+        f("rook_on_seventh", &mut self.rook_on_seventh);
+        f("bishop_pair", &mut self.bishop_pair);
+        f("knight_outpost", &mut self.knight_outpost);
+        f("trapped_bishop", &mut self.trapped_bishop);
+        f("trapped_rook", &mut self.trapped_rook);
+    }
+
+    pub fn to_json(&self) -> JsonValue {
+        let mut json = JsonValue::new_object();
+        self.clone().visit(&mut |name, param| json[name] = param.to_json());
+        json
+    }
+
+    /// Parameters missing in the json keep their default values.
+    pub fn from_json(json: &JsonValue) -> Result<Self, String> {
+        if !json.is_object() {
+            return Err("Parameters must be a json object".to_string());
+        }
+        let mut params = EvalParams::default();
+        let mut known = Vec::new();
+        let mut error = None;
+        params.visit(&mut |name, param| {
+            known.push(name);
+            if json.has_key(name) && !param.read_json(&json[name]) && error.is_none() {
+                error = Some("Invalid value of parameter |".to_string() + name + "|");
+            }
+        });
+        if let Some(error) = error {
+            return Err(error);
+        }
+        if let Some((name, _)) = json.entries().find(|(name, _)| !known.contains(name)) {
+            return Err("Unknown parameter |".to_string() + name + "|");
+        }
+        Ok(params)
+    }
+
+    /// All parameters flattened to single values, in the order of `visit`.
+    pub fn weights(&self) -> Vec<i32> {
+        let mut weights = Vec::new();
+        self.clone().visit(&mut |_, param| weights.extend(param.weights().into_iter().map(|weight| *weight)));
+        weights
+    }
+
+    pub fn set_weights(&mut self, weights: &[i32]) {
+        let mut values = weights.iter();
+        self.visit(&mut |_, param| {
+            for weight in param.weights() {
+                *weight = *values.next().expect("Too few weights");
+            }
+        });
+    }
+
+    pub fn load(path: &str) -> Result<Self, String> {
+        let content = fs::read_to_string(path).map_err(|err| "Cannot read |".to_string() + path + "|: " + &*err.to_string())?;
+        let json = json::parse(&content).map_err(|err| "Cannot parse |".to_string() + path + "|: " + &*err.to_string())?;
+        EvalParams::from_json(&json)
+    }
+
+    pub fn save(&self, path: &str) -> Result<(), String> {
+        fs::write(path, self.to_json().pretty(2)).map_err(|err| "Cannot write |".to_string() + path + "|: " + &*err.to_string())
+    }
+}
+
+thread_local! {
+    static EVAL_PARAMS: RefCell<EvalParams> = RefCell::new(EvalParams::default());
+}
+
+/// Replaces the parameters used by `eval` on the current thread.
+pub fn set_eval_params(params: EvalParams) {
+    EVAL_PARAMS.with(|current| *current.borrow_mut() = params);
+    // cached pawn scores were computed with the old parameters
+    clear_pawn_table();
+}
+
+pub fn eval_params() -> EvalParams {
+    EVAL_PARAMS.with(|params| params.borrow().clone())
+}
+
+pub fn with_eval_params<R>(f: impl FnOnce(&EvalParams) -> R) -> R {
+    EVAL_PARAMS.with(|params| f(&params.borrow()))
+}
+
+#[cfg(test)]
+mod eval_params_tests {
+    use super::*;
+    use std::str::FromStr;
+
+    #[test]
+    fn json_round_trip() {
+        let mut params = EvalParams {
+            knight_value: Score::new(310, 290),
+            ..EvalParams::default()
+        };
+        params.passed_pawn[5] = Score::new(41, 72);
+        params.king_square_table_eg[27] = 44;
+        let restored = EvalParams::from_json(&json::parse(&params.to_json().dump()).unwrap()).unwrap();
+        assert_eq!(restored, params);
+    }
+
+    #[test]
+    fn weights_round_trip() {
+        let defaults = EvalParams::default();
+        let mut weights = defaults.weights();
+        weights[0] += 1;
+        let mut params = EvalParams::default();
+        params.set_weights(&weights);
+        assert_eq!(params.pawn_value, PAWN_VALUE + Score::new(1, 0));
+        assert_eq!(params.weights(), weights);
+    }
+
+    #[test]
+    fn missing_parameters_keep_defaults() {
+        let params = EvalParams::from_json(&json::parse(r#"{"bishop_pair": [10, 20]}"#).unwrap()).unwrap();
+        assert_eq!(params.bishop_pair, Score::new(10, 20));
+        assert_eq!(params.rook_value, ROOK_VALUE);
+    }
+
+    #[test]
+    fn invalid_parameters_are_rejected() {
+        assert!(EvalParams::from_json(&json::parse(r#"{"pawn_value": 100}"#).unwrap()).is_err());
+        assert!(EvalParams::from_json(&json::parse(r#"{"pawn_storm": [1, 2]}"#).unwrap()).is_err());
+        assert!(EvalParams::from_json(&json::parse(r#"{"pawn_vaule": [100, 100]}"#).unwrap()).is_err());
+    }
+
+    #[test]
+    fn active_parameters_change_eval() {
+        let board = chess::Board::from_str("4k3/8/8/8/8/8/8/4KR2 w - - 0 1").unwrap();
+        set_eval_params(EvalParams {
+            rook_value: Score::new(1000, 1000),
+            ..EvalParams::default()
+        });
+        let changed = eval(&board, chess::BoardStatus::Ongoing, 0);
+        set_eval_params(EvalParams::default());
+        assert_ne!(changed, eval(&board, chess::BoardStatus::Ongoing, 0));
+    }
+}
@@ -1,7 +1,77 @@
+use crate::features::endgame::{scale_factor, specialized_eval, SCALE_NORMAL};
+use crate::features::eval_params::{with_eval_params, EvalParams};
+use crate::features::king_safety::{king_safety, side_king_safety};
+use crate::features::mobility::{mobility, side_mobility};
+use crate::features::pawn_structure::{pawn_structure, side_pawn_structure};
 use chess::{BitBoard, Board, BoardStatus, Color, File, Piece, Rank, Square};
+use std::ops::{Add, AddAssign, Neg, Sub, SubAssign};
+
+/// Pair of middlegame and endgame values, interpolated by the game phase.
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct Score {
+    pub mg: i32,
+    pub eg: i32,
+}
+
+impl Score {
+    pub const fn new(mg: i32, eg: i32) -> Self {
+        Score { mg, eg }
+    }
+
+    /// Tapered value with the endgame part multiplied by `scale / SCALE_NORMAL`.
+    pub fn taper_scaled(self, phase: i32, scale: i32) -> i32 {
+        (self.mg * phase * SCALE_NORMAL + self.eg * scale * (MAX_PHASE - phase)) / (MAX_PHASE * SCALE_NORMAL)
+    }
+}
+
+impl Add for Score {
+    type Output = Score;
+    fn add(self, other: Score) -> Score {
+        Score::new(self.mg + other.mg, self.eg + other.eg)
+    }
+}
+
+impl Sub for Score {
+    type Output = Score;
+    fn sub(self, other: Score) -> Score {
+        Score::new(self.mg - other.mg, self.eg - other.eg)
+    }
+}
+
+impl Neg for Score {
+    type Output = Score;
+    fn neg(self) -> Score {
+        Score::new(-self.mg, -self.eg)
+    }
+}
+
+impl AddAssign for Score {
+    fn add_assign(&mut self, other: Score) {
+        *self = *self + other;
+    }
+}
+
+impl SubAssign for Score {
+    fn sub_assign(&mut self, other: Score) {
+        *self = *self - other;
+    }
+}
+
+// phase is computed from non-pawn material, 24 is the starting position
+pub const MAX_PHASE: i32 = 24;
+const KNIGHT_PHASE: i32 = 1;
+const BISHOP_PHASE: i32 = 1;
+const ROOK_PHASE: i32 = 2;
+const QUEEN_PHASE: i32 = 4;
+
+pub const PAWN_VALUE: Score = Score::new(100, 120);
+pub const KNIGHT_VALUE: Score = Score::new(320, 300);
+pub const BISHOP_VALUE: Score = Score::new(330, 320);
+pub const ROOK_VALUE: Score = Score::new(500, 530);
+pub const QUEEN_VALUE: Score = Score::new(900, 950);
 
 #[rustfmt::skip]
-pub const KING_SQUARE_TABLE: [i32; 64] = [
+pub const KING_SQUARE_TABLE_MG: [i32; 64] = [
     -30,-40,-40,-50,-50,-40,-40,-30, 
     -30,-40,-40,-50,-50,-40,-40,-30, 
     -30,-40,-40,-50,-50,-40,-40,-30, 
@@ -13,7 +83,19 @@ pub const KING_SQUARE_TABLE: [i32; 64] = [
 ];
 
 #[rustfmt::skip]
-pub const QUEEN_SQUARE_TABLE: [i32; 64] = [
+pub const KING_SQUARE_TABLE_EG: [i32; 64] = [
+    -50,-40,-30,-20,-20,-30,-40,-50,
+    -30,-20,-10,  0,  0,-10,-20,-30,
+    -30,-10, 20, 30, 30, 20,-10,-30,
+    -30,-10, 30, 40, 40, 30,-10,-30,
+    -30,-10, 30, 40, 40, 30,-10,-30,
+    -30,-10, 20, 30, 30, 20,-10,-30,
+    -30,-30,  0,  0,  0,  0,-30,-30,
+    -50,-30,-30,-30,-30,-30,-30,-50,
+];
+
+#[rustfmt::skip]
+pub const QUEEN_SQUARE_TABLE_MG: [i32; 64] = [
     -20,-10,-10, -5, -5,-10,-10,-20,
     -10,  0,  0,  0,  0,  0,  0,-10,
     -10,  0,  5,  5,  5,  5,  0,-10,
@@ -25,7 +107,19 @@ pub const QUEEN_SQUARE_TABLE: [i32; 64] = [
 ];
 
 #[rustfmt::skip]
-pub const ROOK_SQUARE_TABLE: [i32; 64] = [
+pub const QUEEN_SQUARE_TABLE_EG: [i32; 64] = [
+    -20,-10,-10, -5, -5,-10,-10,-20,
+    -10,  0,  5,  5,  5,  5,  0,-10,
+    -10,  5, 10, 10, 10, 10,  5,-10,
+     -5,  5, 10, 15, 15, 10,  5, -5,
+     -5,  5, 10, 15, 15, 10,  5, -5,
+    -10,  5, 10, 10, 10, 10,  5,-10,
+    -10,  0,  5,  5,  5,  5,  0,-10,
+    -20,-10,-10, -5, -5,-10,-10,-20,
+];
+
+#[rustfmt::skip]
+pub const ROOK_SQUARE_TABLE_MG: [i32; 64] = [
     0,  0,  0,  0,  0,  0,  0,  0,
     5, 10, 10, 10, 10, 10, 10,  5,
    -5,  0,  0,  0,  0,  0,  0, -5,
@@ -37,7 +131,19 @@ pub const ROOK_SQUARE_TABLE: [i32; 64] = [
 ];
 
 #[rustfmt::skip]
-pub const BISHOP_SQUARE_TABLE: [i32; 64] = [
+pub const ROOK_SQUARE_TABLE_EG: [i32; 64] = [
+    5,  5,  5,  5,  5,  5,  5,  5,
+   10, 10, 10, 10, 10, 10, 10, 10,
+    0,  0,  0,  0,  0,  0,  0,  0,
+    0,  0,  0,  0,  0,  0,  0,  0,
+    0,  0,  0,  0,  0,  0,  0,  0,
+    0,  0,  0,  0,  0,  0,  0,  0,
+    0,  0,  0,  0,  0,  0,  0,  0,
+    0,  0,  0,  0,  0,  0,  0,  0,
+];
+
+#[rustfmt::skip]
+pub const BISHOP_SQUARE_TABLE_MG: [i32; 64] = [
     -20,-10,-10,-10,-10,-10,-10,-20,
     -10,  0,  0,  0,  0,  0,  0,-10,
     -10,  0,  5, 10, 10,  5,  0,-10,
@@ -49,7 +155,19 @@ pub const BISHOP_SQUARE_TABLE: [i32; 64] = [
 ];
 
 #[rustfmt::skip]
-pub const KNIGHT_SQUARE_TABLE: [i32; 64] = [
+pub const BISHOP_SQUARE_TABLE_EG: [i32; 64] = [
+    -15,-10,-10,-10,-10,-10,-10,-15,
+    -10,  0,  0,  0,  0,  0,  0,-10,
+    -10,  0,  5,  5,  5,  5,  0,-10,
+    -10,  0,  5, 10, 10,  5,  0,-10,
+    -10,  0,  5, 10, 10,  5,  0,-10,
+    -10,  0,  5,  5,  5,  5,  0,-10,
+    -10,  0,  0,  0,  0,  0,  0,-10,
+    -15,-10,-10,-10,-10,-10,-10,-15,
+];
+
+#[rustfmt::skip]
+pub const KNIGHT_SQUARE_TABLE_MG: [i32; 64] = [
     -50, -40, -30, -30, -30, -30, -40, -50,
     -40, -20,   0,   0,   0,   0, -20, -40,
     -30,   0,  10,  15,  15,  10,   0, -30,
@@ -61,7 +179,19 @@ pub const KNIGHT_SQUARE_TABLE: [i32; 64] = [
 ];
 
 #[rustfmt::skip]
-pub const PAWN_SQUARE_TABLE: [i32; 64] = [
+pub const KNIGHT_SQUARE_TABLE_EG: [i32; 64] = [
+    -50, -40, -30, -30, -30, -30, -40, -50,
+    -40, -20, -10,  -5,  -5, -10, -20, -40,
+    -30, -10,   5,  10,  10,   5, -10, -30,
+    -30,  -5,  10,  15,  15,  10,  -5, -30,
+    -30,  -5,  10,  15,  15,  10,  -5, -30,
+    -30, -10,   5,  10,  10,   5, -10, -30,
+    -40, -20, -10,  -5,  -5, -10, -20, -40,
+    -50, -40, -30, -30, -30, -30, -40, -50,
+];
+
+#[rustfmt::skip]
+pub const PAWN_SQUARE_TABLE_MG: [i32; 64] = [
      0,  0,  0,  0,  0,  0,  0,  0, 
     50, 50, 50, 50, 50, 50, 50, 50,
     10, 10, 20, 30, 30, 20, 10, 10,
@@ -72,6 +202,18 @@ pub const PAWN_SQUARE_TABLE: [i32; 64] = [
      0,  0,  0,  0,  0,  0,  0,  0,
 ];
 
+#[rustfmt::skip]
+pub const PAWN_SQUARE_TABLE_EG: [i32; 64] = [
+     0,  0,  0,  0,  0,  0,  0,  0,
+    80, 80, 80, 80, 80, 80, 80, 80,
+    50, 50, 50, 50, 50, 50, 50, 50,
+    30, 30, 30, 30, 30, 30, 30, 30,
+    15, 15, 15, 15, 15, 15, 15, 15,
+     5,  5,  5,  5,  5,  5,  5,  5,
+     0,  0,  0,  0,  0,  0,  0,  0,
+     0,  0,  0,  0,  0,  0,  0,  0,
+];
+
 fn file_distance(a: File, b: File) -> i32 {
     i32::abs(a.to_index() as i32 - b.to_index() as i32)
 }
@@ -92,66 +234,165 @@ pub fn get_sq_val(sq: Square, square_table: [i32; 64], color: Color) -> i32 {
     square_table[index as usize]
 }
 
-fn get_pieces_value(board: &Board, board_side: &BitBoard) -> u32 {
-    100 * (board.pieces(Piece::Pawn) & board_side).popcnt()
-        + 320 * (board.pieces(Piece::Knight) & board_side).popcnt()
-        + 330 * (board.pieces(Piece::Bishop) & board_side).popcnt()
-        + 500 * (board.pieces(Piece::Rook) & board_side).popcnt()
-        + 900 * (board.pieces(Piece::Queen) & board_side).popcnt()
+pub fn game_phase(board: &Board) -> i32 {
+    let phase = KNIGHT_PHASE * board.pieces(Piece::Knight).popcnt() as i32
+        + BISHOP_PHASE * board.pieces(Piece::Bishop).popcnt() as i32
+        + ROOK_PHASE * board.pieces(Piece::Rook).popcnt() as i32
+        + QUEEN_PHASE * board.pieces(Piece::Queen).popcnt() as i32;
+    phase.min(MAX_PHASE)
 }
 
-pub fn get_position_cumulative_value(board: &Board, color: Color) -> i32 {
-    let color_board = board.color_combined(color);
-    let king_pos_val = (color_board & board.pieces(Piece::King))
-        .into_iter()
-        .map(|sq| get_sq_val(sq, KING_SQUARE_TABLE, color))
-        .sum::<i32>() as i32;
-    let queen_pos_val = (color_board & board.pieces(Piece::Queen))
-        .into_iter()
-        .map(|sq| get_sq_val(sq, QUEEN_SQUARE_TABLE, color))
-        .sum::<i32>() as i32;
-    let rooks_pos_val = (color_board & board.pieces(Piece::Rook))
-        .into_iter()
-        .map(|sq| get_sq_val(sq, ROOK_SQUARE_TABLE, color))
-        .sum::<i32>() as i32;
-    let bishops_pos_val = (color_board & board.pieces(Piece::Bishop))
-        .into_iter()
-        .map(|sq| get_sq_val(sq, BISHOP_SQUARE_TABLE, color))
-        .sum::<i32>() as i32;
-    let knights_pos_val = (color_board & board.pieces(Piece::Knight))
-        .into_iter()
-        .map(|sq| get_sq_val(sq, KNIGHT_SQUARE_TABLE, color))
-        .sum::<i32>() as i32;
-    let pawns_pos_val = (color_board & board.pieces(Piece::Pawn))
+fn get_pieces_value(board: &Board, board_side: &BitBoard, params: &EvalParams) -> Score {
+    let count = |piece: Piece| (board.pieces(piece) & board_side).popcnt() as i32;
+    let value = |piece_value: Score, piece: Piece| {
+        Score::new(piece_value.mg * count(piece), piece_value.eg * count(piece))
+    };
+    value(params.pawn_value, Piece::Pawn)
+        + value(params.knight_value, Piece::Knight)
+        + value(params.bishop_value, Piece::Bishop)
+        + value(params.rook_value, Piece::Rook)
+        + value(params.queen_value, Piece::Queen)
+}
+
+fn get_pieces_sq_val(
+    board: &Board,
+    color: Color,
+    piece: Piece,
+    square_table_mg: [i32; 64],
+    square_table_eg: [i32; 64],
+) -> Score {
+    (board.color_combined(color) & board.pieces(piece))
         .into_iter()
-        .map(|sq| get_sq_val(sq, PAWN_SQUARE_TABLE, color))
-        .sum::<i32>() as i32;
+        .map(|sq| {
+            Score::new(
+                get_sq_val(sq, square_table_mg, color),
+                get_sq_val(sq, square_table_eg, color),
+            )
+        })
+        .fold(Score::default(), |acc, value| acc + value)
+}
 
-    king_pos_val + queen_pos_val + rooks_pos_val + bishops_pos_val + knights_pos_val + pawns_pos_val
+pub fn get_position_cumulative_value(board: &Board, color: Color, params: &EvalParams) -> Score {
+    get_pieces_sq_val(board, color, Piece::King, params.king_square_table_mg, params.king_square_table_eg)
+        + get_pieces_sq_val(board, color, Piece::Queen, params.queen_square_table_mg, params.queen_square_table_eg)
+        + get_pieces_sq_val(board, color, Piece::Rook, params.rook_square_table_mg, params.rook_square_table_eg)
+        + get_pieces_sq_val(board, color, Piece::Bishop, params.bishop_square_table_mg, params.bishop_square_table_eg)
+        + get_pieces_sq_val(board, color, Piece::Knight, params.knight_square_table_mg, params.knight_square_table_eg)
+        + get_pieces_sq_val(board, color, Piece::Pawn, params.pawn_square_table_mg, params.pawn_square_table_eg)
+}
+
+pub const MATE_SCORE: i32 = 1e9 as i32;
+pub const MATE_PLY_PENALTY: i32 = 100;
+
+/// Signed number of moves to the mate encoded in a side to move score,
+/// positive when the side to move mates, `None` for non mate scores.
+pub fn moves_to_mate(score: i32) -> Option<i32> {
+    if score.abs() <= MATE_SCORE / 10 {
+        return None;
+    }
+    let plies = (MATE_SCORE - score.abs()) / MATE_PLY_PENALTY;
+    let moves = (plies + 1) / 2;
+    Some(if score > 0 { moves } else { -moves })
 }
 
 pub fn eval(board: &Board, board_status: BoardStatus, depth: usize) -> i32 {
     match board_status {
         BoardStatus::Checkmate => {
             if board.side_to_move() == Color::White {
-                -1e9 as i32 + 100 * depth as i32
+                -MATE_SCORE + MATE_PLY_PENALTY * depth as i32
             } else {
-                1e9 as i32 - 100 * depth as i32
+                MATE_SCORE - MATE_PLY_PENALTY * depth as i32
             }
         }
 
         BoardStatus::Stalemate => 0,
 
-        BoardStatus::Ongoing => {
-            let white_value = get_pieces_value(board, board.color_combined(Color::White));
-            let black_value = get_pieces_value(board, board.color_combined(Color::Black));
-            white_value as i32 - black_value as i32
-                + get_position_cumulative_value(board, Color::White)
-                - get_position_cumulative_value(board, Color::Black)
-        }
+        BoardStatus::Ongoing => with_eval_params(|params| eval_with_params(board, params)),
+    }
+}
+
+/// White relative score of an ongoing game using the given parameters.
+pub fn eval_with_params(board: &Board, params: &EvalParams) -> i32 {
+    if let Some((_, score)) = specialized_eval(board, params) {
+        return score;
+    }
+    let white_value = get_pieces_value(board, board.color_combined(Color::White), params)
+        + get_position_cumulative_value(board, Color::White, params);
+    let black_value = get_pieces_value(board, board.color_combined(Color::Black), params)
+        + get_position_cumulative_value(board, Color::Black, params);
+    let total =
+        white_value - black_value + pawn_structure(board, params) + king_safety(board, params) + mobility(board, params);
+    total.taper_scaled(game_phase(board), scale_factor(board, strong_side(total), params))
+}
+
+fn strong_side(total: Score) -> Color {
+    if total.eg >= 0 {
+        Color::White
+    } else {
+        Color::Black
     }
 }
 
+/// One evaluation term of both sides, before tapering.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct EvalTerm {
+    pub name: &'static str,
+    pub white: Score,
+    pub black: Score,
+}
+
+impl EvalTerm {
+    pub fn total(&self) -> Score {
+        self.white - self.black
+    }
+}
+
+/// Evaluation split into terms. `score` is white relative and equal to `eval` of an ongoing game.
+#[derive(Clone, Debug, PartialEq)]
+pub struct EvalBreakdown {
+    pub terms: Vec<EvalTerm>,
+    pub phase: i32,
+    /// Name of the specialized endgame evaluator which replaced the terms, if any.
+    pub endgame: Option<&'static str>,
+    pub scale: i32,
+    pub score: i32,
+    pub side_to_move_score: i32,
+}
+
+pub fn eval_breakdown(board: &Board, params: &EvalParams) -> EvalBreakdown {
+    let term = |name: &'static str, side: &dyn Fn(Color) -> Score| EvalTerm {
+        name,
+        white: side(Color::White),
+        black: side(Color::Black),
+    };
+    let pst = |name: &'static str, piece: Piece, table_mg: [i32; 64], table_eg: [i32; 64]| {
+        term(name, &|color| get_pieces_sq_val(board, color, piece, table_mg, table_eg))
+    };
+
+    let terms = vec![
+        term("Material", &|color| get_pieces_value(board, board.color_combined(color), params)),
+        pst("Pawn PST", Piece::Pawn, params.pawn_square_table_mg, params.pawn_square_table_eg),
+        pst("Knight PST", Piece::Knight, params.knight_square_table_mg, params.knight_square_table_eg),
+        pst("Bishop PST", Piece::Bishop, params.bishop_square_table_mg, params.bishop_square_table_eg),
+        pst("Rook PST", Piece::Rook, params.rook_square_table_mg, params.rook_square_table_eg),
+        pst("Queen PST", Piece::Queen, params.queen_square_table_mg, params.queen_square_table_eg),
+        pst("King PST", Piece::King, params.king_square_table_mg, params.king_square_table_eg),
+        term("Pawns", &|color| side_pawn_structure(board, color, params)),
+        term("King safety", &|color| Score::new(side_king_safety(board, color, params), 0)),
+        term("Mobility", &|color| side_mobility(board, color, params)),
+    ];
+
+    let phase = game_phase(board);
+    let total = terms.iter().fold(Score::default(), |acc, term| acc + term.total());
+    let scale = scale_factor(board, strong_side(total), params);
+    let (endgame, score) = match specialized_eval(board, params) {
+        Some((name, score)) => (Some(name), score),
+        None => (None, total.taper_scaled(phase, scale)),
+    };
+    let side_to_move_score = if board.side_to_move() == Color::White { score } else { -score };
+    EvalBreakdown { terms, phase, endgame, scale, score, side_to_move_score }
+}
+
 #[cfg(test)]
 mod eval_tests {
     use std::str::FromStr;
@@ -165,7 +406,30 @@ mod eval_tests {
             Board::from_str("r1b2b1r/pp3Qp1/2nkn2p/3ppP1p/P1p5/1NP1NB2/1PP1PPR1/1K1R3q w - - 0 1")
                 .unwrap();
         // println!("board: {:?}", board);
-        assert_eq!(eval(&board, BoardStatus::Ongoing, 0), -400)
+        assert_eq!(eval(&board, BoardStatus::Ongoing, 0), -285)
+    }
+
+    #[test]
+    fn breakdown_matches_eval() {
+        let params = EvalParams::default();
+        for fen in [
+            "r1b2b1r/pp3Qp1/2nkn2p/3ppP1p/P1p5/1NP1NB2/1PP1PPR1/1K1R3q w - - 0 1",
+            "r4r1k/1R1R2p1/7p/8/8/3Q1Ppq/P7/6K1 b - - 0 1",
+            "8/8/1k3KP1/8/8/8/8/8 w - - 0 1",
+        ] {
+            let board = Board::from_str(fen).unwrap();
+            let breakdown = eval_breakdown(&board, &params);
+            assert_eq!(breakdown.score, eval_with_params(&board, &params));
+            assert_eq!(breakdown.phase, game_phase(&board));
+        }
+    }
+
+    #[test]
+    fn breakdown_of_symmetric_position() {
+        let breakdown = eval_breakdown(&Board::default(), &EvalParams::default());
+        assert!(breakdown.terms.iter().all(|term| term.white == term.black));
+        assert_eq!(breakdown.phase, MAX_PHASE);
+        assert_eq!(breakdown.side_to_move_score, 0);
     }
 
     #[test]
@@ -173,7 +437,32 @@ mod eval_tests {
         // https://www.chess.com/forum/view/livechess/practice-your-checkmate-in-4-moves-in-24-puzzles
         let board = Board::from_str("r4r1k/1R1R2p1/7p/8/8/3Q1Ppq/P7/6K1 w - - 0 1").unwrap();
         // println!("board: {:?}", board);
-        assert_eq!(eval(&board, BoardStatus::Ongoing, 0), -85)
+        assert_eq!(eval(&board, BoardStatus::Ongoing, 0), -126)
+    }
+
+    #[test]
+    fn game_phase_from_non_pawn_material() {
+        assert_eq!(game_phase(&Board::default()), MAX_PHASE);
+        let board = Board::from_str("4k3/pppp4/8/8/8/8/4PPPP/3RK3 w - - 0 1").unwrap();
+        assert_eq!(game_phase(&board), ROOK_PHASE);
+        let board = Board::from_str("4k3/4p3/8/8/8/8/4P3/4K3 w - - 0 1").unwrap();
+        assert_eq!(game_phase(&board), 0);
+    }
+
+    #[test]
+    fn king_centralizes_in_pawn_endgame() {
+        let corner = Board::from_str("7k/4p3/8/8/8/8/4P3/7K w - - 0 1").unwrap();
+        let center = Board::from_str("7k/4p3/8/8/4K3/8/4P3/8 w - - 0 1").unwrap();
+        assert!(eval(&center, BoardStatus::Ongoing, 0) > eval(&corner, BoardStatus::Ongoing, 0));
+    }
+
+    #[test]
+    fn king_shelters_in_middlegame() {
+        let castled = Board::from_str("r1bq1rk1/pppp1ppp/2n2n2/2b1p3/2B1P3/2N2N2/PPPP1PPP/R1BQ1RK1 w - - 0 1")
+            .unwrap();
+        let center = Board::from_str("r1bq1rk1/pppp1ppp/2n2n2/2b1p3/2B1P3/2N1KN2/PPPP1PPP/R1BQ1R2 w - - 0 1")
+            .unwrap();
+        assert!(eval(&castled, BoardStatus::Ongoing, 0) > eval(&center, BoardStatus::Ongoing, 0));
     }
 }
 
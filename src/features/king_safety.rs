@@ -0,0 +1,182 @@
+use crate::features::eval_params::EvalParams;
+use crate::features::evaluation::Score;
+use crate::features::pawn_structure::relative_rank;
+use chess::{
+    get_bishop_moves, get_file, get_king_moves, get_knight_moves, get_rook_moves, BitBoard, Board, Color, File,
+    Piece, Square, EMPTY,
+};
+
+// shield pawn one or two ranks in front of the king, or no pawn on the file at all
+pub const SHIELD_PAWN_CLOSE: i32 = 15;
+pub const SHIELD_PAWN_FAR: i32 = 8;
+pub const SHIELD_PAWN_MISSING: i32 = -10;
+
+// enemy pawn storming the king, indexed by its rank distance from the king
+pub const PAWN_STORM: [i32; 5] = [0, 0, -20, -10, -5];
+
+pub const OPEN_FILE: i32 = -25;
+pub const HALF_OPEN_FILE: i32 = -12;
+
+// attack units per square of the king zone attacked by the piece
+pub const MINOR_ATTACK_WEIGHT: i32 = 2;
+pub const ROOK_ATTACK_WEIGHT: i32 = 3;
+pub const QUEEN_ATTACK_WEIGHT: i32 = 5;
+const MIN_ATTACKERS: usize = 2;
+
+#[rustfmt::skip]
+pub const SAFETY_TABLE: [i32; 100] = [
+      0,   0,   1,   2,   3,   5,   7,   9,  12,  15,
+     18,  22,  26,  30,  35,  39,  44,  50,  56,  62,
+     68,  75,  82,  85,  89,  97, 105, 113, 122, 131,
+    140, 150, 169, 180, 191, 202, 213, 225, 237, 248,
+    260, 272, 283, 295, 307, 319, 330, 342, 354, 366,
+    377, 389, 401, 412, 424, 436, 448, 459, 471, 483,
+    494, 500, 500, 500, 500, 500, 500, 500, 500, 500,
+    500, 500, 500, 500, 500, 500, 500, 500, 500, 500,
+    500, 500, 500, 500, 500, 500, 500, 500, 500, 500,
+    500, 500, 500, 500, 500, 500, 500, 500, 500, 500,
+];
+
+fn king_files(king: Square) -> impl Iterator<Item = File> {
+    let file = king.get_file().to_index();
+    (file.saturating_sub(1)..=(file + 1).min(7)).map(File::from_index)
+}
+
+// rank distance of the square from the king, counted towards the enemy
+fn ranks_ahead(king: Square, sq: Square, color: Color) -> i32 {
+    relative_rank(sq, color) as i32 - relative_rank(king, color) as i32
+}
+
+fn pawn_shelter(board: &Board, color: Color, king: Square, params: &EvalParams) -> i32 {
+    let own_pawns = board.pieces(Piece::Pawn) & board.color_combined(color);
+    let enemy_pawns = board.pieces(Piece::Pawn) & board.color_combined(!color);
+    let mut value = 0;
+
+    for file in king_files(king) {
+        let file_mask = get_file(file);
+        let own_on_file = own_pawns & file_mask;
+        let enemy_on_file = enemy_pawns & file_mask;
+
+        let shield = own_on_file
+            .into_iter()
+            .map(|sq| ranks_ahead(king, sq, color))
+            .filter(|distance| *distance > 0)
+            .min();
+        value += match shield {
+            Some(1) => params.shield_pawn_close,
+            Some(2) => params.shield_pawn_far,
+            Some(_) => 0,
+            None => params.shield_pawn_missing,
+        };
+
+        let storm = enemy_on_file
+            .into_iter()
+            .map(|sq| ranks_ahead(king, sq, color))
+            .filter(|distance| *distance > 0)
+            .min();
+        if let Some(distance) = storm.filter(|distance| (*distance as usize) < params.pawn_storm.len()) {
+            value += params.pawn_storm[distance as usize];
+        }
+
+        if own_on_file == EMPTY {
+            value += if enemy_on_file == EMPTY { params.open_file } else { params.half_open_file };
+        }
+    }
+    value
+}
+
+fn attack_units(board: &Board, color: Color, king: Square, params: &EvalParams) -> usize {
+    let zone = get_king_moves(king) | BitBoard::from_square(king);
+    let occupied = *board.combined();
+    let enemy = board.color_combined(!color);
+    let mut attackers = 0;
+    let mut units = 0;
+
+    for sq in enemy & board.pieces(Piece::Knight) {
+        let attacked = (get_knight_moves(sq) & zone).popcnt() as i32;
+        attackers += (attacked > 0) as usize;
+        units += params.minor_attack_weight * attacked;
+    }
+    for sq in enemy & board.pieces(Piece::Bishop) {
+        let attacked = (get_bishop_moves(sq, occupied) & zone).popcnt() as i32;
+        attackers += (attacked > 0) as usize;
+        units += params.minor_attack_weight * attacked;
+    }
+    for sq in enemy & board.pieces(Piece::Rook) {
+        let attacked = (get_rook_moves(sq, occupied) & zone).popcnt() as i32;
+        attackers += (attacked > 0) as usize;
+        units += params.rook_attack_weight * attacked;
+    }
+    for sq in enemy & board.pieces(Piece::Queen) {
+        let attacks = get_bishop_moves(sq, occupied) | get_rook_moves(sq, occupied);
+        let attacked = (attacks & zone).popcnt() as i32;
+        attackers += (attacked > 0) as usize;
+        units += params.queen_attack_weight * attacked;
+    }
+
+    if attackers < MIN_ATTACKERS {
+        return 0;
+    }
+    units.max(0) as usize
+}
+
+pub fn side_king_safety(board: &Board, color: Color, params: &EvalParams) -> i32 {
+    let king = board.king_square(color);
+    let units = attack_units(board, color, king, params).min(params.safety_table.len() - 1);
+    pawn_shelter(board, color, king, params) - params.safety_table[units]
+}
+
+/// King safety score, white minus black. Only the middlegame part is set.
+pub fn king_safety(board: &Board, params: &EvalParams) -> Score {
+    Score::new(side_king_safety(board, Color::White, params) - side_king_safety(board, Color::Black, params), 0)
+}
+
+#[cfg(test)]
+mod king_safety_tests {
+    use super::*;
+    use std::str::FromStr;
+
+    fn white_safety(fen: &str) -> i32 {
+        side_king_safety(&Board::from_str(fen).unwrap(), Color::White, &EvalParams::default())
+    }
+
+    #[test]
+    fn intact_shield_is_safer() {
+        let intact = white_safety("6k1/8/8/8/8/8/5PPP/6K1 w - - 0 1");
+        let pushed = white_safety("6k1/8/8/8/8/5PPP/8/6K1 w - - 0 1");
+        let missing = white_safety("6k1/8/8/8/8/8/8/6K1 w - - 0 1");
+        assert_eq!(intact, 3 * SHIELD_PAWN_CLOSE);
+        assert_eq!(pushed, 3 * SHIELD_PAWN_FAR);
+        assert!(missing < pushed);
+    }
+
+    #[test]
+    fn open_files_are_dangerous() {
+        let closed = white_safety("6k1/5ppp/8/8/8/8/5PPP/6K1 w - - 0 1");
+        let half_open = white_safety("6k1/5ppp/8/8/8/8/5P1P/6K1 w - - 0 1");
+        let open = white_safety("6k1/5p1p/8/8/8/8/5P1P/6K1 w - - 0 1");
+        assert_eq!(closed - half_open, SHIELD_PAWN_CLOSE - SHIELD_PAWN_MISSING - HALF_OPEN_FILE);
+        assert_eq!(half_open - open, HALF_OPEN_FILE - OPEN_FILE);
+    }
+
+    #[test]
+    fn pawn_storm_is_dangerous() {
+        let calm = white_safety("6k1/6p1/8/8/8/8/5PPP/6K1 w - - 0 1");
+        let storm = white_safety("6k1/8/8/8/8/6p1/5PPP/6K1 w - - 0 1");
+        assert_eq!(calm - storm, -PAWN_STORM[2]);
+    }
+
+    #[test]
+    fn attackers_are_weighted_non_linearly() {
+        let one_attacker = white_safety("6k1/8/8/8/8/8/5PPP/3q2K1 w - - 0 1");
+        let two_attackers = white_safety("6k1/8/8/8/8/7n/5PPP/3q2K1 w - - 0 1");
+        assert_eq!(one_attacker, 3 * SHIELD_PAWN_CLOSE);
+        assert!(two_attackers < one_attacker - SAFETY_TABLE[MIN_ATTACKERS]);
+    }
+
+    #[test]
+    fn only_middlegame_is_affected() {
+        let board = Board::from_str("6k1/8/8/8/8/7n/5PPP/3q2K1 w - - 0 1").unwrap();
+        assert_eq!(king_safety(&board, &EvalParams::default()).eg, 0);
+    }
+}
@@ -0,0 +1,223 @@
+use crate::features::eval_params::EvalParams;
+use crate::features::evaluation::Score;
+use crate::features::pawn_structure::{forward_ranks, relative_rank};
+use chess::{
+    get_adjacent_files, get_bishop_moves, get_file, get_knight_moves, get_pawn_attacks,
+    get_rook_moves, BitBoard, Board, Color, Piece, Rank, Square, EMPTY,
+};
+
+// value of every safe square above (or below) the typical mobility of the piece
+pub const KNIGHT_MOBILITY: Score = Score::new(4, 4);
+pub const BISHOP_MOBILITY: Score = Score::new(5, 5);
+pub const ROOK_MOBILITY: Score = Score::new(2, 4);
+pub const QUEEN_MOBILITY: Score = Score::new(1, 2);
+const KNIGHT_MOBILITY_BASE: i32 = 4;
+const BISHOP_MOBILITY_BASE: i32 = 6;
+const ROOK_MOBILITY_BASE: i32 = 6;
+const QUEEN_MOBILITY_BASE: i32 = 12;
+
+pub const ROOK_ON_OPEN_FILE: Score = Score::new(25, 10);
+pub const ROOK_ON_HALF_OPEN_FILE: Score = Score::new(12, 6);
+pub const ROOK_ON_SEVENTH: Score = Score::new(20, 30);
+pub const BISHOP_PAIR: Score = Score::new(30, 50);
+pub const KNIGHT_OUTPOST: Score = Score::new(20, 10);
+pub const TRAPPED_BISHOP: Score = Score::new(-100, -100);
+pub const TRAPPED_ROOK: Score = Score::new(-40, -10);
+// a rook with at most that many safe squares next to its own king is trapped
+const TRAPPED_ROOK_MOBILITY: i32 = 3;
+
+fn pawn_attacks(board: &Board, color: Color) -> BitBoard {
+    (board.pieces(Piece::Pawn) & board.color_combined(color))
+        .into_iter()
+        .fold(EMPTY, |acc, sq| acc | get_pawn_attacks(sq, color, !EMPTY))
+}
+
+fn mobility_value(weight: Score, safe_squares: BitBoard, base: i32) -> Score {
+    let count = safe_squares.popcnt() as i32 - base;
+    Score::new(weight.mg * count, weight.eg * count)
+}
+
+fn is_outpost(board: &Board, sq: Square, color: Color) -> bool {
+    let own_pawns = board.pieces(Piece::Pawn) & board.color_combined(color);
+    let enemy_pawns = board.pieces(Piece::Pawn) & board.color_combined(!color);
+    let rank = relative_rank(sq, color);
+    let supported = get_pawn_attacks(sq, !color, own_pawns) != EMPTY;
+    let attackable =
+        (enemy_pawns & get_adjacent_files(sq.get_file()) & forward_ranks(sq.get_rank(), color)) != EMPTY;
+    (3..=5).contains(&rank) && supported && !attackable
+}
+
+fn is_trapped_bishop(board: &Board, sq: Square, color: Color) -> bool {
+    let enemy_pawns = board.pieces(Piece::Pawn) & board.color_combined(!color);
+    let (trap, blocker) = match (color, sq) {
+        (Color::White, Square::A7) => (true, Square::B6),
+        (Color::White, Square::H7) => (true, Square::G6),
+        (Color::Black, Square::A2) => (true, Square::B3),
+        (Color::Black, Square::H2) => (true, Square::G3),
+        _ => (false, sq),
+    };
+    trap && (enemy_pawns & BitBoard::from_square(blocker)) != EMPTY
+}
+
+fn is_trapped_rook(board: &Board, sq: Square, color: Color, mobility: i32) -> bool {
+    let king = board.king_square(color);
+    let king_file = king.get_file().to_index();
+    let rook_file = sq.get_file().to_index();
+    let on_back_rank = relative_rank(sq, color) == 0 && relative_rank(king, color) == 0;
+    let boxed_in =
+        (king_file >= 4 && rook_file > king_file) || (king_file < 4 && rook_file < king_file);
+    on_back_rank && boxed_in && mobility <= TRAPPED_ROOK_MOBILITY
+}
+
+pub fn side_mobility(board: &Board, color: Color, params: &EvalParams) -> Score {
+    let own = board.color_combined(color);
+    let occupied = *board.combined();
+    let own_pawns = board.pieces(Piece::Pawn) & own;
+    let enemy_pawns = board.pieces(Piece::Pawn) & board.color_combined(!color);
+    let enemy_king = board.king_square(!color);
+    let available = !own & !pawn_attacks(board, !color);
+    let mut score = Score::default();
+
+    for sq in own & board.pieces(Piece::Knight) {
+        score += mobility_value(
+            params.knight_mobility,
+            get_knight_moves(sq) & available,
+            KNIGHT_MOBILITY_BASE,
+        );
+        if is_outpost(board, sq, color) {
+            score += params.knight_outpost;
+        }
+    }
+
+    let bishops = own & board.pieces(Piece::Bishop);
+    for sq in bishops {
+        score += mobility_value(
+            params.bishop_mobility,
+            get_bishop_moves(sq, occupied) & available,
+            BISHOP_MOBILITY_BASE,
+        );
+        if is_trapped_bishop(board, sq, color) {
+            score += params.trapped_bishop;
+        }
+    }
+    if bishops.popcnt() >= 2 {
+        score += params.bishop_pair;
+    }
+
+    for sq in own & board.pieces(Piece::Rook) {
+        let safe_squares = get_rook_moves(sq, occupied) & available;
+        score += mobility_value(params.rook_mobility, safe_squares, ROOK_MOBILITY_BASE);
+
+        let file = get_file(sq.get_file());
+        if (own_pawns & file) == EMPTY {
+            score += if (enemy_pawns & file) == EMPTY {
+                params.rook_on_open_file
+            } else {
+                params.rook_on_half_open_file
+            };
+        }
+
+        let seventh = match color {
+            Color::White => Rank::Seventh,
+            Color::Black => Rank::Second,
+        };
+        let enemy_on_seventh = (enemy_pawns & chess::get_rank(seventh)) != EMPTY;
+        if relative_rank(sq, color) == 6
+            && (enemy_on_seventh || relative_rank(enemy_king, color) == 7)
+        {
+            score += params.rook_on_seventh;
+        }
+
+        if is_trapped_rook(board, sq, color, safe_squares.popcnt() as i32) {
+            score += params.trapped_rook;
+        }
+    }
+
+    for sq in own & board.pieces(Piece::Queen) {
+        let attacks = get_bishop_moves(sq, occupied) | get_rook_moves(sq, occupied);
+        score += mobility_value(params.queen_mobility, attacks & available, QUEEN_MOBILITY_BASE);
+    }
+    score
+}
+
+/// Mobility and piece activity score, white minus black.
+pub fn mobility(board: &Board, params: &EvalParams) -> Score {
+    side_mobility(board, Color::White, params) - side_mobility(board, Color::Black, params)
+}
+
+#[cfg(test)]
+mod mobility_tests {
+    use super::*;
+    use std::str::FromStr;
+
+    fn white_mobility(fen: &str) -> Score {
+        side_mobility(&Board::from_str(fen).unwrap(), Color::White, &EvalParams::default())
+    }
+
+    #[test]
+    fn start_position_is_balanced() {
+        assert_eq!(mobility(&Board::default(), &EvalParams::default()), Score::default());
+    }
+
+    #[test]
+    fn squares_controlled_by_enemy_pawns_are_not_counted() {
+        let free = white_mobility("4k3/8/8/8/3N4/8/8/4K3 w - - 0 1");
+        let controlled = white_mobility("4k3/8/4p3/8/3N4/8/8/4K3 w - - 0 1");
+        // capturing the pawn on e6 is still possible, f5 is controlled by it
+        assert_eq!(free - controlled, KNIGHT_MOBILITY);
+    }
+
+    #[test]
+    fn rook_on_open_and_half_open_file() {
+        let open = Board::from_str("4k3/p7/8/8/8/8/8/1R2K3 w - - 0 1").unwrap();
+        let half_open = Board::from_str("4k3/p7/8/8/8/8/8/R3K3 w - - 0 1").unwrap();
+        let closed = Board::from_str("4k3/1p6/8/8/8/8/1P6/1R2K3 w - - 0 1").unwrap();
+        // score of the rook without its mobility
+        let rook_value = |board: &Board| {
+            let rook = board.pieces(Piece::Rook).to_square();
+            let safe = get_rook_moves(rook, *board.combined())
+                & !board.color_combined(Color::White)
+                & !pawn_attacks(board, Color::Black);
+            side_mobility(board, Color::White, &EvalParams::default())
+                - mobility_value(ROOK_MOBILITY, safe, ROOK_MOBILITY_BASE)
+        };
+        assert_eq!(rook_value(&open), ROOK_ON_OPEN_FILE);
+        assert_eq!(rook_value(&half_open), ROOK_ON_HALF_OPEN_FILE);
+        assert_eq!(rook_value(&closed), Score::default());
+    }
+
+    #[test]
+    fn rook_on_seventh() {
+        let board = Board::from_str("6k1/2R5/8/8/8/8/8/4K3 w - - 0 1").unwrap();
+        let moved = Board::from_str("6k1/8/2R5/8/8/8/8/4K3 w - - 0 1").unwrap();
+        let seventh = side_mobility(&board, Color::White, &EvalParams::default());
+        let sixth = side_mobility(&moved, Color::White, &EvalParams::default());
+        assert_eq!(seventh - sixth, ROOK_ON_SEVENTH);
+    }
+
+    #[test]
+    fn bishop_pair() {
+        let pair = white_mobility("4k3/8/8/8/8/8/8/2B1KB2 w - - 0 1");
+        let single = white_mobility("4k3/8/8/8/8/8/8/2B1K3 w - - 0 1");
+        let single_bishop = mobility_value(BISHOP_MOBILITY, BitBoard::new(0), BISHOP_MOBILITY_BASE);
+        assert!(pair.mg - single.mg > BISHOP_PAIR.mg + single_bishop.mg);
+    }
+
+    #[test]
+    fn knight_outpost() {
+        let outpost = Board::from_str("4k3/8/8/3N4/4P3/8/8/4K3 w - - 0 1").unwrap();
+        let attackable = Board::from_str("4k3/2p5/8/3N4/4P3/8/8/4K3 w - - 0 1").unwrap();
+        assert!(is_outpost(&outpost, Square::D5, Color::White));
+        assert!(!is_outpost(&attackable, Square::D5, Color::White));
+    }
+
+    #[test]
+    fn trapped_pieces() {
+        let bishop = Board::from_str("4k3/B7/1p6/8/8/8/8/4K3 w - - 0 1").unwrap();
+        assert!(is_trapped_bishop(&bishop, Square::A7, Color::White));
+        let rook = Board::from_str("4k3/8/8/8/8/8/5PPP/5K1R w - - 0 1").unwrap();
+        assert!(is_trapped_rook(&rook, Square::H1, Color::White, 1));
+        let castled = Board::from_str("4k3/8/8/8/8/8/5PPP/5RK1 w - - 0 1").unwrap();
+        assert!(!is_trapped_rook(&castled, Square::F1, Color::White, 1));
+    }
+}
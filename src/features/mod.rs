@@ -1,8 +1,18 @@
 pub mod board_utils;
+pub mod endgame;
+pub mod eval_params;
 pub mod evaluation;
 pub mod killer_moves;
+pub mod king_safety;
+pub mod mobility;
+#[cfg(feature = "nnue")]
+pub mod nnue;
 pub mod null_move_pruning;
 pub mod opening_book;
+pub mod pawn_structure;
+pub mod polyglot;
+pub mod position_book;
 pub mod quiescence;
+pub mod syzygy;
 pub mod time_management;
 pub mod transposition_table;
@@ -0,0 +1,418 @@
+//! HalfKA neural network evaluation, enabled with the `nnue` cargo feature.
+//!
+//! Every perspective has its own accumulator over the features (own king square, piece, piece square),
+//! squares and colors are mirrored for black so the network always sees its side as white.
+//! The output is `(crelu(stm) . w_stm + crelu(nstm) . w_nstm + bias) * SCALE / (QA * QB)`.
+//!
+//! Network file, all values little endian:
+//! `RBNN`, u32 version, u32 hidden size, i16 feature weights `[feature][hidden]`,
+//! i16 feature biases `[hidden]`, i16 output weights `[2 * hidden]` (side to move half first), i32 output bias.
+
+use chess::{Board, Color, Piece, Square, ALL_COLORS, ALL_PIECES};
+#[cfg(test)]
+use rand::{rngs::StdRng, Rng, SeedableRng};
+use std::fs;
+
+const MAGIC: &[u8; 4] = b"RBNN";
+const VERSION: u32 = 1;
+pub const INPUTS: usize = 64 * 12 * 64;
+const QA: i32 = 255;
+const QB: i32 = 64;
+const SCALE: i32 = 400;
+pub const DEFAULT_EVAL_FILE: &str = "nn.bin";
+
+pub struct Network {
+    hidden: usize,
+    feature_weights: Vec<i16>,
+    feature_bias: Vec<i16>,
+    output_weights: Vec<i16>,
+    output_bias: i32,
+}
+
+/// Hidden layer values of both perspectives, indexed by `Color::to_index`.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Accumulator {
+    values: [Vec<i16>; 2],
+}
+
+fn orient(sq: Square, perspective: Color) -> usize {
+    match perspective {
+        Color::White => sq.to_index(),
+        Color::Black => sq.to_index() ^ 56,
+    }
+}
+
+fn feature_index(perspective: Color, king: Square, piece: Piece, color: Color, sq: Square) -> usize {
+    let relative_color = (color != perspective) as usize;
+    orient(king, perspective) * 768 + (relative_color * 6 + piece.to_index()) * 64 + orient(sq, perspective)
+}
+
+struct Reader<'a> {
+    bytes: &'a [u8],
+}
+
+impl Reader<'_> {
+    fn take<const N: usize>(&mut self) -> Result<[u8; N], String> {
+        if self.bytes.len() < N {
+            return Err("Network file is truncated".to_string());
+        }
+        let (value, rest) = self.bytes.split_at(N);
+        self.bytes = rest;
+        Ok(value.try_into().unwrap())
+    }
+
+    fn u32(&mut self) -> Result<u32, String> {
+        Ok(u32::from_le_bytes(self.take()?))
+    }
+
+    fn i32(&mut self) -> Result<i32, String> {
+        Ok(i32::from_le_bytes(self.take()?))
+    }
+
+    fn i16s(&mut self, count: usize) -> Result<Vec<i16>, String> {
+        (0..count).map(|_| Ok(i16::from_le_bytes(self.take()?))).collect()
+    }
+}
+
+impl Network {
+    pub fn load(path: &str) -> Result<Self, String> {
+        let bytes = fs::read(path).map_err(|err| "Cannot read |".to_string() + path + "|: " + &*err.to_string())?;
+        Network::from_bytes(&bytes)
+    }
+
+    pub fn from_bytes(bytes: &[u8]) -> Result<Self, String> {
+        let mut reader = Reader { bytes };
+        if &reader.take::<4>()? != MAGIC {
+            return Err("Not a network file".to_string());
+        }
+        let version = reader.u32()?;
+        if version != VERSION {
+            return Err("Unsupported network version ".to_string() + &*version.to_string());
+        }
+        let hidden = reader.u32()? as usize;
+        let network = Network {
+            hidden,
+            feature_weights: reader.i16s(INPUTS * hidden)?,
+            feature_bias: reader.i16s(hidden)?,
+            output_weights: reader.i16s(2 * hidden)?,
+            output_bias: reader.i32()?,
+        };
+        if !reader.bytes.is_empty() {
+            return Err("Unexpected data after the network".to_string());
+        }
+        Ok(network)
+    }
+
+    #[cfg(test)]
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let mut bytes = MAGIC.to_vec();
+        bytes.extend(VERSION.to_le_bytes());
+        bytes.extend((self.hidden as u32).to_le_bytes());
+        for value in self.feature_weights.iter().chain(&self.feature_bias).chain(&self.output_weights) {
+            bytes.extend(value.to_le_bytes());
+        }
+        bytes.extend(self.output_bias.to_le_bytes());
+        bytes
+    }
+
+    /// Untrained network with small random weights.
+    #[cfg(test)]
+    pub fn random(hidden: usize, seed: u64) -> Self {
+        let mut rng = StdRng::seed_from_u64(seed);
+        let mut values = |count: usize, range: i16| (0..count).map(|_| rng.gen_range(-range..=range)).collect();
+        Network {
+            hidden,
+            feature_weights: values(INPUTS * hidden, 32),
+            feature_bias: values(hidden, 64),
+            output_weights: values(2 * hidden, 64),
+            output_bias: 0,
+        }
+    }
+
+    fn feature_row(&self, index: usize) -> &[i16] {
+        &self.feature_weights[index * self.hidden..(index + 1) * self.hidden]
+    }
+
+    fn refresh_perspective(&self, board: &Board, perspective: Color, values: &mut [i16]) {
+        values.copy_from_slice(&self.feature_bias);
+        let king = board.king_square(perspective);
+        for color in ALL_COLORS {
+            for piece in ALL_PIECES {
+                for sq in board.pieces(piece) & board.color_combined(color) {
+                    simd::add_row(values, self.feature_row(feature_index(perspective, king, piece, color, sq)));
+                }
+            }
+        }
+    }
+
+    /// Accumulator computed from scratch.
+    pub fn accumulator(&self, board: &Board) -> Accumulator {
+        let mut accumulator = Accumulator {
+            values: [vec![0; self.hidden], vec![0; self.hidden]],
+        };
+        for perspective in ALL_COLORS {
+            self.refresh_perspective(board, perspective, &mut accumulator.values[perspective.to_index()]);
+        }
+        accumulator
+    }
+
+    /// Writes to `child` the accumulator of `after`, a position reached from `before` by a move
+    /// (or a null move). Only the pieces which differ are updated, a perspective whose king moved is refreshed.
+    pub fn update(&self, parent: &Accumulator, before: &Board, after: &Board, child: &mut Accumulator) {
+        for perspective in ALL_COLORS {
+            let values = &mut child.values[perspective.to_index()];
+            let king = after.king_square(perspective);
+            if king != before.king_square(perspective) {
+                self.refresh_perspective(after, perspective, values);
+                continue;
+            }
+            values.copy_from_slice(&parent.values[perspective.to_index()]);
+            for color in ALL_COLORS {
+                for piece in ALL_PIECES {
+                    let old = before.pieces(piece) & before.color_combined(color);
+                    let new = after.pieces(piece) & after.color_combined(color);
+                    for sq in old & !new {
+                        simd::sub_row(values, self.feature_row(feature_index(perspective, king, piece, color, sq)));
+                    }
+                    for sq in new & !old {
+                        simd::add_row(values, self.feature_row(feature_index(perspective, king, piece, color, sq)));
+                    }
+                }
+            }
+        }
+    }
+
+    /// Side to move relative score.
+    pub fn evaluate(&self, accumulator: &Accumulator, side_to_move: Color) -> i32 {
+        let (own_weights, enemy_weights) = self.output_weights.split_at(self.hidden);
+        let sum = simd::crelu_dot(&accumulator.values[side_to_move.to_index()], own_weights, QA)
+            + simd::crelu_dot(&accumulator.values[(!side_to_move).to_index()], enemy_weights, QA);
+        (sum + self.output_bias) * SCALE / (QA * QB)
+    }
+
+    pub fn evaluate_board(&self, board: &Board) -> i32 {
+        self.evaluate(&self.accumulator(board), board.side_to_move())
+    }
+}
+
+/// Network with the accumulators of the positions on the current search path, indexed by ply.
+/// Unmaking a move needs no work, the accumulator of the parent is still on the stack.
+pub struct NnueState {
+    network: Network,
+    stack: Vec<Accumulator>,
+}
+
+impl NnueState {
+    pub fn new(network: Network) -> Self {
+        NnueState { network, stack: Vec::new() }
+    }
+
+    pub fn set_root(&mut self, board: &Board) {
+        let accumulator = self.network.accumulator(board);
+        match self.stack.first_mut() {
+            Some(root) => *root = accumulator,
+            None => self.stack.push(accumulator),
+        }
+    }
+
+    pub fn push(&mut self, before: &Board, after: &Board, ply: usize) {
+        if self.stack.len() <= ply {
+            let empty = self.stack[ply - 1].clone();
+            self.stack.resize(ply + 1, empty);
+        }
+        let (parents, children) = self.stack.split_at_mut(ply);
+        self.network.update(&parents[ply - 1], before, after, &mut children[0]);
+    }
+
+    pub fn evaluate(&self, ply: usize, side_to_move: Color) -> i32 {
+        self.network.evaluate(&self.stack[ply], side_to_move)
+    }
+
+    pub fn evaluate_board(&self, board: &Board) -> i32 {
+        self.network.evaluate_board(board)
+    }
+}
+
+mod simd {
+    pub fn add_row(values: &mut [i16], row: &[i16]) {
+        #[cfg(target_arch = "x86_64")]
+        if is_x86_feature_detected!("avx2") {
+            // SAFETY: avx2 support was checked above
+            return unsafe { avx2::add_row(values, row) };
+        }
+        scalar::add_row(values, row)
+    }
+
+    pub fn sub_row(values: &mut [i16], row: &[i16]) {
+        #[cfg(target_arch = "x86_64")]
+        if is_x86_feature_detected!("avx2") {
+            // SAFETY: avx2 support was checked above
+            return unsafe { avx2::sub_row(values, row) };
+        }
+        scalar::sub_row(values, row)
+    }
+
+    pub fn crelu_dot(values: &[i16], weights: &[i16], max: i32) -> i32 {
+        #[cfg(target_arch = "x86_64")]
+        if is_x86_feature_detected!("avx2") {
+            // SAFETY: avx2 support was checked above
+            return unsafe { avx2::crelu_dot(values, weights, max) };
+        }
+        scalar::crelu_dot(values, weights, max)
+    }
+
+    pub mod scalar {
+        pub fn add_row(values: &mut [i16], row: &[i16]) {
+            for (value, weight) in values.iter_mut().zip(row) {
+                *value = value.wrapping_add(*weight);
+            }
+        }
+
+        pub fn sub_row(values: &mut [i16], row: &[i16]) {
+            for (value, weight) in values.iter_mut().zip(row) {
+                *value = value.wrapping_sub(*weight);
+            }
+        }
+
+        pub fn crelu_dot(values: &[i16], weights: &[i16], max: i32) -> i32 {
+            values
+                .iter()
+                .zip(weights)
+                .map(|(value, weight)| (*value as i32).clamp(0, max) * *weight as i32)
+                .sum()
+        }
+    }
+
+    #[cfg(target_arch = "x86_64")]
+    mod avx2 {
+        use std::arch::x86_64::*;
+
+        const LANES: usize = 16;
+
+        #[target_feature(enable = "avx2")]
+        pub unsafe fn add_row(values: &mut [i16], row: &[i16]) {
+            let chunks = values.len().min(row.len()) / LANES;
+            for i in 0..chunks {
+                let value = _mm256_loadu_si256(values.as_ptr().add(i * LANES) as *const __m256i);
+                let weight = _mm256_loadu_si256(row.as_ptr().add(i * LANES) as *const __m256i);
+                _mm256_storeu_si256(values.as_mut_ptr().add(i * LANES) as *mut __m256i, _mm256_add_epi16(value, weight));
+            }
+            super::scalar::add_row(&mut values[chunks * LANES..], &row[chunks * LANES..]);
+        }
+
+        #[target_feature(enable = "avx2")]
+        pub unsafe fn sub_row(values: &mut [i16], row: &[i16]) {
+            let chunks = values.len().min(row.len()) / LANES;
+            for i in 0..chunks {
+                let value = _mm256_loadu_si256(values.as_ptr().add(i * LANES) as *const __m256i);
+                let weight = _mm256_loadu_si256(row.as_ptr().add(i * LANES) as *const __m256i);
+                _mm256_storeu_si256(values.as_mut_ptr().add(i * LANES) as *mut __m256i, _mm256_sub_epi16(value, weight));
+            }
+            super::scalar::sub_row(&mut values[chunks * LANES..], &row[chunks * LANES..]);
+        }
+
+        #[target_feature(enable = "avx2")]
+        pub unsafe fn crelu_dot(values: &[i16], weights: &[i16], max: i32) -> i32 {
+            let chunks = values.len().min(weights.len()) / LANES;
+            let zero = _mm256_setzero_si256();
+            let upper = _mm256_set1_epi16(max as i16);
+            let mut sum = _mm256_setzero_si256();
+            for i in 0..chunks {
+                let value = _mm256_loadu_si256(values.as_ptr().add(i * LANES) as *const __m256i);
+                let weight = _mm256_loadu_si256(weights.as_ptr().add(i * LANES) as *const __m256i);
+                let clamped = _mm256_min_epi16(_mm256_max_epi16(value, zero), upper);
+                sum = _mm256_add_epi32(sum, _mm256_madd_epi16(clamped, weight));
+            }
+            let mut lanes = [0i32; 8];
+            _mm256_storeu_si256(lanes.as_mut_ptr() as *mut __m256i, sum);
+            lanes.iter().sum::<i32>()
+                + super::scalar::crelu_dot(&values[chunks * LANES..], &weights[chunks * LANES..], max)
+        }
+    }
+}
+
+#[cfg(test)]
+mod nnue_tests {
+    use super::*;
+    use chess::{ChessMove, MoveGen};
+    use std::str::FromStr;
+
+    const HIDDEN: usize = 40;
+
+    fn network() -> Network {
+        Network::random(HIDDEN, 7)
+    }
+
+    // plays `plies` pseudo random moves and checks the incremental accumulator after each of them
+    fn check_incremental(network: Network, fen: &str, plies: usize) {
+        let mut board = Board::from_str(fen).unwrap();
+        let mut state = NnueState::new(network);
+        state.set_root(&board);
+        let mut rng = StdRng::seed_from_u64(11);
+        for ply in 1..=plies {
+            let moves = MoveGen::new_legal(&board).collect::<Vec<ChessMove>>();
+            if moves.is_empty() {
+                break;
+            }
+            let next = board.make_move_new(moves[rng.gen_range(0..moves.len())]);
+            state.push(&board, &next, ply);
+            board = next;
+            assert_eq!(state.stack[ply], state.network.accumulator(&board), "after {} plies", ply);
+            assert_eq!(state.evaluate(ply, board.side_to_move()), state.network.evaluate_board(&board));
+        }
+    }
+
+    #[test]
+    fn incremental_matches_refresh_from_start() {
+        check_incremental(network(), "rnbqkbnr/pppppppp/8/8/8/8/PPPPPPPP/RNBQKBNR w KQkq - 0 1", 80);
+    }
+
+    #[test]
+    fn incremental_matches_refresh_with_castling_promotion_and_en_passant() {
+        check_incremental(network(), "r3k2r/pPppqpb1/bn2pnp1/2pPN3/1p2P3/2N2Q1p/PPPBBPPP/R3K2R w KQkq c6 0 1", 60);
+        check_incremental(network(), "8/2P3k1/8/8/8/8/1p4K1/8 w - - 0 1", 20);
+    }
+
+    #[test]
+    fn null_move_keeps_accumulator() {
+        let board = Board::from_str("r1bqkbnr/pppp1ppp/2n5/4p3/4P3/5N2/PPPP1PPP/RNBQKB1R w KQkq - 2 3").unwrap();
+        let mut state = NnueState::new(network());
+        state.set_root(&board);
+        let null = board.null_move().unwrap();
+        state.push(&board, &null, 1);
+        assert_eq!(state.stack[1], state.stack[0]);
+        assert_eq!(state.evaluate(1, Color::Black), state.network.evaluate_board(&null));
+    }
+
+    #[test]
+    fn mirrored_position_has_equal_score() {
+        let network = network();
+        let board = Board::from_str("r1bqkbnr/pppp1ppp/2n5/4p3/4P3/5N2/PPPP1PPP/RNBQKB1R w KQkq - 2 3").unwrap();
+        let mirrored = Board::from_str("rnbqkb1r/pppp1ppp/5n2/4p3/4P3/2N5/PPPP1PPP/R1BQKBNR b KQkq - 2 3").unwrap();
+        assert_eq!(network.evaluate_board(&board), network.evaluate_board(&mirrored));
+    }
+
+    #[test]
+    fn simd_matches_scalar() {
+        let mut rng = StdRng::seed_from_u64(3);
+        let row: Vec<i16> = (0..HIDDEN).map(|_| rng.gen_range(-500..=500)).collect();
+        let mut values: Vec<i16> = (0..HIDDEN).map(|_| rng.gen_range(-500..=500)).collect();
+        let mut expected = values.clone();
+        simd::add_row(&mut values, &row);
+        simd::scalar::add_row(&mut expected, &row);
+        assert_eq!(values, expected);
+        simd::sub_row(&mut values, &row);
+        simd::scalar::sub_row(&mut expected, &row);
+        assert_eq!(values, expected);
+        assert_eq!(simd::crelu_dot(&values, &row, QA), simd::scalar::crelu_dot(&values, &row, QA));
+    }
+
+    #[test]
+    fn file_round_trip() {
+        let network = Network::random(8, 5);
+        let loaded = Network::from_bytes(&network.to_bytes()).unwrap();
+        assert_eq!(loaded.to_bytes(), network.to_bytes());
+        assert!(Network::from_bytes(&network.to_bytes()[..100]).is_err());
+        assert!(Network::from_bytes(b"nope").is_err());
+    }
+}
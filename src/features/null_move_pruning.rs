@@ -26,10 +26,11 @@ pub fn null_move(
         };
     }
 
-    let new_pos = pos.null_move();
+    let new_pos = pos.null_move().unwrap();
+    engine.nnue_push(&pos, &new_pos, total_depth + 1);
 
     let mut result: Result = engine.negamax(
-        new_pos.unwrap(),
+        new_pos,
         depth - NULL_MOVE_DEPTH_REDUCTION,
         qdepth,
         total_depth + 1,
@@ -1,14 +1,123 @@
+use crate::features::polyglot::PolyglotBook;
+use crate::features::position_book::{tree_moves, PositionBook, DEFAULT_WEIGHT, TREE_WEIGHT_KEY};
 use crate::io::output::send_info;
-use chess::ChessMove;
+use chess::{Board, ChessMove, Color};
 use json::JsonValue;
+use rand::rngs::StdRng;
+use rand::seq::SliceRandom;
+use rand::SeedableRng;
 use std::fs;
 use std::path::Path;
 use std::str::FromStr;
+use std::sync::{Arc, Mutex};
+
+const POLYGLOT_EXTENSION: &str = "bin";
+pub const DEFAULT_VARIETY_THRESHOLD: u32 = 50;
+// a result changes a weight by at most this fraction of it, so one bad game cannot remove a main line
+const LEARNING_STEP_DIVISOR: u32 = 8;
+
+/// How a move is picked among the book moves of a position.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum BookVariety {
+    // the `best` move, or the heaviest one for books without it
+    Best,
+    // a random move with a probability proportional to its weight
+    Weighted,
+    // a random move among the moves with at least the given percentage of the heaviest weight
+    Uniform(u32),
+}
+
+impl BookVariety {
+    pub fn parse(name: &str, threshold: u32) -> Option<Self> {
+        match name.to_lowercase().as_str() {
+            "best" => Some(BookVariety::Best),
+            "weighted" => Some(BookVariety::Weighted),
+            "uniform" => Some(BookVariety::Uniform(threshold.min(100))),
+            _ => None,
+        }
+    }
+}
+
+/// Strategy and random generator of the book moves. The generator is shared by the copies of
+/// a book, so a match started with a seed is reproducible.
+#[derive(Clone)]
+pub struct BookSelection {
+    variety: BookVariety,
+    rng: Arc<Mutex<StdRng>>,
+}
+
+impl BookSelection {
+    pub fn new(variety: BookVariety, seed: Option<u64>) -> Self {
+        let rng = match seed {
+            Some(seed) => StdRng::seed_from_u64(seed),
+            None => StdRng::from_entropy(),
+        };
+        BookSelection { variety, rng: Arc::new(Mutex::new(rng)) }
+    }
+
+    #[cfg(test)]
+    pub fn best() -> Self {
+        Self::new(BookVariety::Best, Some(0))
+    }
+
+    /// Picks one of the allowed moves, `best` is the move preferred by the book.
+    pub fn choose(&self, moves: &[(ChessMove, u32)], best: Option<ChessMove>) -> Option<ChessMove> {
+        // the first of the heaviest moves
+        let heaviest = moves.iter().rev().max_by_key(|(_, weight)| *weight).copied();
+        let mut rng = self.rng.lock().unwrap();
+        match self.variety {
+            BookVariety::Best => best.filter(|best| moves.iter().any(|(mv, _)| mv == best)).or(heaviest.map(|(mv, _)| mv)),
+            BookVariety::Weighted => moves
+                .choose_weighted(&mut *rng, |(_, weight)| *weight)
+                .map(|(mv, _)| *mv)
+                .ok()
+                .or(heaviest.map(|(mv, _)| mv)),
+            BookVariety::Uniform(threshold) => {
+                let max_weight = heaviest?.1 as u64;
+                let candidates: Vec<ChessMove> = moves
+                    .iter()
+                    .filter(|(_, weight)| *weight as u64 * 100 >= max_weight * threshold as u64)
+                    .map(|(mv, _)| *mv)
+                    .collect();
+                candidates.choose(&mut *rng).copied()
+            }
+        }
+    }
+}
+
+/// Weight of a book move after a game, `score` is the result of the side which played it.
+pub fn learned_weight(weight: u32, score: f64) -> u32 {
+    let step = (weight / LEARNING_STEP_DIVISOR).max(1);
+    if score > 0.5 {
+        weight.saturating_add(step)
+    } else if score < 0.5 {
+        // a weight of 0 means never play and is kept
+        weight.saturating_sub(step).max(DEFAULT_WEIGHT.min(weight))
+    } else {
+        weight
+    }
+}
+
+/// Book move played by the engine, remembered to learn from the result of the game.
+#[derive(Clone)]
+struct PlayedMove {
+    // moves from the root of a tree book to the position
+    line: Vec<ChessMove>,
+    board: Board,
+    mv: ChessMove,
+}
 
 #[derive(Clone)]
 pub struct OpeningBook {
     node: Option<JsonValue>,
+    // Polyglot books are looked up by the position instead of the played moves
+    polyglot: Option<Arc<PolyglotBook>>,
+    positions: Option<Arc<PositionBook>>,
     path: String,
+    selection: BookSelection,
+    // moves from the root of a tree book to `node`
+    line: Vec<ChessMove>,
+    played: Vec<PlayedMove>,
 }
 
 pub struct QueryResult {
@@ -17,69 +126,330 @@ pub struct QueryResult {
 }
 
 impl OpeningBook {
-    pub fn new(path: &String) -> Self {
-        return if Path::new(path).exists() {
-            let json = fs::read_to_string(path).unwrap();
-            let book = json::parse(&*json).unwrap();
-            OpeningBook { node: Some(book), path: path.clone() }
-        } else {
-            OpeningBook { node: None, path: path.clone() }
+    /// Polyglot book for a `.bin` file, the book of positions for a JSON file with `positions`,
+    /// the JSON tree of moves otherwise.
+    pub fn new(path: &String, selection: BookSelection) -> Self {
+        let book = OpeningBook {
+            node: None,
+            polyglot: None,
+            positions: None,
+            path: path.clone(),
+            selection,
+            line: Vec::new(),
+            played: Vec::new(),
         };
+        if Path::new(path).extension().is_some_and(|extension| extension == POLYGLOT_EXTENSION) {
+            let polyglot = PolyglotBook::load(path)
+                .map_err(|err| send_info(err + ", playing without a book"))
+                .ok()
+                .map(Arc::new);
+            return OpeningBook { polyglot, ..book };
+        }
+        if !Path::new(path).exists() {
+            return book;
+        }
+        let tree = fs::read_to_string(path)
+            .map_err(|err| "Cannot read |".to_string() + path + "|: " + &*err.to_string())
+            .and_then(|json| json::parse(&json).map_err(|err| "Cannot parse |".to_string() + path + "|: " + &*err.to_string()));
+        match tree {
+            Ok(tree) if PositionBook::is_position_book(&tree) => {
+                let positions = Some(Arc::new(PositionBook::from_json(&tree)));
+                OpeningBook { positions, ..book }
+            }
+            Ok(tree) => book.with_node(Some(tree)),
+            Err(err) => {
+                send_info(err + ", playing without a book");
+                book
+            }
+        }
     }
 
-    pub fn empty() -> Self {
-        return OpeningBook {
-            node: None,
-            path: "".to_string(),
-        };
+    fn with_node(self, node: Option<JsonValue>) -> Self {
+        OpeningBook { node, ..self }
+    }
+
+    fn is_keyed_by_position(&self) -> bool {
+        self.polyglot.is_some() || self.positions.is_some()
+    }
+
+    /// Book for a game started from a FEN, only the books keyed by position can be used.
+    pub fn after_fen(self) -> Self {
+        self.out_of_book()
     }
 
     pub fn update(self, mv: String) -> Self {
-        if self.node.is_none() {
-            send_info("Book empty: ".to_string() + &*mv);
-            return OpeningBook { node: None, path: self.path };
+        if self.is_keyed_by_position() {
+            return self;
         }
+        let Some(node) = &self.node else {
+            send_info("Book empty: ".to_string() + &*mv);
+            return self.with_node(None);
+        };
 
-        let node = self.node.unwrap();
         if !node.has_key(mv.as_str()) {
             send_info("Move not in book: ".to_string() + &*mv);
-            return OpeningBook { node: None, path: self.path };
+            return self.with_node(None);
         };
 
         send_info("Move in book:".to_string() + &*mv);
         let nxt = node[mv.as_str()].clone();
-        OpeningBook { node: Some(nxt), path: self.path }
+        let mut book = self.with_node(Some(nxt));
+        book.line.extend(ChessMove::from_str(&mv));
+        book
     }
 
-    pub fn try_get_best(self) -> QueryResult {
-        if self.node.is_none() {
-            send_info("No move found".to_string());
-            return QueryResult {
-                mv: None,
-                book: OpeningBook { node: None, path: self.path },
+    /// Book moves of the position with their weights, and the move preferred by the book.
+    fn book_moves(&self, pos: &Board) -> (Option<ChessMove>, Vec<(ChessMove, u32)>) {
+        if let Some(polyglot) = &self.polyglot {
+            let moves = polyglot.moves(pos).into_iter().map(|(mv, weight)| (mv, weight as u32)).collect();
+            return (None, moves);
+        }
+        if let Some(positions) = &self.positions {
+            return match positions.get(pos) {
+                Some(entry) => (Some(entry.best), entry.moves.clone()),
+                None => (None, Vec::new()),
             };
         }
+        match &self.node {
+            Some(node) => tree_moves(pos, node),
+            None => (None, Vec::new()),
+        }
+    }
 
-        let node = self.node.unwrap();
-        if !node.has_key("best") {
+    // a tree book is left once no move is played from it
+    fn out_of_book(self) -> Self {
+        if self.is_keyed_by_position() {
+            return self;
+        }
+        self.with_node(None)
+    }
+
+    pub fn try_get_best(self, pos: &Board, search_moves: &[ChessMove]) -> QueryResult {
+        let (best, moves) = self.book_moves(pos);
+        if moves.is_empty() {
             send_info("No move found".to_string());
-            return QueryResult {
-                mv: None,
-                book: OpeningBook { node: None, path: self.path },
-            };
+            return QueryResult { mv: None, book: self.out_of_book() };
         }
 
-        let mv = node["best"].as_str().unwrap();
-        let nxt = node[mv].clone();
-        send_info("Move from book: ".to_string() + mv);
-        let mov = ChessMove::from_str(mv).unwrap();
-        QueryResult {
-            mv: Some(mov),
-            book: OpeningBook { node: Some(nxt), path: self.path },
+        let allowed: Vec<(ChessMove, u32)> = moves
+            .into_iter()
+            .filter(|(mv, _)| search_moves.is_empty() || search_moves.contains(mv))
+            .collect();
+        let Some(mv) = self.selection.choose(&allowed, best) else {
+            send_info("No move found in searchmoves".to_string());
+            return QueryResult { mv: None, book: self.out_of_book() };
+        };
+
+        send_info("Move from book: ".to_string() + &*mv.to_string());
+        let mut book = self;
+        book.played.push(PlayedMove { line: book.line.clone(), board: *pos, mv });
+        if book.is_keyed_by_position() {
+            return QueryResult { mv: Some(mv), book };
         }
+        let nxt = book.node.as_ref().map(|node| node[mv.to_string().as_str()].clone());
+        book.line.push(mv);
+        QueryResult { mv: Some(mv), book: book.with_node(nxt) }
     }
 
     pub fn restart(&self) -> Self {
-        Self::new(&self.path)
+        if self.is_keyed_by_position() {
+            return OpeningBook { played: Vec::new(), ..self.clone() };
+        }
+        Self::new(&self.path, self.selection.clone())
+    }
+
+    /// Changes the weights of the book moves played in the game by its result, from the white
+    /// point of view, and writes them to the book file. Returns the number of changed moves.
+    pub fn learn(&mut self, white_result: f64) -> Result<usize, String> {
+        let played = std::mem::take(&mut self.played);
+        let score = |board: &Board| if board.side_to_move() == Color::White { white_result } else { 1.0 - white_result };
+        let mut learned = 0;
+        if self.polyglot.is_some() {
+            let mut book = PolyglotBook::load(&self.path)?;
+            for played in played.iter() {
+                if let Some(weight) = book.weight_mut(&played.board, played.mv) {
+                    *weight = learned_weight(*weight as u32, score(&played.board)).min(u16::MAX as u32) as u16;
+                    learned += 1;
+                }
+            }
+            book.save(&self.path)?;
+            self.polyglot = Some(Arc::new(book));
+            return Ok(learned);
+        }
+
+        let content = fs::read_to_string(&self.path).map_err(|err| "Cannot read |".to_string() + &self.path + "|: " + &*err.to_string())?;
+        let mut json = json::parse(&content).map_err(|err| "Cannot parse |".to_string() + &self.path + "|: " + &*err.to_string())?;
+        if self.positions.is_some() {
+            let mut book = PositionBook::from_json(&json);
+            for played in played.iter() {
+                if let Some(weight) = book.weight_mut(&played.board, played.mv) {
+                    *weight = learned_weight(*weight, score(&played.board));
+                    learned += 1;
+                }
+            }
+            book.save(&self.path)?;
+            self.positions = Some(Arc::new(book));
+            return Ok(learned);
+        }
+
+        for played in played.iter() {
+            let Some(node) = played.line.iter().try_fold(&mut json, |node, mv| {
+                let mv = mv.to_string();
+                node.has_key(&mv).then(|| &mut node[mv.as_str()])
+            }) else {
+                continue;
+            };
+            let child = &mut node[played.mv.to_string().as_str()];
+            if child.is_null() {
+                *child = JsonValue::new_object();
+            }
+            let weight = child[TREE_WEIGHT_KEY].as_u32().unwrap_or(DEFAULT_WEIGHT);
+            child[TREE_WEIGHT_KEY] = learned_weight(weight, score(&played.board)).into();
+            learned += 1;
+        }
+        // the tree is written compact like the book of the repository
+        fs::write(&self.path, json.dump()).map_err(|err| "Cannot write |".to_string() + &self.path + "|: " + &*err.to_string())?;
+        Ok(learned)
+    }
+}
+
+#[cfg(test)]
+mod opening_book_tests {
+    use super::*;
+
+    fn moves(weights: &[(&str, u32)]) -> Vec<(ChessMove, u32)> {
+        weights.iter().map(|(mv, weight)| (ChessMove::from_str(mv).unwrap(), *weight)).collect()
+    }
+
+    fn picks(selection: &BookSelection, moves: &[(ChessMove, u32)], count: usize) -> Vec<String> {
+        (0..count).map(|_| selection.choose(moves, None).unwrap().to_string()).collect()
+    }
+
+    #[test]
+    fn best_variety_plays_the_best_or_heaviest_move() {
+        let moves = moves(&[("e2e4", 1), ("d2d4", 9), ("c2c4", 9)]);
+        let selection = BookSelection::best();
+        assert_eq!(selection.choose(&moves, ChessMove::from_str("e2e4").ok()), ChessMove::from_str("e2e4").ok());
+        assert_eq!(selection.choose(&moves, None), ChessMove::from_str("d2d4").ok());
+        assert_eq!(selection.choose(&moves, ChessMove::from_str("g1f3").ok()), ChessMove::from_str("d2d4").ok());
+        assert_eq!(selection.choose(&[], None), None);
+    }
+
+    #[test]
+    fn weighted_variety_follows_the_weights() {
+        let moves = moves(&[("e2e4", 3), ("d2d4", 1), ("c2c4", 0)]);
+        let played = picks(&BookSelection::new(BookVariety::Weighted, Some(7)), &moves, 200);
+        let e4 = played.iter().filter(|mv| *mv == "e2e4").count();
+        assert!(e4 > 120 && e4 < 180, "{}", e4);
+        assert!(played.iter().all(|mv| mv != "c2c4"));
+        // a move is still played when no move has a weight
+        let unweighted = [(ChessMove::from_str("g1f3").unwrap(), 0)];
+        assert_eq!(BookSelection::new(BookVariety::Weighted, Some(7)).choose(&unweighted, None), Some(unweighted[0].0));
+    }
+
+    #[test]
+    fn uniform_variety_skips_moves_below_the_threshold() {
+        let moves = moves(&[("e2e4", 10), ("d2d4", 6), ("c2c4", 4)]);
+        let played = picks(&BookSelection::new(BookVariety::Uniform(50), Some(7)), &moves, 200);
+        assert!(played.contains(&"e2e4".to_string()) && played.contains(&"d2d4".to_string()));
+        assert!(!played.contains(&"c2c4".to_string()));
+    }
+
+    #[test]
+    fn seeded_selection_is_reproducible() {
+        let moves = moves(&[("e2e4", 5), ("d2d4", 4), ("c2c4", 3), ("g1f3", 2)]);
+        let first = picks(&BookSelection::new(BookVariety::Weighted, Some(42)), &moves, 200);
+        assert_eq!(first, picks(&BookSelection::new(BookVariety::Weighted, Some(42)), &moves, 200));
+        // copies of a book share the generator, so they continue the same sequence
+        let selection = BookSelection::new(BookVariety::Weighted, Some(42));
+        let mut shared = picks(&selection, &moves, 100);
+        shared.extend(picks(&selection.clone(), &moves, 100));
+        assert_eq!(shared, first);
+
+        assert_eq!(BookVariety::parse("Uniform", 150), Some(BookVariety::Uniform(100)));
+        assert_eq!(BookVariety::parse("random", 50), None);
+    }
+
+    #[test]
+    fn one_result_changes_a_weight_by_a_fraction() {
+        assert_eq!(learned_weight(100, 0.0), 88);
+        assert_eq!(learned_weight(100, 1.0), 112);
+        assert_eq!(learned_weight(100, 0.5), 100);
+        assert_eq!(learned_weight(1, 1.0), 2);
+        assert_eq!(learned_weight(1, 0.0), 1);
+        assert_eq!(learned_weight(0, 0.0), 0);
+        assert_eq!(learned_weight(u32::MAX, 1.0), u32::MAX);
+    }
+
+    fn temp_path(name: &str) -> String {
+        let path = std::env::temp_dir().join(format!("rdzawa_learning_{}_{}", std::process::id(), name));
+        path.to_str().unwrap().to_string()
+    }
+
+    #[test]
+    fn bad_entries_are_out_of_book() {
+        let path = temp_path("bad.json");
+        fs::write(&path, r#"{"best": "e2e5", "e2e5": {"best": "e7e5"}, "d2d4": "not a node"}"#).unwrap();
+        let board = Board::default();
+        let result = OpeningBook::new(&path, BookSelection::best()).try_get_best(&board, &[]);
+        // the illegal best move is skipped for the legal child
+        assert_eq!(result.mv, ChessMove::from_str("d2d4").ok());
+        let after_d4 = board.make_move_new(result.mv.unwrap());
+        assert_eq!(result.book.try_get_best(&after_d4, &[]).mv, None);
+
+        fs::write(&path, r#"{"best": "e2e4", "#).unwrap();
+        assert_eq!(OpeningBook::new(&path, BookSelection::best()).try_get_best(&board, &[]).mv, None);
+        fs::remove_file(path).unwrap();
+    }
+
+    #[test]
+    fn learns_tree_book_from_result() {
+        let path = temp_path("tree.json");
+        fs::write(
+            &path,
+            r#"{"best": "e2e4", "e2e4": {"weight": 16, "best": "e7e5", "e7e5": {"best": "g1f3"}}, "d2d4": {"weight": 4}}"#,
+        )
+        .unwrap();
+        let book = OpeningBook::new(&path, BookSelection::best());
+        let mut board = Board::default();
+        let result = book.try_get_best(&board, &[]);
+        board = board.make_move_new(result.mv.unwrap());
+        let mut book = result.book.update("e7e5".to_string());
+        board = board.make_move_new(ChessMove::from_str("e7e5").unwrap());
+        let result = book.try_get_best(&board, &[]);
+        assert_eq!(result.mv, ChessMove::from_str("g1f3").ok());
+        book = result.book;
+
+        // white, the side of the book, lost
+        assert_eq!(book.learn(0.0), Ok(2));
+        let tree = json::parse(&fs::read_to_string(&path).unwrap()).unwrap();
+        assert_eq!(tree["e2e4"]["weight"], 14);
+        assert_eq!(tree["e2e4"]["e7e5"]["g1f3"]["weight"], 1);
+        assert_eq!(tree["d2d4"]["weight"], 4);
+        assert_eq!(book.learn(0.0), Ok(0));
+        fs::remove_file(path).unwrap();
+    }
+
+    #[test]
+    fn learns_books_keyed_by_position() {
+        let path = temp_path("positions.json");
+        let after_e4 = Board::default().make_move_new(ChessMove::from_str("e2e4").unwrap());
+        fs::write(
+            &path,
+            r#"{"positions": {"rnbqkbnr/pppppppp/8/8/4P3/8/PPPP1PPP/RNBQKBNR b KQkq -":
+                {"best": "c7c5", "moves": [{"move": "c7c5", "weight": 40}, {"move": "e7e5", "weight": 30}]}}}"#,
+        )
+        .unwrap();
+        let mut book = OpeningBook::new(&path, BookSelection::best());
+        assert_eq!(book.clone().learn(1.0), Ok(0));
+        book = book.after_fen().try_get_best(&after_e4, &[]).book;
+
+        // black, the side of the book, won
+        assert_eq!(book.learn(0.0), Ok(1));
+        let learned = PositionBook::from_json(&json::parse(&fs::read_to_string(&path).unwrap()).unwrap());
+        let moves = &learned.get(&after_e4).unwrap().moves;
+        assert_eq!(moves[0], (ChessMove::from_str("c7c5").unwrap(), 45));
+        assert_eq!(moves[1], (ChessMove::from_str("e7e5").unwrap(), 30));
+        fs::remove_file(path).unwrap();
     }
 }
@@ -0,0 +1,311 @@
+use crate::features::eval_params::EvalParams;
+use crate::features::evaluation::Score;
+use chess::{
+    get_adjacent_files, get_file, get_pawn_attacks, get_rank, BitBoard, Board, Color, Piece, Rank, Square,
+    ALL_COLORS, EMPTY,
+};
+use std::cell::RefCell;
+
+const PAWN_TABLE_SIZE: usize = 1 << 14;
+
+pub const DOUBLED_PAWN: Score = Score::new(-10, -20);
+pub const ISOLATED_PAWN: Score = Score::new(-10, -15);
+pub const BACKWARD_PAWN: Score = Score::new(-8, -10);
+
+// indexed by the rank relative to the pawn owner
+#[rustfmt::skip]
+pub const CONNECTED_PAWN: [Score; 8] = [
+    Score::new(0, 0), Score::new(3, 0), Score::new(5, 2), Score::new(8, 5),
+    Score::new(12, 10), Score::new(20, 20), Score::new(35, 35), Score::new(0, 0),
+];
+
+#[rustfmt::skip]
+pub const PASSED_PAWN: [Score; 8] = [
+    Score::new(0, 0), Score::new(5, 10), Score::new(10, 15), Score::new(15, 25),
+    Score::new(25, 45), Score::new(40, 70), Score::new(60, 110), Score::new(0, 0),
+];
+
+// endgame only adjustments of passed pawns, depend on pieces so they are not cached
+pub const PASSED_PAWN_FREE_PATH: [i32; 8] = [0, 0, 5, 10, 20, 35, 50, 0];
+pub const PASSED_PAWN_ENEMY_KING_DISTANCE: i32 = 5;
+pub const PASSED_PAWN_OWN_KING_DISTANCE: i32 = 2;
+
+const fn split_mix(state: u64) -> (u64, u64) {
+    let state = state.wrapping_add(0x9E3779B97F4A7C15);
+    let mut z = state;
+    z = (z ^ (z >> 30)).wrapping_mul(0xBF58476D1CE4E5B9);
+    z = (z ^ (z >> 27)).wrapping_mul(0x94D049BB133111EB);
+    (state, z ^ (z >> 31))
+}
+
+const fn generate_pawn_keys() -> [[u64; 64]; 2] {
+    let mut keys = [[0; 64]; 2];
+    let mut state = 0x2545F4914F6CDD1D_u64;
+    let mut color = 0;
+    while color < 2 {
+        let mut sq = 0;
+        while sq < 64 {
+            let (next_state, key) = split_mix(state);
+            state = next_state;
+            keys[color][sq] = key;
+            sq += 1;
+        }
+        color += 1;
+    }
+    keys
+}
+
+const PAWN_KEYS: [[u64; 64]; 2] = generate_pawn_keys();
+
+/// Zobrist hash of the pawns only.
+pub fn pawn_hash(board: &Board) -> u64 {
+    let mut hash = 0;
+    for color in ALL_COLORS {
+        for sq in board.pieces(Piece::Pawn) & board.color_combined(color) {
+            hash ^= PAWN_KEYS[color.to_index()][sq.to_index()];
+        }
+    }
+    hash
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct PawnEntry {
+    pub key: u64,
+    // white minus black
+    pub score: Score,
+    pub passed: [BitBoard; 2],
+}
+
+pub struct PawnHashTable {
+    entries: Vec<Option<PawnEntry>>,
+}
+
+impl PawnHashTable {
+    pub fn new(size: usize) -> Self {
+        PawnHashTable {
+            entries: vec![None; size.next_power_of_two()],
+        }
+    }
+
+    pub fn probe(&mut self, board: &Board, params: &EvalParams) -> PawnEntry {
+        let key = pawn_hash(board);
+        let index = key as usize & (self.entries.len() - 1);
+        match self.entries[index] {
+            Some(entry) if entry.key == key => entry,
+            _ => {
+                let entry = evaluate_pawns(board, key, params);
+                self.entries[index] = Some(entry);
+                entry
+            }
+        }
+    }
+
+    pub fn clear(&mut self) {
+        self.entries.fill(None);
+    }
+}
+
+thread_local! {
+    static PAWN_TABLE: RefCell<PawnHashTable> = RefCell::new(PawnHashTable::new(PAWN_TABLE_SIZE));
+}
+
+/// Drops all cached entries, needed when the evaluation parameters change.
+pub fn clear_pawn_table() {
+    PAWN_TABLE.with(|table| table.borrow_mut().clear());
+}
+
+/// Rank of the square counted from the side of `color`, 0 is its back rank.
+pub(crate) fn relative_rank(sq: Square, color: Color) -> usize {
+    match color {
+        Color::White => sq.get_rank().to_index(),
+        Color::Black => 7 - sq.get_rank().to_index(),
+    }
+}
+
+/// Ranks strictly in front of `rank` from the point of view of `color`.
+pub(crate) fn forward_ranks(rank: Rank, color: Color) -> BitBoard {
+    let rank = rank.to_index() as u32;
+    match color {
+        Color::White => BitBoard::new(u64::MAX.checked_shl(8 * (rank + 1)).unwrap_or(0)),
+        Color::Black => BitBoard::new((1u64 << (8 * rank)) - 1),
+    }
+}
+
+fn square_distance(a: Square, b: Square) -> i32 {
+    let file_distance = (a.get_file().to_index() as i32 - b.get_file().to_index() as i32).abs();
+    let rank_distance = (a.get_rank().to_index() as i32 - b.get_rank().to_index() as i32).abs();
+    file_distance.max(rank_distance)
+}
+
+fn evaluate_pawns(board: &Board, key: u64, params: &EvalParams) -> PawnEntry {
+    let mut score = Score::default();
+    let mut passed = [EMPTY; 2];
+    for color in ALL_COLORS {
+        let (side_score, side_passed) = evaluate_side_pawns(board, color, params);
+        passed[color.to_index()] = side_passed;
+        match color {
+            Color::White => score += side_score,
+            Color::Black => score -= side_score,
+        }
+    }
+    PawnEntry { key, score, passed }
+}
+
+fn evaluate_side_pawns(board: &Board, color: Color, params: &EvalParams) -> (Score, BitBoard) {
+    let own_pawns = board.pieces(Piece::Pawn) & board.color_combined(color);
+    let enemy_pawns = board.pieces(Piece::Pawn) & board.color_combined(!color);
+    let mut score = Score::default();
+    let mut passed = EMPTY;
+
+    for sq in own_pawns {
+        let file = get_file(sq.get_file());
+        let adjacent_files = get_adjacent_files(sq.get_file());
+        let in_front = forward_ranks(sq.get_rank(), color);
+        let rank = relative_rank(sq, color);
+
+        let doubled = (own_pawns & file & in_front) != EMPTY;
+        if doubled {
+            score += params.doubled_pawn;
+        }
+
+        // only the front pawn of a doubled pair is passed
+        if !doubled && (enemy_pawns & (file | adjacent_files) & in_front) == EMPTY {
+            score += params.passed_pawn[rank];
+            passed |= BitBoard::from_square(sq);
+        }
+
+        let supported = get_pawn_attacks(sq, !color, own_pawns) != EMPTY;
+        let phalanx = (own_pawns & adjacent_files & get_rank(sq.get_rank())) != EMPTY;
+        if supported || phalanx {
+            score += params.connected_pawn[rank];
+        }
+
+        if (own_pawns & adjacent_files) == EMPTY {
+            score += params.isolated_pawn;
+        } else if (own_pawns & adjacent_files & !in_front) == EMPTY {
+            // all neighbours are ahead, so the pawn cannot be supported when its stop square is attacked
+            let stop_square_attacked = sq
+                .forward(color)
+                .is_some_and(|stop| get_pawn_attacks(stop, color, enemy_pawns) != EMPTY);
+            if stop_square_attacked {
+                score += params.backward_pawn;
+            }
+        }
+    }
+    (score, passed)
+}
+
+fn passed_pawns_endgame(board: &Board, passed: BitBoard, color: Color, params: &EvalParams) -> i32 {
+    let occupied = *board.combined();
+    let own_king = board.king_square(color);
+    let enemy_king = board.king_square(!color);
+    let mut value = 0;
+
+    for sq in passed {
+        let rank = relative_rank(sq, color);
+        let path = get_file(sq.get_file()) & forward_ranks(sq.get_rank(), color);
+        if (path & occupied) == EMPTY {
+            value += params.passed_pawn_free_path[rank];
+        }
+
+        if let Some(stop) = sq.forward(color) {
+            let weight = rank as i32 - 2;
+            if weight > 0 {
+                value += weight
+                    * (params.passed_pawn_enemy_king_distance * square_distance(enemy_king, stop)
+                        - params.passed_pawn_own_king_distance * square_distance(own_king, stop));
+            }
+        }
+    }
+    value
+}
+
+/// Pawn structure score, white minus black. The pawn only part is cached in the pawn hash table.
+pub fn pawn_structure(board: &Board, params: &EvalParams) -> Score {
+    let entry = PAWN_TABLE.with(|table| table.borrow_mut().probe(board, params));
+    let endgame = passed_pawns_endgame(board, entry.passed[Color::White.to_index()], Color::White, params)
+        - passed_pawns_endgame(board, entry.passed[Color::Black.to_index()], Color::Black, params);
+    entry.score + Score::new(0, endgame)
+}
+
+/// Uncached pawn structure score of one side, for tracing the evaluation.
+pub fn side_pawn_structure(board: &Board, color: Color, params: &EvalParams) -> Score {
+    let (score, passed) = evaluate_side_pawns(board, color, params);
+    score + Score::new(0, passed_pawns_endgame(board, passed, color, params))
+}
+
+#[cfg(test)]
+mod pawn_structure_tests {
+    use super::*;
+    use std::str::FromStr;
+
+    fn side_score(fen: &str, color: Color) -> (Score, BitBoard) {
+        evaluate_side_pawns(&Board::from_str(fen).unwrap(), color, &EvalParams::default())
+    }
+
+    #[test]
+    fn doubled_and_isolated_pawns() {
+        let (score, _) = side_score("4k3/8/8/8/8/2P5/2P5/4K3 w - - 0 1", Color::White);
+        let expected = DOUBLED_PAWN + ISOLATED_PAWN + ISOLATED_PAWN + PASSED_PAWN[2];
+        assert_eq!(score, expected);
+    }
+
+    #[test]
+    fn passed_pawns_are_detected() {
+        let (_, passed) = side_score("4k3/8/1p6/8/8/8/P5P1/4K3 w - - 0 1", Color::White);
+        assert_eq!(passed, BitBoard::from_square(Square::G2));
+        let (_, passed) = side_score("4k3/8/1p6/8/8/8/P5P1/4K3 w - - 0 1", Color::Black);
+        assert_eq!(passed, EMPTY);
+    }
+
+    #[test]
+    fn passed_pawn_bonus_grows_with_rank() {
+        let (low, _) = side_score("4k3/8/8/8/8/8/P7/4K3 w - - 0 1", Color::White);
+        let (high, _) = side_score("4k3/8/P7/8/8/8/8/4K3 w - - 0 1", Color::White);
+        assert!(high.mg > low.mg && high.eg > low.eg);
+    }
+
+    #[test]
+    fn backward_pawn() {
+        let (score, _) = side_score("4k3/8/8/3p4/1P6/2P5/8/4K3 w - - 0 1", Color::White);
+        let (without_attacker, _) = side_score("4k3/8/8/8/1P6/2P5/8/4K3 w - - 0 1", Color::White);
+        assert_eq!(score - without_attacker, BACKWARD_PAWN - PASSED_PAWN[2]);
+    }
+
+    #[test]
+    fn pawn_hash_ignores_pieces() {
+        let board = Board::from_str("r3k3/pp6/8/8/8/8/PP6/R3K3 w - - 0 1").unwrap();
+        let moved_rook = Board::from_str("r3k3/pp6/8/8/8/8/PP6/3RK3 w - - 0 1").unwrap();
+        let moved_pawn = Board::from_str("r3k3/pp6/8/8/8/P7/1P6/R3K3 w - - 0 1").unwrap();
+        assert_eq!(pawn_hash(&board), pawn_hash(&moved_rook));
+        assert_ne!(pawn_hash(&board), pawn_hash(&moved_pawn));
+    }
+
+    #[test]
+    fn cached_entry_matches_evaluation() {
+        let board = Board::from_str("4k3/pp3p2/8/3P4/8/8/PP3PP1/4K3 w - - 0 1").unwrap();
+        let mut table = PawnHashTable::new(16);
+        let params = EvalParams::default();
+        let first = table.probe(&board, &params);
+        let second = table.probe(&board, &params);
+        assert_eq!(first, second);
+        assert_eq!(first, evaluate_pawns(&board, pawn_hash(&board), &params));
+    }
+
+    #[test]
+    fn king_supports_passed_pawn_in_endgame() {
+        let supported = Board::from_str("8/8/1k3KP1/8/8/8/8/8 w - - 0 1").unwrap();
+        let unsupported = Board::from_str("6k1/8/6P1/8/8/8/8/K7 w - - 0 1").unwrap();
+        let params = EvalParams::default();
+        assert!(pawn_structure(&supported, &params).eg > pawn_structure(&unsupported, &params).eg);
+    }
+
+    #[test]
+    fn side_scores_match_cached_score() {
+        let board = Board::from_str("4k3/pp3p2/8/3P4/8/8/PP3PP1/4K3 w - - 0 1").unwrap();
+        let params = EvalParams::default();
+        let sides = side_pawn_structure(&board, Color::White, &params)
+            - side_pawn_structure(&board, Color::Black, &params);
+        assert_eq!(sides, pawn_structure(&board, &params));
+    }
+}
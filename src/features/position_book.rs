@@ -0,0 +1,237 @@
+//! Opening book keyed by the position, so it is found after transpositions and from any FEN.
+//!
+//! The file is a JSON object with the moves of every position, the positions are FENs without
+//! the move counters: `{"positions": {"<fen>": {"best": "e2e4", "moves": [{"move": "e2e4", "weight": 3}]}}}`.
+//! A move can also be given as a plain string, its weight is then 1. Positions are looked up by
+//! their Polyglot key.
+
+use crate::features::polyglot::polyglot_key;
+use chess::{Board, ChessMove};
+use json::JsonValue;
+use std::collections::{HashMap, VecDeque};
+use std::fs;
+use std::str::FromStr;
+
+pub const POSITIONS_KEY: &str = "positions";
+// keys of the JSON tree which are not moves
+pub const TREE_BEST_KEY: &str = "best";
+pub const TREE_WEIGHT_KEY: &str = "weight";
+pub const TREE_VALUES_KEY: &str = "values";
+// weight of a move without one, like the moves of the books written before weights
+pub const DEFAULT_WEIGHT: u32 = 1;
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct BookEntry {
+    pub fen: String,
+    pub best: ChessMove,
+    // all book moves of the position with their weights, `best` included
+    pub moves: Vec<(ChessMove, u32)>,
+}
+
+#[derive(Default)]
+pub struct PositionBook {
+    entries: HashMap<u64, BookEntry>,
+    // keys in the order of the file, so a saved book keeps it
+    order: Vec<u64>,
+}
+
+/// FEN of the position without the move counters.
+pub fn book_fen(board: &Board) -> String {
+    board.to_string().split(' ').take(4).collect::<Vec<&str>>().join(" ")
+}
+
+/// Move in coordinates like `e2e4`, `None` when it is not legal in the position.
+pub fn parse_move(board: &Board, mv: &str) -> Option<ChessMove> {
+    ChessMove::from_str(mv).ok().filter(|mv| board.legal(*mv))
+}
+
+/// Weight of a child of the JSON tree.
+pub fn tree_weight(node: &JsonValue) -> u32 {
+    node[TREE_WEIGHT_KEY].as_u32().unwrap_or(DEFAULT_WEIGHT)
+}
+
+/// Moves of a node of the JSON tree with their weights, `best` is added if it has no child.
+pub fn tree_moves(board: &Board, node: &JsonValue) -> (Option<ChessMove>, Vec<(ChessMove, u32)>) {
+    let mut moves: Vec<(ChessMove, u32)> = node
+        .entries()
+        .filter(|(key, _)| ![TREE_BEST_KEY, TREE_WEIGHT_KEY, TREE_VALUES_KEY].contains(key))
+        .filter_map(|(key, child)| parse_move(board, key).map(|mv| (mv, tree_weight(child))))
+        .collect();
+    let best = node[TREE_BEST_KEY].as_str().and_then(|mv| parse_move(board, mv));
+    add_best(best, &mut moves);
+    (best, moves)
+}
+
+fn add_best(best: Option<ChessMove>, moves: &mut Vec<(ChessMove, u32)>) {
+    if let Some(best) = best.filter(|best| moves.iter().all(|(mv, _)| mv != best)) {
+        moves.insert(0, (best, DEFAULT_WEIGHT));
+    }
+}
+
+impl PositionBook {
+    pub fn is_position_book(json: &JsonValue) -> bool {
+        json.has_key(POSITIONS_KEY)
+    }
+
+    /// Reads the positions of a book, positions and moves which are not legal are skipped.
+    pub fn from_json(json: &JsonValue) -> Self {
+        let mut book = PositionBook::default();
+        for (fen, node) in json[POSITIONS_KEY].entries() {
+            let Ok(board) = Board::from_str(&(fen.to_string() + " 0 1")) else {
+                continue;
+            };
+            let mut moves: Vec<(ChessMove, u32)> = node["moves"]
+                .members()
+                .filter_map(|mv| match mv.as_str() {
+                    Some(mv) => parse_move(&board, mv).map(|mv| (mv, DEFAULT_WEIGHT)),
+                    None => mv["move"]
+                        .as_str()
+                        .and_then(|name| parse_move(&board, name))
+                        .map(|name| (name, mv[TREE_WEIGHT_KEY].as_u32().unwrap_or(DEFAULT_WEIGHT))),
+                })
+                .collect();
+            let best = node[TREE_BEST_KEY].as_str().and_then(|mv| parse_move(&board, mv));
+            add_best(best, &mut moves);
+            if let Some(best) = best.or(moves.first().map(|(mv, _)| *mv)) {
+                book.insert(&board, best, moves);
+            }
+        }
+        book
+    }
+
+    pub fn to_json(&self) -> JsonValue {
+        let mut positions = JsonValue::new_object();
+        for key in self.order.iter() {
+            let entry = &self.entries[key];
+            let mut node = JsonValue::new_object();
+            node[TREE_BEST_KEY] = entry.best.to_string().into();
+            let mut moves = JsonValue::new_array();
+            for (mv, weight) in entry.moves.iter() {
+                let mut book_move = JsonValue::new_object();
+                book_move["move"] = mv.to_string().into();
+                book_move[TREE_WEIGHT_KEY] = (*weight).into();
+                moves.push(book_move).unwrap();
+            }
+            node["moves"] = moves;
+            positions[entry.fen.as_str()] = node;
+        }
+        let mut json = JsonValue::new_object();
+        json[POSITIONS_KEY] = positions;
+        json
+    }
+
+    pub fn save(&self, path: &str) -> Result<(), String> {
+        fs::write(path, self.to_json().pretty(2)).map_err(|err| "Cannot write |".to_string() + path + "|: " + &*err.to_string())
+    }
+
+    /// Positions of a JSON tree of moves from the initial position. Moves of transposed
+    /// positions are merged and their weights added, the best move of the shortest line is kept.
+    pub fn from_tree(tree: &JsonValue) -> Self {
+        let mut book = PositionBook::default();
+        let mut queue = VecDeque::from([(Board::default(), tree)]);
+        while let Some((board, node)) = queue.pop_front() {
+            let (best, moves) = tree_moves(&board, node);
+            for (mv, _) in moves.iter() {
+                if node.has_key(&mv.to_string()) {
+                    queue.push_back((board.make_move_new(*mv), &node[mv.to_string().as_str()]));
+                }
+            }
+            if let Some(best) = best.or(moves.first().map(|(mv, _)| *mv)) {
+                book.insert(&board, best, moves);
+            }
+        }
+        book
+    }
+
+    fn insert(&mut self, board: &Board, best: ChessMove, moves: Vec<(ChessMove, u32)>) {
+        let key = polyglot_key(board);
+        match self.entries.get_mut(&key) {
+            Some(entry) => {
+                for (mv, weight) in moves {
+                    match entry.moves.iter_mut().find(|(known, _)| *known == mv) {
+                        Some((_, known_weight)) => *known_weight += weight,
+                        None => entry.moves.push((mv, weight)),
+                    }
+                }
+            }
+            None => {
+                self.entries.insert(key, BookEntry { fen: book_fen(board), best, moves });
+                self.order.push(key);
+            }
+        }
+    }
+
+    pub fn get(&self, board: &Board) -> Option<&BookEntry> {
+        self.entries.get(&polyglot_key(board))
+    }
+
+    pub fn weight_mut(&mut self, board: &Board, mv: ChessMove) -> Option<&mut u32> {
+        let entry = self.entries.get_mut(&polyglot_key(board))?;
+        entry.moves.iter_mut().find(|(known, _)| *known == mv).map(|(_, weight)| weight)
+    }
+
+    pub fn len(&self) -> usize {
+        self.entries.len()
+    }
+}
+
+#[cfg(test)]
+mod position_book_tests {
+    use super::*;
+
+    fn play(moves: &[&str]) -> Board {
+        moves.iter().fold(Board::default(), |board, mv| board.make_move_new(ChessMove::from_str(mv).unwrap()))
+    }
+
+    fn tree() -> JsonValue {
+        json::parse(
+            r#"{"best": "e2e4", "values": {"max_depth": 32},
+                "e2e4": {"weight": 5, "best": "e7e5", "e7e5": {"best": "g1f3", "g1f3": {"best": "b8c6", "b8c6": {"weight": 2}}}},
+                "g1f3": {"best": "e7e5", "e7e5": {"best": "e2e4", "e2e4": {"best": "g8f6", "b8c6": {"weight": 3}}}, "e1e8": {"best": "a7a6"}}}"#,
+        )
+        .unwrap()
+    }
+
+    #[test]
+    fn converts_tree_with_transpositions() {
+        let book = PositionBook::from_tree(&tree());
+        let start = book.get(&Board::default()).unwrap();
+        assert_eq!(start.best.to_string(), "e2e4");
+        let moves: Vec<(String, u32)> = start.moves.iter().map(|(mv, weight)| (mv.to_string(), *weight)).collect();
+        assert_eq!(moves, vec![("e2e4".to_string(), 5), ("g1f3".to_string(), 1)]);
+
+        // 1. e4 e5 2. Nf3 and 1. Nf3 e5 2. e4 reach the same position
+        let transposed = book.get(&play(&["g1f3", "e7e5", "e2e4"])).unwrap();
+        assert_eq!(transposed.best.to_string(), "b8c6");
+        // the weights of the moves of both lines are added
+        let b8c6 = ChessMove::from_str("b8c6").unwrap();
+        assert_eq!(transposed.moves, vec![(b8c6, 5), (ChessMove::from_str("g8f6").unwrap(), 1)]);
+        assert_eq!(book.len(), 6);
+    }
+
+    #[test]
+    fn saved_book_is_read_back() {
+        let book = PositionBook::from_tree(&tree());
+        let restored = PositionBook::from_json(&json::parse(&book.to_json().dump()).unwrap());
+        assert_eq!(restored.len(), book.len());
+        assert_eq!(restored.to_json(), book.to_json());
+        let position = play(&["e2e4", "e7e5"]);
+        assert_eq!(restored.get(&position), book.get(&position));
+    }
+
+    #[test]
+    fn skips_illegal_entries() {
+        let json = json::parse(
+            r#"{"positions": {
+                "rnbqkbnr/pppppppp/8/8/8/8/PPPPPPPP/RNBQKBNR w KQkq -": {"best": "e2e5", "moves": ["e2e5", "d2d4", {"move": "c2c4", "weight": 4}]},
+                "not a fen": {"best": "e2e4"},
+                "rnbqkbnr/pppppppp/8/8/4P3/8/PPPP1PPP/RNBQKBNR b KQkq -": {"best": "e2e4"}}}"#,
+        )
+        .unwrap();
+        let book = PositionBook::from_json(&json);
+        assert_eq!(book.len(), 1);
+        let entry = book.get(&Board::default()).unwrap();
+        assert_eq!(entry.best.to_string(), "d2d4");
+        assert_eq!(entry.moves, vec![(ChessMove::from_str("d2d4").unwrap(), 1), (ChessMove::from_str("c2c4").unwrap(), 4)]);
+    }
+}
@@ -1,7 +1,6 @@
 use crate::features::board_utils::{is_insufficient_material, status};
-use crate::features::evaluation::eval;
 use crate::minmax_engine::{MinMaxEngine, Result};
-use chess::{Board, BoardStatus, ChessMove, Color, MoveGen, Piece, EMPTY};
+use chess::{Board, BoardStatus, ChessMove, MoveGen, Piece, EMPTY};
 use std::time::Instant;
 
 pub fn quiescence(
@@ -13,7 +12,7 @@ pub fn quiescence(
     beta: i32,
     end_time: Instant,
 ) -> Result {
-    if (engine.evaluations_cnt & 511) == 0 && end_time <= Instant::now() {
+    if engine.should_stop(end_time) {
         return Result {
             score: alpha,
             chosen_move: None,
@@ -21,6 +20,10 @@ pub fn quiescence(
         };
     }
 
+    if total_depth == 0 {
+        engine.nnue_set_root(&pos);
+    }
+
     let mut moves_generator = MoveGen::new_legal(&pos);
     let any_legal_move = moves_generator.size_hint().0 > 0;
     if *pos.checkers() == EMPTY {
@@ -33,11 +36,7 @@ pub fn quiescence(
 
     let board_status = status(&pos, any_legal_move, insufficient_material);
 
-    let stand_pat = if pos.side_to_move() == Color::White {
-        eval(&pos, board_status, total_depth)
-    } else {
-        -eval(&pos, board_status, total_depth)
-    };
+    let stand_pat = engine.evaluate_node(&pos, board_status, total_depth);
 
     if stand_pat >= beta {
         return Result {
@@ -87,6 +86,7 @@ pub fn quiescence(
     let mut new_pos = pos.clone();
     for (_, next_move) in move_order {
         pos.make_move(next_move, &mut new_pos);
+        engine.nnue_push(&pos, &new_pos, total_depth + 1);
 
         let mut result: Result = quiescence(
             &mut engine,
@@ -0,0 +1,1619 @@
+//! Syzygy endgame tablebase probing.
+//!
+//! WDL (`.rtbw`) and DTZ (`.rtbz`) files are found by name in the directories of the
+//! `SyzygyPath` option and read into memory on the first probe of their material.
+//! The decoding follows the layout of the original generator: a header with the piece
+//! order of every sub-table, the canonical Huffman coded pairs data, an index table
+//! with one entry per `2^idx_bits` positions, the block sizes and the 64 byte aligned blocks.
+//! Values are from the side to move point of view: -2 loss, -1 loss saved by the 50 move
+//! rule, 0 draw, 1 win spoiled by the 50 move rule, 2 win.
+
+use crate::features::endgame::KNOWN_WIN;
+use chess::{Board, BoardStatus, CastleRights, ChessMove, Color, MoveGen, Piece, EMPTY};
+use std::collections::HashMap;
+use std::fs;
+use std::path::PathBuf;
+use std::sync::OnceLock;
+
+pub const MAX_PIECES: usize = 7;
+/// Score of a won tablebase position, above the known wins of the evaluation and reported in
+/// centipawns, not as a mate.
+pub const TB_WIN_SCORE: i32 = 2 * KNOWN_WIN;
+// shorter paths to a tablebase win score higher
+pub const TB_PLY_PENALTY: i32 = 1;
+
+const WDL_MAGIC: u32 = 0x5d23e871;
+const DTZ_MAGIC: u32 = 0xa50c66d7;
+const WDL_SUFFIX: &str = "rtbw";
+const DTZ_SUFFIX: &str = "rtbz";
+
+// piece letters of a table name in file order, with the piece codes used in the files
+const PIECE_LETTERS: [(char, Piece); 6] = [
+    ('K', Piece::King),
+    ('Q', Piece::Queen),
+    ('R', Piece::Rook),
+    ('B', Piece::Bishop),
+    ('N', Piece::Knight),
+    ('P', Piece::Pawn),
+];
+
+// dtz of a position after a zeroing move, indexed by wdl + 2
+const WDL_TO_DTZ: [i32; 5] = [-1, -101, 0, 101, 1];
+const WDL_TO_MAP: [usize; 5] = [1, 3, 0, 2, 0];
+const PA_FLAGS: [u8; 5] = [8, 0, 0, 0, 4];
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Wdl {
+    Loss,
+    BlessedLoss,
+    Draw,
+    CursedWin,
+    Win,
+}
+
+impl Wdl {
+    fn from_value(value: i32) -> Wdl {
+        match value {
+            -2 => Wdl::Loss,
+            -1 => Wdl::BlessedLoss,
+            0 => Wdl::Draw,
+            1 => Wdl::CursedWin,
+            _ => Wdl::Win,
+        }
+    }
+
+    fn from_dtz(dtz: i32) -> Wdl {
+        match dtz {
+            dtz if dtz < -100 => Wdl::BlessedLoss,
+            dtz if dtz < 0 => Wdl::Loss,
+            0 => Wdl::Draw,
+            dtz if dtz <= 100 => Wdl::Win,
+            _ => Wdl::CursedWin,
+        }
+    }
+}
+
+/// Side to move score of a tablebase result found `total_depth` plies from the root.
+/// Results spoiled by the 50 move rule are draws.
+pub fn wdl_score(wdl: Wdl, total_depth: usize) -> i32 {
+    match wdl {
+        Wdl::Win => TB_WIN_SCORE - TB_PLY_PENALTY * total_depth as i32,
+        Wdl::Loss => -TB_WIN_SCORE + TB_PLY_PENALTY * total_depth as i32,
+        _ => 0,
+    }
+}
+
+fn off_diag(sq: usize) -> i32 {
+    ((sq >> 3) as i32 - (sq & 7) as i32).signum()
+}
+
+fn flip_diag(sq: usize) -> usize {
+    ((sq >> 3) | (sq << 3)) & 63
+}
+
+#[rustfmt::skip]
+const TRIANGLE: [usize; 64] = [
+    6, 0, 1, 2, 2, 1, 0, 6,
+    0, 7, 3, 4, 4, 3, 7, 0,
+    1, 3, 8, 5, 5, 8, 3, 1,
+    2, 4, 5, 9, 9, 5, 4, 2,
+    2, 4, 5, 9, 9, 5, 4, 2,
+    1, 3, 8, 5, 5, 8, 3, 1,
+    0, 7, 3, 4, 4, 3, 7, 0,
+    6, 0, 1, 2, 2, 1, 0, 6,
+];
+
+#[rustfmt::skip]
+const LOWER: [usize; 64] = [
+    28,  0,  1,  2,  3,  4,  5,  6,
+     0, 29,  7,  8,  9, 10, 11, 12,
+     1,  7, 30, 13, 14, 15, 16, 17,
+     2,  8, 13, 31, 18, 19, 20, 21,
+     3,  9, 14, 18, 32, 22, 23, 24,
+     4, 10, 15, 19, 22, 33, 25, 26,
+     5, 11, 16, 20, 23, 25, 34, 27,
+     6, 12, 17, 21, 24, 26, 27, 35,
+];
+
+#[rustfmt::skip]
+const DIAG: [usize; 64] = [
+     0,  0,  0,  0,  0,  0,  0,  8,
+     0,  1,  0,  0,  0,  0,  9,  0,
+     0,  0,  2,  0,  0, 10,  0,  0,
+     0,  0,  0,  3, 11,  0,  0,  0,
+     0,  0,  0, 12,  4,  0,  0,  0,
+     0,  0, 13,  0,  0,  5,  0,  0,
+     0, 14,  0,  0,  0,  0,  6,  0,
+    15,  0,  0,  0,  0,  0,  0,  7,
+];
+
+#[rustfmt::skip]
+const FLAP: [usize; 64] = [
+    0,  0,  0,  0,  0,  0,  0, 0,
+    0,  6, 12, 18, 18, 12,  6, 0,
+    1,  7, 13, 19, 19, 13,  7, 1,
+    2,  8, 14, 20, 20, 14,  8, 2,
+    3,  9, 15, 21, 21, 15,  9, 3,
+    4, 10, 16, 22, 22, 16, 10, 4,
+    5, 11, 17, 23, 23, 17, 11, 5,
+    0,  0,  0,  0,  0,  0,  0, 0,
+];
+
+#[rustfmt::skip]
+const PAWN_TWIST: [usize; 64] = [
+     0,  0,  0,  0,  0,  0,  0,  0,
+    47, 35, 23, 11, 10, 22, 34, 46,
+    45, 33, 21,  9,  8, 20, 32, 44,
+    43, 31, 19,  7,  6, 18, 30, 42,
+    41, 29, 17,  5,  4, 16, 28, 40,
+    39, 27, 15,  3,  2, 14, 26, 38,
+    37, 25, 13,  1,  0, 12, 24, 36,
+     0,  0,  0,  0,  0,  0,  0,  0,
+];
+
+const FILE_TO_FILE: [usize; 8] = [0, 1, 2, 3, 3, 2, 1, 0];
+
+// number of placements of two kings with the first one in the a1-d1-d4 triangle
+const KK_SIZE: u64 = 462;
+// number of placements of three unique pieces with the first one in the triangle
+const TRIPLE_SIZE: u64 = 31332;
+
+struct Indices {
+    // binomial[k][n] is n choose k
+    binomial: [[u64; 64]; MAX_PIECES],
+    pawn_idx: [[u64; 24]; MAX_PIECES - 1],
+    pawn_factor: [[u64; 4]; MAX_PIECES - 1],
+    kk: [[u64; 64]; 10],
+}
+
+fn indices() -> &'static Indices {
+    static INDICES: OnceLock<Indices> = OnceLock::new();
+    INDICES.get_or_init(|| {
+        let mut binomial = [[0u64; 64]; MAX_PIECES];
+        for (k, row) in binomial.iter_mut().enumerate() {
+            for (n, value) in row.iter_mut().enumerate() {
+                *value = if n < k { 0 } else { (0..k as u64).fold(1, |f, i| f * (n as u64 - i) / (i + 1)) };
+            }
+        }
+
+        let mut pawn_idx = [[0u64; 24]; MAX_PIECES - 1];
+        let mut pawn_factor = [[0u64; 4]; MAX_PIECES - 1];
+        for pawns in 0..MAX_PIECES - 1 {
+            let mut s = 0;
+            for j in 0..24 {
+                pawn_idx[pawns][j] = s;
+                s += binomial[pawns][PAWN_TWIST[(1 + j % 6) * 8 + j / 6]];
+                if (j + 1) % 6 == 0 {
+                    pawn_factor[pawns][j / 6] = s;
+                    s = 0;
+                }
+            }
+        }
+
+        // the second king can not be above the a1-h8 diagonal when the first one is on it,
+        // placements with both kings on the diagonal come last
+        let mut kk = [[u64::MAX; 64]; 10];
+        let mut code = 0;
+        let mut both_on_diagonal = Vec::new();
+        for (idx, row) in kk.iter_mut().enumerate() {
+            let first = (0..64).find(|sq| sq & 7 <= 3 && sq >> 3 <= sq & 7 && TRIANGLE[*sq] == idx).unwrap();
+            for (second, second_code) in row.iter_mut().enumerate() {
+                let file_distance = ((first & 7) as i32 - (second & 7) as i32).abs();
+                let rank_distance = ((first >> 3) as i32 - (second >> 3) as i32).abs();
+                if file_distance <= 1 && rank_distance <= 1 {
+                    continue;
+                }
+                if off_diag(first) == 0 && off_diag(second) > 0 {
+                    continue;
+                }
+                if off_diag(first) == 0 && off_diag(second) == 0 {
+                    both_on_diagonal.push((idx, second));
+                } else {
+                    *second_code = code;
+                    code += 1;
+                }
+            }
+        }
+        for (idx, second) in both_on_diagonal {
+            kk[idx][second] = code;
+            code += 1;
+        }
+        debug_assert_eq!(code, KK_SIZE);
+
+        Indices { binomial, pawn_idx, pawn_factor, kk }
+    })
+}
+
+fn read_u16(data: &[u8], pos: usize) -> Option<u16> {
+    Some(u16::from_le_bytes(data.get(pos..pos + 2)?.try_into().ok()?))
+}
+
+fn read_u32(data: &[u8], pos: usize) -> Option<u32> {
+    Some(u32::from_le_bytes(data.get(pos..pos + 4)?.try_into().ok()?))
+}
+
+// big endian reads of the compressed stream, bytes past the end read as zero
+fn read_be(data: &[u8], pos: usize, bytes: usize) -> u64 {
+    (0..bytes).fold(0, |value, i| (value << 8) | *data.get(pos + i).unwrap_or(&0) as u64)
+}
+
+/// Piece counts of a table, `white` is the side named first in the file name.
+#[derive(Clone, Debug)]
+struct Material {
+    white: [u8; 6],
+    black: [u8; 6],
+}
+
+impl Material {
+    fn parse(name: &str) -> Option<Material> {
+        let (white, black) = name.split_once('v')?;
+        let count = |side: &str| {
+            let mut counts = [0u8; 6];
+            for letter in side.chars() {
+                let index = PIECE_LETTERS.iter().position(|(piece_letter, _)| *piece_letter == letter)?;
+                counts[index] += 1;
+            }
+            (counts[0] == 1).then_some(counts)
+        };
+        let material = Material { white: count(white)?, black: count(black)? };
+        (material.num() <= MAX_PIECES).then_some(material)
+    }
+
+    fn of_board(board: &Board) -> Material {
+        let count = |color: Color| {
+            PIECE_LETTERS.map(|(_, piece)| (board.pieces(piece) & board.color_combined(color)).popcnt() as u8)
+        };
+        Material { white: count(Color::White), black: count(Color::Black) }
+    }
+
+    fn side_name(counts: &[u8; 6]) -> String {
+        PIECE_LETTERS
+            .iter()
+            .zip(counts)
+            .flat_map(|((letter, _), count)| std::iter::repeat_n(*letter, *count as usize))
+            .collect()
+    }
+
+    fn name(&self) -> String {
+        Self::side_name(&self.white) + "v" + &*Self::side_name(&self.black)
+    }
+
+    fn mirrored_name(&self) -> String {
+        Self::side_name(&self.black) + "v" + &*Self::side_name(&self.white)
+    }
+
+    fn num(&self) -> usize {
+        self.white.iter().chain(self.black.iter()).map(|count| *count as usize).sum()
+    }
+
+    fn is_symmetric(&self) -> bool {
+        self.white == self.black
+    }
+
+    fn has_pawns(&self) -> bool {
+        self.white[5] + self.black[5] > 0
+    }
+
+    // pawns of the leading color first, the color with less pawns leads
+    fn pawns(&self) -> [usize; 2] {
+        let (white, black) = (self.white[5] as usize, self.black[5] as usize);
+        if black > 0 && (white == 0 || black < white) {
+            [black, white]
+        } else {
+            [white, black]
+        }
+    }
+
+    // with only the two kings unique the leading group is the kings, otherwise three unique pieces
+    fn kk_enc(&self) -> bool {
+        self.white.iter().chain(self.black.iter()).filter(|count| **count == 1).count() == 2
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+enum Kind {
+    Wdl,
+    Dtz,
+}
+
+/// Piece order and index factors of one sub-table.
+#[derive(Clone, Debug, Default)]
+struct EncInfo {
+    factor: [u64; MAX_PIECES],
+    pieces: [u8; MAX_PIECES],
+    norm: [usize; MAX_PIECES],
+}
+
+impl EncInfo {
+    // header of the sub-table for the file `file` (0 for pawnless tables), returns its size
+    fn parse(material: &Material, data: &[u8], pos: usize, shift: u32, file: usize) -> Option<(EncInfo, u64)> {
+        let indices = indices();
+        let num = material.num();
+        let has_pawns = material.has_pawns();
+        let pawns = material.pawns();
+        let more_pawns = has_pawns && pawns[1] > 0;
+        let mut info = EncInfo::default();
+        for i in 0..num {
+            info.pieces[i] = (data.get(pos + i + 1 + more_pawns as usize)? >> shift) & 0x0f;
+        }
+        let order = ((data.get(pos)? >> shift) & 0x0f) as usize;
+        let order2 = if more_pawns { ((data.get(pos + 1)? >> shift) & 0x0f) as usize } else { 0x0f };
+
+        let mut k = if has_pawns {
+            pawns[0]
+        } else if material.kk_enc() {
+            2
+        } else {
+            3
+        };
+        info.norm[0] = k;
+        if more_pawns {
+            info.norm[k] = pawns[1];
+            k += pawns[1];
+        }
+        let mut i = k;
+        while i < num {
+            let mut j = i;
+            while j < num && info.pieces[j] == info.pieces[i] {
+                info.norm[i] += 1;
+                j += 1;
+            }
+            i += info.norm[i];
+        }
+
+        let mut free = 64 - k;
+        let mut size = 1u64;
+        let mut i = 0;
+        while k < num || i == order || i == order2 {
+            if i == order {
+                info.factor[0] = size;
+                size *= if has_pawns {
+                    indices.pawn_factor[info.norm[0] - 1][file]
+                } else if material.kk_enc() {
+                    KK_SIZE
+                } else {
+                    TRIPLE_SIZE
+                };
+            } else if i == order2 {
+                info.factor[info.norm[0]] = size;
+                size *= indices.binomial[info.norm[info.norm[0]]][48 - info.norm[0]];
+            } else {
+                info.factor[k] = size;
+                size *= indices.binomial[info.norm[k]][free];
+                free -= info.norm[k];
+                k += info.norm[k];
+            }
+            i += 1;
+        }
+        Some((info, size))
+    }
+}
+
+/// Canonical Huffman coded data of one sub-table.
+#[derive(Default)]
+struct PairsData {
+    // value of a sub-table with a single value
+    constant: Option<u8>,
+    block_size: u32,
+    idx_bits: u32,
+    min_len: u32,
+    offsets: usize,
+    sympat: usize,
+    base: Vec<u64>,
+    symlen: Vec<u8>,
+    index_table: usize,
+    size_table: usize,
+    blocks: usize,
+}
+
+impl PairsData {
+    // returns the pairs data, its flags and the sizes of the index table, size table and blocks
+    fn parse(data: &[u8], pos: &mut usize, tb_size: u64, kind: Kind) -> Option<(PairsData, u8, [usize; 3])> {
+        let flags = *data.get(*pos)?;
+        if flags & 0x80 != 0 {
+            let value = if kind == Kind::Wdl { *data.get(*pos + 1)? } else { 0 };
+            *pos += 2;
+            return Some((PairsData { constant: Some(value), ..Default::default() }, flags, [0; 3]));
+        }
+
+        let header = data.get(*pos..*pos + 10)?;
+        let block_size = header[1] as u32;
+        let idx_bits = header[2] as u32;
+        let real_num_blocks = read_u32(data, *pos + 4)? as usize;
+        let num_blocks = real_num_blocks + header[3] as usize;
+        let max_len = header[8] as u32;
+        let min_len = header[9] as u32;
+        if max_len < min_len || min_len == 0 || idx_bits == 0 {
+            return None;
+        }
+        let h = (max_len - min_len + 1) as usize;
+        let offsets = *pos + 10;
+        let num_syms = read_u16(data, offsets + 2 * h)? as usize;
+        let sympat = offsets + 2 * h + 2;
+        data.get(sympat..sympat + 3 * num_syms)?;
+        *pos = sympat + 3 * num_syms + (num_syms & 1);
+
+        let mut pairs = PairsData {
+            constant: None,
+            block_size,
+            idx_bits,
+            min_len,
+            offsets,
+            sympat,
+            base: vec![0; h],
+            symlen: vec![0; num_syms],
+            ..Default::default()
+        };
+        let mut done = vec![false; num_syms];
+        for symbol in 0..num_syms {
+            if !done[symbol] {
+                pairs.calc_symlen(data, symbol, &mut done)?;
+            }
+        }
+        for i in (0..h - 1).rev() {
+            let offset = read_u16(data, offsets + 2 * i)? as u64;
+            let next_offset = read_u16(data, offsets + 2 * i + 2)? as u64;
+            pairs.base[i] = pairs.base[i + 1].wrapping_add(offset).wrapping_sub(next_offset) / 2;
+        }
+        for (i, base) in pairs.base.iter_mut().enumerate() {
+            *base = base.checked_shl(64 - (min_len + i as u32)).unwrap_or(0);
+        }
+
+        let num_indices = (tb_size + (1 << idx_bits) - 1) >> idx_bits;
+        let sizes = [6 * num_indices as usize, 2 * num_blocks, real_num_blocks << block_size];
+        Some((pairs, flags, sizes))
+    }
+
+    fn symbol(&self, data: &[u8], symbol: usize) -> Option<(usize, usize)> {
+        let w = data.get(self.sympat + 3 * symbol..self.sympat + 3 * symbol + 3)?;
+        let left = (((w[1] & 0x0f) as usize) << 8) | w[0] as usize;
+        let right = ((w[2] as usize) << 4) | (w[1] >> 4) as usize;
+        Some((left, right))
+    }
+
+    // number of values of a symbol minus one, symbols are either values or pairs of symbols
+    fn calc_symlen(&mut self, data: &[u8], symbol: usize, done: &mut [bool]) -> Option<()> {
+        done[symbol] = true;
+        let (left, right) = self.symbol(data, symbol)?;
+        if right == 0x0fff {
+            self.symlen[symbol] = 0;
+        } else {
+            for child in [left, right] {
+                if !*done.get(child)? {
+                    self.calc_symlen(data, child, done)?;
+                }
+            }
+            self.symlen[symbol] = self.symlen[left].wrapping_add(self.symlen[right]).wrapping_add(1);
+        }
+        Some(())
+    }
+
+    fn decompress(&self, data: &[u8], idx: u64) -> Option<u8> {
+        if let Some(value) = self.constant {
+            return Some(value);
+        }
+
+        let main_idx = (idx >> self.idx_bits) as usize;
+        let mut lit_idx = (idx & ((1 << self.idx_bits) - 1)) as i64 - (1i64 << (self.idx_bits - 1));
+        let entry = self.index_table + 6 * main_idx;
+        let mut block = read_u32(data, entry)? as usize;
+        lit_idx += read_u16(data, entry + 4)? as i64;
+        let block_length = |block: usize| read_u16(data, self.size_table + 2 * block).map(|size| size as i64 + 1);
+        while lit_idx < 0 {
+            block = block.checked_sub(1)?;
+            lit_idx += block_length(block)?;
+        }
+        while lit_idx >= block_length(block)? {
+            lit_idx -= block_length(block)?;
+            block += 1;
+        }
+
+        let mut ptr = self.blocks + (block << self.block_size);
+        let mut code = read_be(data, ptr, 8);
+        ptr += 8;
+        let mut bit_count = 0;
+        let mut symbol;
+        loop {
+            let mut len = 0;
+            while code < self.base[len] {
+                len += 1;
+                if len == self.base.len() {
+                    return None;
+                }
+            }
+            let bits = self.min_len + len as u32;
+            symbol = read_u16(data, self.offsets + 2 * len)? as usize + ((code - self.base[len]) >> (64 - bits)) as usize;
+            let values = *self.symlen.get(symbol)? as i64 + 1;
+            if lit_idx < values {
+                break;
+            }
+            lit_idx -= values;
+            code = code.checked_shl(bits).unwrap_or(0);
+            bit_count += bits;
+            if bit_count >= 32 {
+                bit_count -= 32;
+                code |= read_be(data, ptr, 4) << bit_count;
+                ptr += 4;
+            }
+        }
+
+        while self.symlen[symbol] != 0 {
+            let (left, right) = self.symbol(data, symbol)?;
+            let left_values = *self.symlen.get(left)? as i64 + 1;
+            if lit_idx < left_values {
+                symbol = left;
+            } else {
+                lit_idx -= left_values;
+                symbol = right;
+            }
+            self.symlen.get(symbol)?;
+        }
+        data.get(self.sympat + 3 * symbol).copied()
+    }
+}
+
+/// Piece order and encoding of all sub-tables of a file, without the compressed data.
+struct Layout {
+    material: Material,
+    // 4 files of pawn tables, 1 otherwise
+    num_tables: usize,
+    split: bool,
+    // indexed by table + num_tables * side
+    infos: Vec<EncInfo>,
+    sizes: Vec<u64>,
+}
+
+impl Layout {
+    // parses the headers following the magic and the flags byte, `pos` ends after them
+    fn parse(material: &Material, kind: Kind, data: &[u8], pos: &mut usize) -> Option<Layout> {
+        let split = kind == Kind::Wdl && data.get(4)? & 0x01 != 0;
+        let num_tables = if material.has_pawns() { 4 } else { 1 };
+        let sides = if split { 2 } else { 1 };
+        let mut infos = vec![EncInfo::default(); num_tables * sides];
+        let mut sizes = vec![0; num_tables * sides];
+        for table in 0..num_tables {
+            for side in 0..sides {
+                let (info, size) = EncInfo::parse(material, data, *pos, 4 * side as u32, table)?;
+                infos[table + num_tables * side] = info;
+                sizes[table + num_tables * side] = size;
+            }
+            *pos += material.num() + 1 + (material.has_pawns() && material.pawns()[1] > 0) as usize;
+        }
+        *pos += *pos & 1;
+        Some(Layout { material: material.clone(), num_tables, split, infos, sizes })
+    }
+
+    // sub-table and index of a position, `flip` swaps the colors of the board and the table
+    fn index(&self, board: &Board, flip: bool, side: usize) -> Option<(usize, u64)> {
+        let num = self.material.num();
+        let color_flip = if flip { 8 } else { 0 };
+        let rank_flip = if flip { 0x38 } else { 0 };
+        let side = if self.split { side } else { 0 };
+        let squares_of = |code: u8| {
+            let color = if (code ^ color_flip) & 8 == 0 { Color::White } else { Color::Black };
+            let piece = match code & 7 {
+                1 => Piece::Pawn,
+                2 => Piece::Knight,
+                3 => Piece::Bishop,
+                4 => Piece::Rook,
+                5 => Piece::Queen,
+                _ => Piece::King,
+            };
+            let squares = board.pieces(piece) & board.color_combined(color);
+            (squares != EMPTY).then_some(squares)
+        };
+        let mut p = [0usize; MAX_PIECES];
+
+        if !self.material.has_pawns() {
+            let info = &self.infos[self.num_tables * side];
+            let mut i = 0;
+            while i < num {
+                for sq in squares_of(info.pieces[i])? {
+                    *p.get_mut(i)? = sq.to_index();
+                    i += 1;
+                }
+            }
+            return Some((self.num_tables * side, encode_piece(&self.material, info, &mut p[..num])));
+        }
+
+        let mut i = 0;
+        for sq in squares_of(self.infos[0].pieces[0])? {
+            *p.get_mut(i)? = sq.to_index() ^ rank_flip;
+            i += 1;
+        }
+        let pawns = self.material.pawns();
+        if i != pawns[0] {
+            return None;
+        }
+        for j in 1..pawns[0] {
+            if FLAP[p[0]] > FLAP[p[j]] {
+                p.swap(0, j);
+            }
+        }
+        let table = FILE_TO_FILE[p[0] & 7] + self.num_tables * side;
+        let info = &self.infos[table];
+        while i < num {
+            for sq in squares_of(info.pieces[i])? {
+                *p.get_mut(i)? = sq.to_index() ^ rank_flip;
+                i += 1;
+            }
+        }
+        Some((table, encode_pawn(&self.material, info, &mut p[..num])))
+    }
+}
+
+fn encode_piece(material: &Material, info: &EncInfo, p: &mut [usize]) -> u64 {
+    let indices = indices();
+    if p[0] & 0x04 != 0 {
+        p.iter_mut().for_each(|sq| *sq ^= 0x07);
+    }
+    if p[0] & 0x20 != 0 {
+        p.iter_mut().for_each(|sq| *sq ^= 0x38);
+    }
+    let leading = if material.kk_enc() { 2 } else { 3 };
+    if let Some(i) = p.iter().position(|sq| off_diag(*sq) != 0) {
+        if off_diag(p[i]) > 0 && i < leading {
+            p.iter_mut().for_each(|sq| *sq = flip_diag(*sq));
+        }
+    }
+
+    let idx = if material.kk_enc() {
+        indices.kk[TRIANGLE[p[0]]][p[1]]
+    } else {
+        let s1 = (p[1] > p[0]) as usize;
+        let s2 = (p[2] > p[0]) as usize + (p[2] > p[1]) as usize;
+        (if off_diag(p[0]) != 0 {
+            TRIANGLE[p[0]] * 63 * 62 + (p[1] - s1) * 62 + (p[2] - s2)
+        } else if off_diag(p[1]) != 0 {
+            6 * 63 * 62 + DIAG[p[0]] * 28 * 62 + LOWER[p[1]] * 62 + p[2] - s2
+        } else if off_diag(p[2]) != 0 {
+            6 * 63 * 62 + 4 * 28 * 62 + DIAG[p[0]] * 7 * 28 + (DIAG[p[1]] - s1) * 28 + LOWER[p[2]]
+        } else {
+            6 * 63 * 62 + 4 * 28 * 62 + 4 * 7 * 28 + DIAG[p[0]] * 7 * 6 + (DIAG[p[1]] - s1) * 6 + (DIAG[p[2]] - s2)
+        }) as u64
+    };
+    encode_groups(info, p, leading, idx * info.factor[0])
+}
+
+fn encode_pawn(material: &Material, info: &EncInfo, p: &mut [usize]) -> u64 {
+    let indices = indices();
+    if p[0] & 0x04 != 0 {
+        p.iter_mut().for_each(|sq| *sq ^= 0x07);
+    }
+    let pawns = material.pawns();
+    for i in 1..pawns[0] {
+        for j in i + 1..pawns[0] {
+            if PAWN_TWIST[p[i]] < PAWN_TWIST[p[j]] {
+                p.swap(i, j);
+            }
+        }
+    }
+    let mut k = pawns[0];
+    let mut idx = indices.pawn_idx[k - 1][FLAP[p[0]]];
+    for i in 1..k {
+        idx += indices.binomial[k - i][PAWN_TWIST[p[i]]];
+    }
+    idx *= info.factor[0];
+
+    // pawns of the other color can not be on the first and the last rank
+    if pawns[1] > 0 {
+        let end = k + pawns[1];
+        p[k..end].sort_unstable();
+        let mut s = 0;
+        for i in k..end {
+            let skips = p[..k].iter().filter(|sq| p[i] > **sq).count();
+            s += indices.binomial[i - k + 1][p[i] - skips - 8];
+        }
+        idx += s * info.factor[k];
+        k = end;
+    }
+    encode_groups(info, p, k, idx)
+}
+
+// groups of identical pieces after the leading ones, each as a combination of the free squares
+fn encode_groups(info: &EncInfo, p: &mut [usize], mut k: usize, mut idx: u64) -> u64 {
+    let indices = indices();
+    while k < p.len() {
+        let end = k + info.norm[k].max(1);
+        p[k..end].sort_unstable();
+        let mut s = 0;
+        for i in k..end {
+            let skips = p[..k].iter().filter(|sq| p[i] > **sq).count();
+            s += indices.binomial[i - k + 1][p[i] - skips];
+        }
+        idx += s * info.factor[k];
+        k = end;
+    }
+    idx
+}
+
+struct Table {
+    data: Vec<u8>,
+    layout: Layout,
+    pairs: Vec<PairsData>,
+    // dtz tables: flags, offset of the value maps and the start of every map
+    dtz_flags: [u8; 4],
+    dtz_map: usize,
+    dtz_map_idx: [[usize; 4]; 4],
+}
+
+impl Table {
+    fn load(path: &PathBuf, material: &Material, kind: Kind) -> Option<Table> {
+        Self::from_bytes(fs::read(path).ok()?, material, kind)
+    }
+
+    fn from_bytes(data: Vec<u8>, material: &Material, kind: Kind) -> Option<Table> {
+        let magic = if kind == Kind::Wdl { WDL_MAGIC } else { DTZ_MAGIC };
+        if read_u32(&data, 0)? != magic {
+            return None;
+        }
+        let mut pos = 5;
+        let layout = Layout::parse(material, kind, &data, &mut pos)?;
+
+        // pairs data, index tables, size tables and blocks are stored file by file,
+        // with both sides of a split table next to each other
+        let sides = layout.infos.len() / layout.num_tables;
+        let order = (0..layout.num_tables)
+            .flat_map(|table| (0..sides).map(move |side| table + layout.num_tables * side))
+            .collect::<Vec<usize>>();
+        let mut parsed: Vec<Option<(PairsData, [usize; 3])>> = (0..order.len()).map(|_| None).collect();
+        let mut dtz_flags = [0u8; 4];
+        for &index in order.iter() {
+            let (pairs, flags, sizes) = PairsData::parse(&data, &mut pos, layout.sizes[index], kind)?;
+            parsed[index] = Some((pairs, sizes));
+            if kind == Kind::Dtz {
+                dtz_flags[index] = flags;
+            }
+        }
+        let (mut pairs, sizes): (Vec<PairsData>, Vec<[usize; 3]>) = parsed.into_iter().map(Option::unwrap).unzip();
+
+        let mut dtz_map_idx = [[0usize; 4]; 4];
+        let dtz_map = pos;
+        if kind == Kind::Dtz {
+            for (table, map_idx) in dtz_map_idx.iter_mut().enumerate().take(layout.num_tables) {
+                if dtz_flags[table] & 2 == 0 {
+                    continue;
+                }
+                if dtz_flags[table] & 16 == 0 {
+                    for idx in map_idx.iter_mut() {
+                        *idx = pos + 1 - dtz_map;
+                        pos += 1 + *data.get(pos)? as usize;
+                    }
+                } else {
+                    pos += pos & 1;
+                    for idx in map_idx.iter_mut() {
+                        *idx = (pos - dtz_map) / 2 + 1;
+                        pos += 2 + 2 * read_u16(&data, pos)? as usize;
+                    }
+                }
+            }
+            pos += pos & 1;
+        }
+
+        for &index in order.iter() {
+            pairs[index].index_table = pos;
+            pos += sizes[index][0];
+        }
+        for &index in order.iter() {
+            pairs[index].size_table = pos;
+            pos += sizes[index][1];
+        }
+        for &index in order.iter() {
+            pos = (pos + 0x3f) & !0x3f;
+            pairs[index].blocks = pos;
+            pos += sizes[index][2];
+        }
+        if pos > data.len() {
+            return None;
+        }
+        Some(Table { data, layout, pairs, dtz_flags, dtz_map, dtz_map_idx })
+    }
+
+    fn wdl(&self, board: &Board, flip: bool, side: usize) -> Option<i32> {
+        let (table, idx) = self.layout.index(board, flip, side)?;
+        Some(self.pairs[table].decompress(&self.data, idx)? as i32 - 2)
+    }
+
+    // `None` when the table stores the other side to move
+    fn dtz(&self, board: &Board, flip: bool, side: usize, wdl: i32) -> Option<Option<i32>> {
+        let (table, idx) = self.layout.index(board, flip, side)?;
+        let flags = self.dtz_flags[table];
+        if (flags & 1) as usize != side && !self.layout.material.is_symmetric() {
+            return Some(None);
+        }
+        let mut value = self.pairs[table].decompress(&self.data, idx)? as usize;
+        if flags & 2 != 0 {
+            let map = self.dtz_map_idx[table][WDL_TO_MAP[(wdl + 2) as usize]];
+            value = if flags & 16 == 0 {
+                *self.data.get(self.dtz_map + map + value)? as usize
+            } else {
+                read_u16(&self.data, self.dtz_map + 2 * (map + value))? as usize
+            };
+        }
+        let mut value = value as i32;
+        if flags & PA_FLAGS[(wdl + 2) as usize] == 0 || wdl & 1 != 0 {
+            value *= 2;
+        }
+        Some(Some(value))
+    }
+}
+
+struct Entry {
+    material: Material,
+    wdl_path: Option<PathBuf>,
+    dtz_path: Option<PathBuf>,
+    wdl: OnceLock<Option<Table>>,
+    dtz: OnceLock<Option<Table>>,
+}
+
+impl Entry {
+    fn wdl(&self) -> Option<&Table> {
+        let path = self.wdl_path.as_ref()?;
+        self.wdl.get_or_init(|| Table::load(path, &self.material, Kind::Wdl)).as_ref()
+    }
+
+    fn dtz(&self) -> Option<&Table> {
+        let path = self.dtz_path.as_ref()?;
+        self.dtz.get_or_init(|| Table::load(path, &self.material, Kind::Dtz)).as_ref()
+    }
+}
+
+/// Result of the root probe: the move which keeps the best tablebase result.
+pub struct RootProbe {
+    pub mv: ChessMove,
+    pub wdl: Wdl,
+}
+
+pub struct Tablebase {
+    entries: HashMap<String, Entry>,
+    max_pieces: usize,
+}
+
+fn is_capture(board: &Board, mv: &ChessMove) -> bool {
+    board.piece_on(mv.get_dest()).is_some() || is_en_passant(board, mv)
+}
+
+fn is_en_passant(board: &Board, mv: &ChessMove) -> bool {
+    board.piece_on(mv.get_source()) == Some(Piece::Pawn)
+        && mv.get_source().get_file() != mv.get_dest().get_file()
+        && board.piece_on(mv.get_dest()).is_none()
+}
+
+fn is_zeroing(board: &Board, mv: &ChessMove) -> bool {
+    board.piece_on(mv.get_source()) == Some(Piece::Pawn) || is_capture(board, mv)
+}
+
+impl Tablebase {
+    /// Finds the tables in the directories of `paths`, separated like the `PATH` variable.
+    pub fn open(paths: &str) -> Result<Tablebase, String> {
+        let mut entries: HashMap<String, Entry> = HashMap::new();
+        for dir in std::env::split_paths(paths) {
+            let files = fs::read_dir(&dir).map_err(|err| "Cannot read |".to_string() + paths + "|: " + &*err.to_string())?;
+            for file in files.flatten() {
+                let path = file.path();
+                let (Some(stem), Some(extension)) = (path.file_stem(), path.extension()) else {
+                    continue;
+                };
+                let Some(material) = stem.to_str().and_then(Material::parse) else {
+                    continue;
+                };
+                let entry = entries.entry(material.name()).or_insert_with(|| Entry {
+                    material,
+                    wdl_path: None,
+                    dtz_path: None,
+                    wdl: OnceLock::new(),
+                    dtz: OnceLock::new(),
+                });
+                match extension.to_str() {
+                    Some(WDL_SUFFIX) => entry.wdl_path = Some(path),
+                    Some(DTZ_SUFFIX) => entry.dtz_path = Some(path),
+                    _ => {}
+                }
+            }
+        }
+        entries.retain(|_, entry| entry.wdl_path.is_some());
+        let max_pieces = entries.values().map(|entry| entry.material.num()).max().unwrap_or(0);
+        if entries.is_empty() {
+            return Err("No Syzygy tables in |".to_string() + paths + "|");
+        }
+        Ok(Tablebase { entries, max_pieces })
+    }
+
+    /// Largest number of pieces, kings included, of the found tables.
+    pub fn max_pieces(&self) -> usize {
+        self.max_pieces
+    }
+
+    fn can_probe(&self, board: &Board) -> bool {
+        board.combined().popcnt() as usize <= self.max_pieces
+            && board.castle_rights(Color::White) == CastleRights::NoRights
+            && board.castle_rights(Color::Black) == CastleRights::NoRights
+    }
+
+    /// Win, draw or loss of the side to move, `None` without the table.
+    pub fn probe_wdl(&self, board: &Board) -> Option<Wdl> {
+        if !self.can_probe(board) {
+            return None;
+        }
+        self.wdl(board).map(Wdl::from_value)
+    }
+
+    /// Best move of the root keeping the tablebase result: the fastest zeroing when winning,
+    /// the longest resistance when losing. Only moves accepted by `allowed` are considered.
+    pub fn probe_root(&self, board: &Board, allowed: impl Fn(&ChessMove) -> bool) -> Option<RootProbe> {
+        if !self.can_probe(board) {
+            return None;
+        }
+        let mut best: Option<(ChessMove, i32)> = None;
+        for mv in MoveGen::new_legal(board).filter(|mv| allowed(mv)) {
+            let child = board.make_move_new(mv);
+            let dtz = if child.status() == BoardStatus::Checkmate {
+                1
+            } else if is_zeroing(board, &mv) {
+                WDL_TO_DTZ[(2 - self.wdl(&child)?) as usize]
+            } else {
+                let dtz = -self.dtz(&child)?;
+                dtz + dtz.signum()
+            };
+            if best.is_none_or(|(_, best_dtz)| Self::root_rank(dtz) > Self::root_rank(best_dtz)) {
+                best = Some((mv, dtz));
+            }
+        }
+        best.map(|(mv, dtz)| RootProbe { mv, wdl: Wdl::from_dtz(dtz) })
+    }
+
+    // wins before losses, then the shortest win or the longest loss
+    fn root_rank(dtz: i32) -> (i32, i32) {
+        match dtz {
+            dtz if dtz > 0 => (2, -dtz),
+            0 => (1, 0),
+            dtz => (0, -dtz),
+        }
+    }
+
+    // table entry of the material and whether the colors of the table are swapped
+    fn entry(&self, board: &Board) -> Option<(&Entry, bool)> {
+        let material = Material::of_board(board);
+        if material.is_symmetric() {
+            let entry = self.entries.get(&material.name())?;
+            return Some((entry, board.side_to_move() == Color::Black));
+        }
+        match self.entries.get(&material.name()) {
+            Some(entry) => Some((entry, false)),
+            None => Some((self.entries.get(&material.mirrored_name())?, true)),
+        }
+    }
+
+    // sub-table side to move of the probed position
+    fn side(entry: &Entry, board: &Board, flip: bool) -> usize {
+        if entry.material.is_symmetric() {
+            0
+        } else {
+            ((board.side_to_move() == Color::White) == flip) as usize
+        }
+    }
+
+    fn wdl_table(&self, board: &Board) -> Option<i32> {
+        if board.combined().popcnt() == 2 {
+            return Some(0);
+        }
+        let (entry, flip) = self.entry(board)?;
+        entry.wdl()?.wdl(board, flip, Self::side(entry, board, flip))
+    }
+
+    fn dtz_table(&self, board: &Board, wdl: i32) -> Option<Option<i32>> {
+        let (entry, flip) = self.entry(board)?;
+        entry.dtz()?.dtz(board, flip, Self::side(entry, board, flip), wdl)
+    }
+
+    // captures are resolved first since the tables may store anything for positions with
+    // a winning capture, returns the value and whether it comes from a capture
+    fn probe_ab(&self, board: &Board, mut alpha: i32, beta: i32) -> Option<(i32, bool)> {
+        let mut captures = MoveGen::new_legal(board);
+        captures.set_iterator_mask(*board.color_combined(!board.side_to_move()));
+        for mv in captures {
+            let (value, _) = self.probe_ab(&board.make_move_new(mv), -beta, -alpha)?;
+            let value = -value;
+            if value > alpha {
+                if value >= beta {
+                    return Some((value, true));
+                }
+                alpha = value;
+            }
+        }
+        let value = self.wdl_table(board)?;
+        if alpha >= value {
+            Some((alpha, alpha > 0))
+        } else {
+            Some((value, false))
+        }
+    }
+
+    // best value of the en passant captures, if any
+    fn en_passant_value(&self, board: &Board) -> Option<Option<i32>> {
+        if board.en_passant().is_none() {
+            return Some(None);
+        }
+        let mut best = None;
+        for mv in MoveGen::new_legal(board).filter(|mv| is_en_passant(board, mv)) {
+            let value = -self.probe_ab(&board.make_move_new(mv), -2, 2)?.0;
+            best = Some(best.map_or(value, |best: i32| best.max(value)));
+        }
+        Some(best)
+    }
+
+    fn only_en_passant_moves(board: &Board) -> bool {
+        MoveGen::new_legal(board).all(|mv| is_en_passant(board, &mv))
+    }
+
+    fn wdl(&self, board: &Board) -> Option<i32> {
+        let (mut value, _) = self.probe_ab(board, -2, 2)?;
+        if let Some(en_passant) = self.en_passant_value(board)? {
+            if en_passant >= value || (value == 0 && Self::only_en_passant_moves(board)) {
+                value = en_passant;
+            }
+        }
+        Some(value)
+    }
+
+    // distance to the next zeroing move of the winning side, negative when the side to move
+    // loses, with 100 added when the result is spoiled by the 50 move rule
+    fn dtz(&self, board: &Board) -> Option<i32> {
+        let mut value = self.dtz_no_en_passant(board)?;
+        if let Some(en_passant) = self.en_passant_value(board)? {
+            let en_passant = WDL_TO_DTZ[(en_passant + 2) as usize];
+            let better = if value < -100 {
+                en_passant >= 0
+            } else if value < 0 {
+                !(-100..0).contains(&en_passant)
+            } else if value > 100 {
+                en_passant > 0
+            } else if value > 0 {
+                en_passant == 1
+            } else {
+                en_passant >= 0 || Self::only_en_passant_moves(board)
+            };
+            if better {
+                value = en_passant;
+            }
+        }
+        Some(value)
+    }
+
+    fn dtz_no_en_passant(&self, board: &Board) -> Option<i32> {
+        let (wdl, from_capture) = self.probe_ab(board, -2, 2)?;
+        if wdl == 0 {
+            return Some(0);
+        }
+        if from_capture {
+            return Some(if wdl == 2 { 1 } else { 101 });
+        }
+        let moves = MoveGen::new_legal(board).collect::<Vec<ChessMove>>();
+        let is_pawn_push = |mv: &ChessMove| board.piece_on(mv.get_source()) == Some(Piece::Pawn) && !is_capture(board, mv);
+        if wdl > 0 {
+            for mv in moves.iter().filter(|mv| is_pawn_push(mv)) {
+                if -self.wdl(&board.make_move_new(*mv))? == wdl {
+                    return Some(if wdl == 2 { 1 } else { 101 });
+                }
+            }
+        }
+
+        if let Some(dtz) = self.dtz_table(board, wdl)? {
+            let dtz = 1 + dtz + if wdl & 1 != 0 { 100 } else { 0 };
+            return Some(if wdl >= 0 { dtz } else { -dtz });
+        }
+
+        // the table stores the other side to move, one more ply is searched
+        if wdl > 0 {
+            let mut best = 0xffff;
+            for mv in moves.iter().filter(|mv| !is_zeroing(board, mv)) {
+                let value = -self.dtz(&board.make_move_new(*mv))?;
+                if value > 0 && value + 1 < best {
+                    best = value + 1;
+                }
+            }
+            Some(best)
+        } else {
+            let mut best = -1;
+            for mv in moves.iter() {
+                let child = board.make_move_new(*mv);
+                let value = if is_zeroing(board, mv) {
+                    if wdl == -2 {
+                        -1
+                    } else if self.probe_ab(&child, 1, 2)?.0 == 2 {
+                        0
+                    } else {
+                        -101
+                    }
+                } else {
+                    -self.dtz(&child)? - 1
+                };
+                best = best.min(value);
+            }
+            Some(best)
+        }
+    }
+}
+
+/// Writes a table in the Syzygy format with one byte symbols, `value` gives the stored value
+/// of a position with the colors of the table name and `None` for positions left out.
+#[cfg(test)]
+fn write_test_table(dir: &std::path::Path, name: &str, kind: Kind, value: &dyn Fn(&Board) -> Option<u8>) {
+    use chess::{get_rank, BoardBuilder, Rank, ALL_SQUARES};
+
+    const BLOCK_SIZE: u32 = 10;
+    const IDX_BITS: u32 = 10;
+    let material = Material::parse(name).unwrap();
+    let num = material.num();
+    let pawns = material.pawns();
+    let more_pawns = material.has_pawns() && pawns[1] > 0;
+
+    // the leading pawns, or the unique pieces, come first and identical pieces are consecutive
+    let mut codes = Vec::new();
+    for (color, counts) in [(0u8, &material.white), (8u8, &material.black)] {
+        for (i, count) in counts.iter().enumerate() {
+            let code = color | [6, 5, 4, 3, 2, 1][i];
+            codes.extend(std::iter::repeat_n(code, *count as usize));
+        }
+    }
+    let leading = |code: &u8| {
+        if material.has_pawns() {
+            let leading_color = if material.white[5] as usize == pawns[0] { 0 } else { 8 };
+            *code == leading_color | 1
+        } else {
+            let counts = if code & 8 == 0 { &material.white } else { &material.black };
+            counts[6 - (code & 7) as usize] == 1
+        }
+    };
+    codes.sort_by_key(|code| !leading(code));
+    if more_pawns {
+        let other = codes.iter().position(|code| code & 7 == 1 && !leading(code)).unwrap();
+        let code = codes.remove(other);
+        codes.insert(pawns[0], code);
+    }
+
+    let mut data = match kind {
+        Kind::Wdl => WDL_MAGIC,
+        Kind::Dtz => DTZ_MAGIC,
+    }
+    .to_le_bytes()
+    .to_vec();
+    let split = kind == Kind::Wdl;
+    data.push(split as u8 | if material.has_pawns() { 2 } else { 0 });
+    let num_tables = if material.has_pawns() { 4 } else { 1 };
+    for _ in 0..num_tables {
+        data.push(0);
+        if more_pawns {
+            data.push(0x11);
+        }
+        data.extend(codes.iter().map(|code| code | (code << 4)));
+    }
+    if data.len() & 1 != 0 {
+        data.push(0);
+    }
+    let mut pos = 5;
+    let layout = Layout::parse(&material, kind, &data, &mut pos).unwrap();
+    assert_eq!(pos, data.len());
+
+    let mut values: Vec<Vec<u8>> = layout.sizes.iter().map(|size| vec![0; *size as usize]).collect();
+    let mut squares = vec![0usize; num];
+    let total = 64usize.pow(num as u32);
+    for placement in 0..total {
+        let mut rest = placement;
+        for sq in squares.iter_mut() {
+            *sq = rest % 64;
+            rest /= 64;
+        }
+        for side_to_move in [Color::White, Color::Black] {
+            let mut builder = BoardBuilder::new();
+            let mut occupied = EMPTY;
+            for (sq, code) in squares.iter().zip(codes.iter()) {
+                let square = ALL_SQUARES[*sq];
+                let piece = [Piece::Pawn, Piece::Knight, Piece::Bishop, Piece::Rook, Piece::Queen, Piece::King]
+                    [(code & 7) as usize - 1];
+                let color = if code & 8 == 0 { Color::White } else { Color::Black };
+                builder.piece(square, piece, color);
+                occupied |= chess::BitBoard::from_square(square);
+            }
+            if occupied.popcnt() as usize != num {
+                break;
+            }
+            builder.side_to_move(side_to_move);
+            let Ok(board) = Board::try_from(&builder) else {
+                continue;
+            };
+            let side = (side_to_move == Color::Black) as usize;
+            let back_ranks = get_rank(Rank::First) | get_rank(Rank::Eighth);
+            if (kind == Kind::Dtz && side == 1) || board.pieces(Piece::Pawn) & back_ranks != EMPTY {
+                continue;
+            }
+            if let Some(stored) = value(&board) {
+                let (table, idx) = layout.index(&board, false, side).unwrap();
+                values[table][idx as usize] = stored;
+            }
+        }
+    }
+
+    // header of every sub-table: 8 bit codes of 256 leaf symbols, one value each
+    let order = (0..num_tables)
+        .flat_map(|table| (0..values.len() / num_tables).map(move |side| table + num_tables * side))
+        .collect::<Vec<usize>>();
+    let per_block = 1usize << BLOCK_SIZE;
+    for &index in order.iter() {
+        let blocks = values[index].len().div_ceil(per_block);
+        data.extend([0, BLOCK_SIZE as u8, IDX_BITS as u8, 0]);
+        data.extend((blocks as u32).to_le_bytes());
+        data.extend([8, 8]);
+        data.extend(0u16.to_le_bytes());
+        data.extend(256u16.to_le_bytes());
+        for symbol in 0..=255u8 {
+            data.extend([symbol, 0xf0, 0xff]);
+        }
+    }
+    for &index in order.iter() {
+        let num_indices = values[index].len().div_ceil(1 << IDX_BITS);
+        for main_idx in 0..num_indices {
+            data.extend((main_idx as u32).to_le_bytes());
+            data.extend((1u16 << (IDX_BITS - 1)).to_le_bytes());
+        }
+    }
+    for &index in order.iter() {
+        for block in values[index].chunks(per_block) {
+            data.extend(((block.len() - 1) as u16).to_le_bytes());
+        }
+    }
+    for &index in order.iter() {
+        data.resize((data.len() + 0x3f) & !0x3f, 0);
+        for block in values[index].chunks(per_block) {
+            let mut bytes = block.to_vec();
+            bytes.resize(per_block, 0);
+            data.extend(bytes);
+        }
+    }
+    let suffix = match kind {
+        Kind::Wdl => WDL_SUFFIX,
+        Kind::Dtz => DTZ_SUFFIX,
+    };
+    fs::write(dir.join(name.to_string() + "." + suffix), data).unwrap();
+}
+
+#[cfg(test)]
+// white to move always wins, black draws by taking the rook or by stalemate
+fn krk_wdl(board: &Board) -> Option<u8> {
+    if board.side_to_move() == Color::White {
+        return Some(4);
+    }
+    let rook = board.pieces(Piece::Rook).to_square();
+    let can_take = MoveGen::new_legal(board).any(|mv| mv.get_dest() == rook);
+    Some(if can_take || board.status() == BoardStatus::Stalemate { 2 } else { 0 })
+}
+
+/// Plies to mate of the KRvK positions with the white rook, solved by retrograde analysis
+/// without the tables. Draws and illegal positions are `None`.
+#[cfg(test)]
+pub(crate) struct KrkDistances {
+    plies: Vec<Option<u8>>,
+}
+
+#[cfg(test)]
+impl KrkDistances {
+    fn position_index(white_king: usize, rook: usize, black_king: usize, side: usize) -> usize {
+        ((side * 64 + white_king) * 64 + rook) * 64 + black_king
+    }
+
+    fn board_index(board: &Board) -> usize {
+        let white = board.color_combined(Color::White);
+        Self::position_index(
+            board.king_square(Color::White).to_index(),
+            (board.pieces(Piece::Rook) & white).to_square().to_index(),
+            board.king_square(Color::Black).to_index(),
+            (board.side_to_move() == Color::Black) as usize,
+        )
+    }
+
+    fn board(white_king: usize, rook: usize, black_king: usize, side: usize) -> Option<Board> {
+        use chess::{BoardBuilder, ALL_SQUARES};
+        if white_king == rook || white_king == black_king || rook == black_king {
+            return None;
+        }
+        let mut builder = BoardBuilder::new();
+        builder
+            .piece(ALL_SQUARES[white_king], Piece::King, Color::White)
+            .piece(ALL_SQUARES[rook], Piece::Rook, Color::White)
+            .piece(ALL_SQUARES[black_king], Piece::King, Color::Black)
+            .side_to_move(if side == 0 { Color::White } else { Color::Black });
+        Board::try_from(&builder).ok()
+    }
+
+    /// Every legal position of the table.
+    pub(crate) fn boards() -> impl Iterator<Item = Board> {
+        (0..2 * 64 * 64 * 64).filter_map(|i| Self::board(i / 4096 % 64, i / 64 % 64, i % 64, i / (64 * 64 * 64)))
+    }
+
+    /// Distances solved on the first call.
+    pub(crate) fn get() -> &'static KrkDistances {
+        static DISTANCES: OnceLock<KrkDistances> = OnceLock::new();
+        DISTANCES.get_or_init(Self::solve)
+    }
+
+    fn solve() -> KrkDistances {
+        let size = 2 * 64 * 64 * 64;
+        let mut plies: Vec<Option<u8>> = vec![None; size];
+        // positions after every move, `None` when black takes the rook
+        let mut children: Vec<Vec<Option<usize>>> = vec![Vec::new(); size];
+        for board in Self::boards() {
+            let index = Self::board_index(&board);
+            if board.status() == BoardStatus::Checkmate {
+                plies[index] = Some(0);
+            }
+            children[index] = MoveGen::new_legal(&board)
+                .map(|mv| board.make_move_new(mv))
+                .map(|child| (child.pieces(Piece::Rook) != &EMPTY).then(|| Self::board_index(&child)))
+                .collect();
+        }
+        let mut ply: u8 = 1;
+        loop {
+            let mut changed = false;
+            let side = (ply as usize + 1) % 2;
+            for index in side * size / 2..(side + 1) * size / 2 {
+                if plies[index].is_some() || children[index].is_empty() {
+                    continue;
+                }
+                let solved = if side == 0 {
+                    // white mates as soon as one move reaches a lost position
+                    children[index].iter().any(|child| child.is_some_and(|child| plies[child] == Some(ply - 1)))
+                } else {
+                    // black is lost when every move reaches a won position, the longest one counts
+                    children[index].iter().all(|child| child.is_some_and(|child| plies[child].is_some()))
+                        && children[index].iter().any(|child| plies[child.unwrap()] == Some(ply - 1))
+                };
+                if solved {
+                    plies[index] = Some(ply);
+                    changed = true;
+                }
+            }
+            if !changed && ply > 2 {
+                break;
+            }
+            ply += 1;
+        }
+        KrkDistances { plies }
+    }
+
+    pub(crate) fn plies(&self, board: &Board) -> Option<u8> {
+        self.plies[Self::board_index(board)]
+    }
+
+    pub(crate) fn max_plies(&self) -> u8 {
+        self.plies.iter().flatten().copied().max().unwrap_or(0)
+    }
+}
+
+/// Writes KRvK tables with the right results and distances to mate for the side to move.
+#[cfg(test)]
+pub(crate) fn write_krk_fixture(dir: &std::path::Path) {
+    let distances = KrkDistances::get();
+    write_test_table(dir, "KRvK", Kind::Wdl, &krk_wdl);
+    // white wins in an odd number of plies, stored as in the Syzygy tables in full moves
+    write_test_table(dir, "KRvK", Kind::Dtz, &|board| Some(distances.plies(board).map_or(0, |plies| (plies - 1) / 2)));
+}
+
+#[cfg(test)]
+mod syzygy_tests {
+    use super::*;
+    use crate::features::endgame::kpk_is_win;
+    use std::str::FromStr;
+
+    fn board(fen: &str) -> Board {
+        Board::from_str(fen).unwrap()
+    }
+
+    fn fixture_dir(name: &str) -> PathBuf {
+        let dir = std::env::temp_dir().join(format!("rdzawa_syzygy_{}_{}", name, std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        dir
+    }
+
+    fn kpk_wdl(board: &Board) -> Option<u8> {
+        let pawn = board.pieces(Piece::Pawn).to_square();
+        let win = kpk_is_win(
+            board.king_square(Color::White),
+            pawn,
+            board.king_square(Color::Black),
+            board.side_to_move() == Color::White,
+            Color::White,
+        );
+        Some(match (win, board.side_to_move()) {
+            (false, _) => 2,
+            (true, Color::White) => 4,
+            (true, Color::Black) => 0,
+        })
+    }
+
+    #[test]
+    fn index_tables() {
+        let indices = indices();
+        assert_eq!(indices.kk.iter().flatten().filter(|idx| **idx != u64::MAX).count() as u64, KK_SIZE);
+        assert_eq!(indices.pawn_factor[0], [6, 6, 6, 6]);
+        assert_eq!(indices.binomial[2][5], 10);
+        assert_eq!(6 * 63 * 62 + 4 * 28 * 62 + 4 * 7 * 28 + 4 * 7 * 6, TRIPLE_SIZE);
+    }
+
+    #[test]
+    fn parses_table_names() {
+        let material = Material::parse("KRPvKR").unwrap();
+        assert_eq!(material.num(), 5);
+        assert!(material.has_pawns());
+        assert_eq!(material.pawns(), [1, 0]);
+        assert_eq!(Material::of_board(&board("8/8/8/8/2k5/8/1P6/KR6 w - - 0 1")).name(), "KRPvK");
+        assert_eq!(Material::of_board(&board("8/8/8/8/2k5/8/1P6/KR6 w - - 0 1")).mirrored_name(), "KvKRP");
+        assert!(Material::parse("KRvR").is_none());
+        assert!(Material::parse("KQQQQvKRR").is_none());
+    }
+
+    #[test]
+    fn probes_wdl_of_piece_table() {
+        let dir = fixture_dir("wdl");
+        write_krk_fixture(&dir);
+        let tablebase = Tablebase::open(dir.to_str().unwrap()).unwrap();
+        assert_eq!(tablebase.max_pieces(), 3);
+        assert_eq!(tablebase.probe_wdl(&board("8/8/8/4k3/8/8/8/R3K3 w - - 0 1")), Some(Wdl::Win));
+        assert_eq!(tablebase.probe_wdl(&board("8/8/8/4k3/8/8/8/R3K3 b - - 0 1")), Some(Wdl::Loss));
+        // the rook can be taken
+        assert_eq!(tablebase.probe_wdl(&board("8/8/8/8/8/8/1k6/R6K b - - 0 1")), Some(Wdl::Draw));
+        // colors of the table swapped
+        assert_eq!(tablebase.probe_wdl(&board("r3k3/8/8/8/4K3/8/8/8 b - - 0 1")), Some(Wdl::Win));
+        assert_eq!(tablebase.probe_wdl(&board("r3k3/8/8/8/4K3/8/8/8 w - - 0 1")), Some(Wdl::Loss));
+        // no table for the material, castling is not in the tables
+        assert_eq!(tablebase.probe_wdl(&board("8/8/8/3k4/8/8/8/Q3K3 w - - 0 1")), None);
+        assert_eq!(tablebase.probe_wdl(&board("4k3/8/8/8/8/8/8/R3K3 w Q - 0 1")), None);
+        fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn probes_wdl_of_pawn_table() {
+        let dir = fixture_dir("pawn");
+        write_test_table(&dir, "KPvK", Kind::Wdl, &kpk_wdl);
+        let tablebase = Tablebase::open(dir.to_str().unwrap()).unwrap();
+        for fen in [
+            "8/8/8/8/8/8/4P3/k3K3 w - - 0 1",
+            "k7/8/8/P7/8/8/8/K7 w - - 0 1",
+            "8/8/8/8/8/3k4/4P3/K7 b - - 0 1",
+            "8/8/4k3/8/4K3/4P3/8/8 b - - 0 1",
+            "8/8/4k3/8/4K3/4P3/8/8 w - - 0 1",
+            "8/6k1/8/8/8/8/1P6/7K w - - 0 1",
+            "8/8/8/8/8/8/4p3/K3k3 b - - 0 1",
+            "8/1p6/8/8/8/6K1/8/7k b - - 0 1",
+        ] {
+            let board = board(fen);
+            let strong = if board.pieces(Piece::Pawn) & board.color_combined(Color::White) != EMPTY {
+                Color::White
+            } else {
+                Color::Black
+            };
+            let pawn = board.pieces(Piece::Pawn).to_square();
+            let win =
+                kpk_is_win(board.king_square(strong), pawn, board.king_square(!strong), board.side_to_move() == strong, strong);
+            let expected = match (win, board.side_to_move() == strong) {
+                (false, _) => Wdl::Draw,
+                (true, true) => Wdl::Win,
+                (true, false) => Wdl::Loss,
+            };
+            assert_eq!(tablebase.probe_wdl(&board), Some(expected), "{}", fen);
+        }
+        fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn root_probe_keeps_the_result() {
+        let dir = fixture_dir("root");
+        write_krk_fixture(&dir);
+        let tablebase = Tablebase::open(dir.to_str().unwrap()).unwrap();
+        let mate = board("7k/8/6K1/8/8/8/8/R7 w - - 0 1");
+        let root = tablebase.probe_root(&mate, |_| true).unwrap();
+        assert_eq!(root.mv.to_string(), "a1a8");
+        assert_eq!(root.wdl, Wdl::Win);
+        assert_eq!(tablebase.dtz(&mate), Some(1));
+        let without_mate = tablebase.probe_root(&mate, |mv| mv.to_string() != "a1a8").unwrap();
+        assert_eq!(without_mate.wdl, Wdl::Win);
+
+        let defence = board("8/8/8/8/8/8/1k6/R6K b - - 0 1");
+        let root = tablebase.probe_root(&defence, |_| true).unwrap();
+        assert_eq!((root.mv.to_string().as_str(), root.wdl), ("b2a1", Wdl::Draw));
+        fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn solves_rook_endings() {
+        let distances = KrkDistances::get();
+        // the longest win of KRvK is a mate in 16 after a move of black
+        assert_eq!(distances.max_plies(), 32);
+        assert_eq!(distances.plies(&board("7k/8/6K1/8/8/8/8/R7 w - - 0 1")), Some(1));
+        assert_eq!(distances.plies(&board("8/8/8/8/8/8/1k6/R6K b - - 0 1")), None);
+    }
+
+    #[test]
+    fn probes_every_rook_ending() {
+        let dir = fixture_dir("every_rook");
+        write_krk_fixture(&dir);
+        let tablebase = Tablebase::open(dir.to_str().unwrap()).unwrap();
+        let distances = KrkDistances::get();
+        // every entry of the table has the white king in the a1-d1-d4 triangle
+        let triangle = |square: chess::Square| {
+            square.get_rank().to_index() <= square.get_file().to_index() && square.get_file().to_index() < 4
+        };
+        for board in KrkDistances::boards().filter(|board| triangle(board.king_square(Color::White))) {
+            let plies = distances.plies(&board);
+            let wdl = match (board.side_to_move(), plies) {
+                (Color::White, Some(_)) => Wdl::Win,
+                (Color::Black, Some(_)) => Wdl::Loss,
+                (_, None) => Wdl::Draw,
+            };
+            assert_eq!(tablebase.probe_wdl(&board), Some(wdl), "{}", board);
+            // the distances with black to move come from a search over these
+            if board.side_to_move() == Color::White {
+                assert_eq!(tablebase.dtz(&board), plies.map(|plies| plies as i32), "{}", board);
+            }
+        }
+        let mated = board("8/8/8/8/8/R7/8/k1K5 b - - 0 1");
+        assert_eq!(tablebase.dtz(&mated), Some(-1));
+        let longest = KrkDistances::boards().find(|board| distances.plies(board) == Some(32)).unwrap();
+        assert_eq!(tablebase.dtz(&longest), Some(-32), "{}", longest);
+        fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn probes_real_tables() {
+        let dir = std::path::Path::new(concat!(env!("CARGO_MANIFEST_DIR"), "/resources/syzygy"));
+        let missing: Vec<&str> = ["KRvK.rtbw", "KRvK.rtbz", "KPvK.rtbw", "KPvK.rtbz"]
+            .into_iter()
+            .filter(|name| !dir.join(name).exists())
+            .collect();
+        if !missing.is_empty() {
+            eprintln!("Skipped the real tables, missing {} in {}", missing.join(" "), dir.display());
+            return;
+        }
+        let tablebase = Tablebase::open(dir.to_str().unwrap()).unwrap();
+        for (fen, wdl, dtz) in [
+            ("7k/8/6K1/8/8/8/8/R7 w - - 0 1", Wdl::Win, Some(1)),
+            ("8/8/8/8/8/8/1k6/R6K b - - 0 1", Wdl::Draw, Some(0)),
+            ("8/8/8/8/8/4k3/4P3/4K3 w - - 0 1", Wdl::Draw, Some(0)),
+            ("8/8/8/8/8/4k3/4P3/4K3 b - - 0 1", Wdl::Draw, Some(0)),
+            ("4k3/8/4K3/4P3/8/8/8/8 w - - 0 1", Wdl::Win, None),
+            ("4k3/8/4K3/4P3/8/8/8/8 b - - 0 1", Wdl::Loss, None),
+            // the pawn move resets the counter
+            ("8/8/8/8/8/8/4P3/k3K3 w - - 0 1", Wdl::Win, Some(1)),
+        ] {
+            let board = board(fen);
+            assert_eq!(tablebase.probe_wdl(&board), Some(wdl), "{}", fen);
+            if dtz.is_some() {
+                assert_eq!(tablebase.dtz(&board), dtz, "{}", fen);
+            }
+        }
+        // every rook ending against the solved distances
+        let distances = KrkDistances::get();
+        for board in KrkDistances::boards() {
+            let win = distances.plies(&board).is_some();
+            let wdl = match (board.side_to_move(), win) {
+                (_, false) => Wdl::Draw,
+                (Color::White, true) => Wdl::Win,
+                (Color::Black, true) => Wdl::Loss,
+            };
+            assert_eq!(tablebase.probe_wdl(&board), Some(wdl), "{}", board);
+        }
+    }
+
+    #[test]
+    fn rejects_corrupted_tables() {
+        let material = Material::parse("KRvK").unwrap();
+        assert!(Table::from_bytes(vec![0; 64], &material, Kind::Wdl).is_none());
+        let dir = fixture_dir("corrupted");
+        write_krk_fixture(&dir);
+        let mut data = fs::read(dir.join("KRvK.rtbw")).unwrap();
+        data.truncate(data.len() / 2);
+        assert!(Table::from_bytes(data, &material, Kind::Wdl).is_none());
+        assert!(Table::load(&dir.join("KRvK.rtbz"), &material, Kind::Dtz).is_some());
+        assert!(Table::load(&dir.join("KRvK.rtbz"), &material, Kind::Wdl).is_none());
+        fs::remove_dir_all(dir).unwrap();
+    }
+}
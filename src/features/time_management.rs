@@ -1,3 +1,251 @@
-pub fn default_time_manager(time: u64) ->u64 {
-    (time as f64 / 25.0).floor() as u64
-}
\ No newline at end of file
+use chess::ChessMove;
+
+pub const DEFAULT_MOVE_OVERHEAD: u64 = 30;
+const DEFAULT_MOVES_TO_GO: u64 = 25;
+const HARD_LIMIT_FACTOR: u64 = 4;
+// percent of the remaining clock which can be used on a single move
+const MAX_CLOCK_USAGE: u64 = 80;
+
+// iterations with the same best move after which the search can stop early
+const STABLE_ITERATIONS: u32 = 4;
+const STABLE_FACTOR: f64 = 0.5;
+const BEST_MOVE_CHANGE_FACTOR: f64 = 0.4;
+const SCORE_DROP_MARGIN: i32 = 30;
+const SCORE_DROP_SCALE: i32 = 300;
+const MAX_EXTENSION: f64 = 2.5;
+const DEFAULT_BRANCHING_FACTOR: f64 = 3.0;
+const MAX_BRANCHING_FACTOR: f64 = 8.0;
+
+/// Clock state of the side to move as sent in `go`, all values in milliseconds.
+#[derive(Clone, Default)]
+pub struct Clock {
+    pub time: Option<u64>,
+    pub increment: u64,
+    pub moves_to_go: Option<u64>,
+    pub move_time: Option<u64>,
+}
+
+/// `soft` is the time after which no new iteration should be started,
+/// `hard` is the time after which the search is aborted.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct TimeLimits {
+    pub soft: u64,
+    pub hard: u64,
+}
+
+impl Clock {
+    pub fn is_set(&self) -> bool {
+        self.time.is_some() || self.move_time.is_some()
+    }
+}
+
+pub fn compute_limits(clock: &Clock, move_overhead: u64) -> Option<TimeLimits> {
+    if let Some(move_time) = clock.move_time {
+        let time = move_time.saturating_sub(move_overhead);
+        return Some(TimeLimits { soft: time, hard: time });
+    }
+
+    let time = clock.time?;
+    let usable = time.saturating_sub(move_overhead);
+    let moves_to_go = clock.moves_to_go.unwrap_or(DEFAULT_MOVES_TO_GO).max(1);
+
+    let hard_cap = if moves_to_go == 1 {
+        usable
+    } else {
+        usable * MAX_CLOCK_USAGE / 100
+    };
+    let soft = (usable / moves_to_go + clock.increment * 3 / 4).min(hard_cap);
+    let hard = (soft * HARD_LIMIT_FACTOR).min(hard_cap);
+    Some(TimeLimits { soft, hard })
+}
+
+/// Decides between iterations of the iterative deepening whether to search deeper,
+/// stretching the soft limit when the best move is unstable or the score drops.
+pub struct TimeManager {
+    limits: TimeLimits,
+    root_moves: usize,
+    best_move: Option<ChessMove>,
+    stable_iterations: u32,
+    best_move_changes: f64,
+    score: Option<i32>,
+    score_drop: i32,
+    iteration_time: Option<u64>,
+    branching_factor: f64,
+}
+
+impl TimeManager {
+    pub fn new(limits: TimeLimits, root_moves: usize) -> Self {
+        TimeManager {
+            limits,
+            root_moves,
+            best_move: None,
+            stable_iterations: 0,
+            best_move_changes: 0.0,
+            score: None,
+            score_drop: 0,
+            iteration_time: None,
+            branching_factor: DEFAULT_BRANCHING_FACTOR,
+        }
+    }
+
+    /// Records the result of a finished iteration which took `iteration_time` ms.
+    pub fn update(&mut self, best_move: ChessMove, score: i32, iteration_time: u64) {
+        self.best_move_changes /= 2.0;
+        if self.best_move == Some(best_move) {
+            self.stable_iterations += 1;
+        } else {
+            if self.best_move.is_some() {
+                self.best_move_changes += 1.0;
+            }
+            self.stable_iterations = 0;
+        }
+        self.best_move = Some(best_move);
+
+        self.score_drop = self.score.map(|previous| previous - score).unwrap_or(0);
+        self.score = Some(score);
+
+        if let Some(previous_time) = self.iteration_time.filter(|time| *time > 0) {
+            self.branching_factor =
+                (iteration_time as f64 / previous_time as f64).clamp(1.0, MAX_BRANCHING_FACTOR);
+        }
+        self.iteration_time = Some(iteration_time);
+    }
+
+    /// Soft limit scaled by the search stability, a fixed budget (like `movetime`) is not scaled.
+    pub fn soft_limit(&self) -> u64 {
+        if self.limits.soft == self.limits.hard {
+            return self.limits.soft;
+        }
+
+        let mut factor = 1.0 + BEST_MOVE_CHANGE_FACTOR * self.best_move_changes;
+        if self.score_drop > SCORE_DROP_MARGIN {
+            factor *= 1.0 + self.score_drop.min(SCORE_DROP_SCALE) as f64 / SCORE_DROP_SCALE as f64;
+        } else if self.stable_iterations >= STABLE_ITERATIONS {
+            factor *= STABLE_FACTOR;
+        }
+        let soft = self.limits.soft as f64 * factor.min(MAX_EXTENSION);
+        (soft as u64).min(self.limits.hard)
+    }
+
+    pub fn should_start_iteration(&self, elapsed: u64) -> bool {
+        if self.best_move.is_some() && self.root_moves <= 1 {
+            return false;
+        }
+        if elapsed >= self.soft_limit() {
+            return false;
+        }
+        // an iteration which would be aborted by the hard limit is wasted
+        let estimated_time = self
+            .iteration_time
+            .map(|time| (time as f64 * self.branching_factor) as u64)
+            .unwrap_or(0);
+        elapsed + estimated_time <= self.limits.hard
+    }
+}
+
+#[cfg(test)]
+mod time_management_tests {
+    use super::*;
+    use std::str::FromStr;
+    use test_case::test_case;
+
+    fn clock(time: Option<u64>, increment: u64, moves_to_go: Option<u64>, move_time: Option<u64>) -> Clock {
+        Clock {
+            time,
+            increment,
+            moves_to_go,
+            move_time,
+        }
+    }
+
+    #[test_case(clock(Some(60_000), 0, None, None), 30, 2398, 9592; "sudden death")]
+    #[test_case(clock(Some(60_000), 1_000, None, None), 30, 3148, 12592; "with increment")]
+    #[test_case(clock(Some(60_000), 0, Some(10), None), 30, 5997, 23988; "moves to go")]
+    #[test_case(clock(Some(5_000), 0, Some(1), None), 30, 4970, 4970; "last move before control")]
+    #[test_case(clock(Some(1_000), 2_000, None, None), 30, 776, 776; "increment larger than clock")]
+    #[test_case(clock(Some(20), 0, None, None), 30, 0, 0; "clock below overhead")]
+    #[test_case(clock(None, 0, None, Some(1_000)), 30, 970, 970; "move time")]
+    #[test_case(clock(Some(60_000), 0, None, Some(500)), 100, 400, 400; "move time wins over clock")]
+    fn limits(clock: Clock, move_overhead: u64, soft: u64, hard: u64) {
+        let limits = compute_limits(&clock, move_overhead).unwrap();
+        assert_eq!(limits, TimeLimits { soft, hard });
+        assert!(limits.soft <= limits.hard);
+        if let Some(time) = clock.time {
+            assert!(limits.hard < time.max(1));
+        }
+    }
+
+    fn mv(mv: &str) -> ChessMove {
+        ChessMove::from_str(mv).unwrap()
+    }
+
+    #[test]
+    fn stable_best_move_stops_early() {
+        let mut manager = TimeManager::new(TimeLimits { soft: 1000, hard: 4000 }, 20);
+        for _ in 0..=STABLE_ITERATIONS {
+            manager.update(mv("e2e4"), 20, 10);
+        }
+        assert_eq!(manager.soft_limit(), 500);
+        assert!(!manager.should_start_iteration(600));
+    }
+
+    #[test]
+    fn unstable_best_move_extends() {
+        let mut manager = TimeManager::new(TimeLimits { soft: 1000, hard: 4000 }, 20);
+        manager.update(mv("e2e4"), 20, 10);
+        manager.update(mv("d2d4"), 20, 10);
+        manager.update(mv("e2e4"), 20, 10);
+        assert!(manager.soft_limit() > 1000);
+        assert!(manager.should_start_iteration(1100));
+    }
+
+    #[test]
+    fn score_drop_extends() {
+        let mut manager = TimeManager::new(TimeLimits { soft: 1000, hard: 4000 }, 20);
+        manager.update(mv("e2e4"), 50, 10);
+        manager.update(mv("e2e4"), -100, 10);
+        assert_eq!(manager.soft_limit(), 1500);
+    }
+
+    #[test]
+    fn extension_never_exceeds_hard_limit() {
+        let mut manager = TimeManager::new(TimeLimits { soft: 1000, hard: 1200 }, 20);
+        manager.update(mv("e2e4"), 300, 10);
+        manager.update(mv("d2d4"), -300, 10);
+        assert_eq!(manager.soft_limit(), 1200);
+    }
+
+    #[test]
+    fn single_legal_move_stops_after_first_iteration() {
+        let mut manager = TimeManager::new(TimeLimits { soft: 1000, hard: 4000 }, 1);
+        assert!(manager.should_start_iteration(0));
+        manager.update(mv("e1f1"), 0, 1);
+        assert!(!manager.should_start_iteration(1));
+    }
+
+    #[test]
+    fn expensive_iteration_is_not_started() {
+        let mut manager = TimeManager::new(TimeLimits { soft: 1000, hard: 4000 }, 20);
+        manager.update(mv("e2e4"), 20, 100);
+        manager.update(mv("e2e4"), 20, 400);
+        // next iteration estimated at 1600 ms
+        assert!(manager.should_start_iteration(500));
+        manager.update(mv("e2e4"), 20, 1600);
+        // next iteration estimated at 6400 ms
+        assert!(!manager.should_start_iteration(700));
+    }
+
+    #[test]
+    fn fixed_budget_is_not_scaled() {
+        let mut manager = TimeManager::new(TimeLimits { soft: 1000, hard: 1000 }, 20);
+        for _ in 0..=STABLE_ITERATIONS {
+            manager.update(mv("e2e4"), 20, 10);
+        }
+        assert_eq!(manager.soft_limit(), 1000);
+    }
+
+    #[test]
+    fn no_limits_without_clock() {
+        assert_eq!(compute_limits(&Clock::default(), DEFAULT_MOVE_OVERHEAD), None);
+    }
+}
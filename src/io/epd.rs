@@ -0,0 +1,104 @@
+use crate::io::pgn::parse_san;
+use chess::{Board, ChessMove};
+use std::str::FromStr;
+
+/// Position of an EPD test suite with the moves which solve it.
+pub struct EpdPosition {
+    pub board: Board,
+    pub id: Option<String>,
+    // best moves, one of them has to be played
+    pub best: Vec<ChessMove>,
+    // avoid moves, none of them may be played
+    pub avoid: Vec<ChessMove>,
+}
+
+impl EpdPosition {
+    pub fn is_solved_by(&self, mv: ChessMove) -> bool {
+        (self.best.is_empty() || self.best.contains(&mv)) && !self.avoid.contains(&mv)
+    }
+}
+
+// SAN like `Qg6+`, or coordinates like `g3g6`
+fn parse_move(board: &Board, mv: &str) -> Option<ChessMove> {
+    parse_san(board, mv).or(ChessMove::from_str(mv).ok().filter(|mv| board.legal(*mv)))
+}
+
+/// Operations of the line after the position, separated by `;` outside of quotes.
+fn operations(text: &str) -> Vec<String> {
+    let mut operations = Vec::new();
+    let mut operation = String::new();
+    let mut in_quotes = false;
+    for c in text.chars() {
+        match c {
+            '"' => {
+                in_quotes = !in_quotes;
+                operation.push(c);
+            }
+            ';' if !in_quotes => operations.push(std::mem::take(&mut operation)),
+            _ => operation.push(c),
+        }
+    }
+    operations.push(operation);
+    operations.into_iter().map(|operation| operation.trim().to_string()).filter(|operation| !operation.is_empty()).collect()
+}
+
+/// Line of an EPD file, like `<fen without clocks> bm Qg6; id "WAC.001";`.
+pub fn parse_epd(line: &str) -> Result<EpdPosition, String> {
+    let fields: Vec<&str> = line.split_whitespace().collect();
+    if fields.len() < 4 {
+        return Err("Missing position in |".to_string() + line + "|");
+    }
+    let fen = fields[..4].join(" ");
+    let board = Board::from_str(&(fen.clone() + " 0 1")).map_err(|_| "Invalid position |".to_string() + &*fen + "|")?;
+    let mut position = EpdPosition { board, id: None, best: Vec::new(), avoid: Vec::new() };
+    for operation in operations(&fields[4..].join(" ")) {
+        let (opcode, operands) = operation.split_once(' ').unwrap_or((&operation, ""));
+        let moves = match opcode {
+            "bm" => &mut position.best,
+            "am" => &mut position.avoid,
+            "id" => {
+                position.id = Some(operands.trim().trim_matches('"').to_string());
+                continue;
+            }
+            _ => continue,
+        };
+        for mv in operands.split_whitespace() {
+            moves.push(parse_move(&board, mv).ok_or("Illegal move |".to_string() + mv + "| in |" + &*fen + "|")?);
+        }
+    }
+    if position.best.is_empty() && position.avoid.is_empty() {
+        return Err("Missing bm and am in |".to_string() + line + "|");
+    }
+    Ok(position)
+}
+
+#[cfg(test)]
+mod epd_tests {
+    use super::*;
+
+    #[test]
+    fn reads_operations() {
+        let position = parse_epd(
+            r#"2rr3k/pp3pp1/1nnqbN1p/3pN3/2pP4/2P3Q1/PPB4P/R4RK1 w - - bm Qg6; id "WAC.001; first";"#,
+        )
+        .unwrap();
+        assert_eq!(position.id.as_deref(), Some("WAC.001; first"));
+        assert_eq!(position.best, vec![ChessMove::from_str("g3g6").unwrap()]);
+        assert!(position.avoid.is_empty());
+        assert!(position.is_solved_by(ChessMove::from_str("g3g6").unwrap()));
+        assert!(!position.is_solved_by(ChessMove::from_str("g3g4").unwrap()));
+
+        let position = parse_epd("7k/8/8/8/8/8/8/KR3R2 w - - am Rb8+ f1f8; bm Ka2 Kb2; c0 \"comment\";").unwrap();
+        assert_eq!(position.id, None);
+        assert_eq!(position.best.len(), 2);
+        assert_eq!(position.avoid, vec![ChessMove::from_str("b1b8").unwrap(), ChessMove::from_str("f1f8").unwrap()]);
+        assert!(position.is_solved_by(ChessMove::from_str("a1a2").unwrap()));
+    }
+
+    #[test]
+    fn rejects_invalid_lines() {
+        assert!(parse_epd("8/8/8 w - -").is_err());
+        assert!(parse_epd("2rr3k/pp3pp1/1nnqbN1p/3pN3/2pP4/2P3Q1/PPB4P/R4RK1 w - - id \"x\";").is_err());
+        assert!(parse_epd("2rr3k/pp3pp1/1nnqbN1p/3pN3/2pP4/2P3Q1/PPB4P/R4RK1 w - - bm Qh8;").is_err());
+    }
+}
@@ -1,3 +1,5 @@
+pub mod epd;
 pub mod output;
+pub mod pgn;
 pub mod uci;
 pub mod options;
\ No newline at end of file
@@ -1,5 +1,6 @@
 use std::collections::{HashMap, HashSet};
 
+#[derive(Clone)]
 pub struct Options {
     values: HashMap<String, String>,
     flags: HashSet<String>,
@@ -0,0 +1,250 @@
+use chess::{Board, ChessMove, File, MoveGen, Piece, Rank, Square};
+use std::str::FromStr;
+
+/// Game of a PGN file, the moves are stopped at the first one which cannot be read.
+pub struct PgnGame {
+    pub start: Board,
+    pub moves: Vec<ChessMove>,
+    // result from the white point of view: 1 win, 0.5 draw, 0 loss, None when unknown
+    pub result: Option<f64>,
+    // false when a move could not be read
+    pub complete: bool,
+}
+
+pub fn parse_result(result: &str) -> Option<f64> {
+    match result {
+        "1-0" => Some(1.0),
+        "0-1" => Some(0.0),
+        "1/2-1/2" => Some(0.5),
+        _ => None,
+    }
+}
+
+fn parse_piece(letter: char) -> Option<Piece> {
+    match letter {
+        'N' => Some(Piece::Knight),
+        'B' => Some(Piece::Bishop),
+        'R' => Some(Piece::Rook),
+        'Q' => Some(Piece::Queen),
+        'K' => Some(Piece::King),
+        _ => None,
+    }
+}
+
+/// Legal move written in SAN, like `Nbd7`, `exd8=Q+` or `O-O`.
+pub fn parse_san(board: &Board, san: &str) -> Option<ChessMove> {
+    let san = san.trim_end_matches(['+', '#', '!', '?']);
+    let castle = match san {
+        "O-O" | "0-0" => Some(File::G),
+        "O-O-O" | "0-0-0" => Some(File::C),
+        _ => None,
+    };
+    if let Some(file) = castle {
+        let rank = board.side_to_move().to_my_backrank();
+        return MoveGen::new_legal(board).find(|mv| {
+            board.piece_on(mv.get_source()) == Some(Piece::King)
+                && mv.get_source().get_file() == File::E
+                && mv.get_dest() == Square::make_square(rank, file)
+        });
+    }
+
+    let mut chars: Vec<char> = san.chars().filter(|c| *c != 'x' && *c != '=').collect();
+    let piece = chars.first().and_then(|c| parse_piece(*c));
+    if piece.is_some() {
+        chars.remove(0);
+    }
+    let promotion = chars.last().and_then(|c| parse_piece(*c));
+    if promotion.is_some() {
+        chars.pop();
+    }
+    if chars.len() < 2 {
+        return None;
+    }
+    let dest: String = chars[chars.len() - 2..].iter().collect();
+    let dest = Square::from_str(&dest).ok()?;
+    let mut source_file = None;
+    let mut source_rank = None;
+    for c in chars[..chars.len() - 2].iter() {
+        match c {
+            'a'..='h' => source_file = Some(File::from_index(*c as usize - 'a' as usize)),
+            '1'..='8' => source_rank = Some(Rank::from_index(*c as usize - '1' as usize)),
+            _ => return None,
+        }
+    }
+    let piece = piece.unwrap_or(Piece::Pawn);
+    let mut candidates = MoveGen::new_legal(board).filter(|mv| {
+        mv.get_dest() == dest
+            && mv.get_promotion() == promotion
+            && board.piece_on(mv.get_source()) == Some(piece)
+            && source_file.is_none_or(|file| mv.get_source().get_file() == file)
+            && source_rank.is_none_or(|rank| mv.get_source().get_rank() == rank)
+    });
+    let mv = candidates.next()?;
+    // an ambiguous move is not guessed
+    candidates.next().is_none().then_some(mv)
+}
+
+#[derive(Default)]
+struct GameText {
+    fen: Option<String>,
+    result: Option<String>,
+    movetext: String,
+}
+
+impl GameText {
+    fn is_empty(&self) -> bool {
+        self.fen.is_none() && self.result.is_none() && self.movetext.trim().is_empty()
+    }
+
+    fn parse(self) -> Option<PgnGame> {
+        let start = match &self.fen {
+            Some(fen) => Board::from_str(fen).ok()?,
+            None => Board::default(),
+        };
+        let mut game = PgnGame {
+            start,
+            moves: Vec::new(),
+            result: self.result.as_deref().and_then(parse_result),
+            complete: true,
+        };
+        let mut board = start;
+        for token in movetext_tokens(&self.movetext) {
+            if token == "*" || parse_result(&token).is_some() {
+                game.result = game.result.or(parse_result(&token));
+                break;
+            }
+            match parse_san(&board, &token) {
+                Some(mv) => {
+                    game.moves.push(mv);
+                    board = board.make_move_new(mv);
+                }
+                None => {
+                    game.complete = false;
+                    break;
+                }
+            }
+        }
+        Some(game)
+    }
+}
+
+/// Moves and results of the movetext, without move numbers, comments, variations and NAGs.
+fn movetext_tokens(movetext: &str) -> Vec<String> {
+    let mut tokens = Vec::new();
+    let mut token = String::new();
+    let mut variation_depth = 0;
+    let mut chars = movetext.chars();
+    while let Some(c) = chars.next() {
+        match c {
+            '{' => {
+                chars.by_ref().find(|c| *c == '}');
+            }
+            ';' => {
+                chars.by_ref().find(|c| *c == '\n');
+            }
+            '(' => variation_depth += 1,
+            ')' => variation_depth -= 1,
+            _ if variation_depth > 0 => {}
+            _ if c.is_whitespace() => tokens.push(std::mem::take(&mut token)),
+            _ => token.push(c),
+        }
+        if "{;()".contains(c) {
+            tokens.push(std::mem::take(&mut token));
+        }
+    }
+    tokens.push(token);
+    tokens
+        .into_iter()
+        // move numbers, like `12.` or `12...e5`
+        .map(|token| match token.rfind('.') {
+            Some(i) if token[..i].chars().all(|c| c.is_ascii_digit() || c == '.') => token[i + 1..].to_string(),
+            _ => token,
+        })
+        .filter(|token| !token.is_empty() && !token.starts_with('$'))
+        .collect()
+}
+
+/// Games of the PGN text, games with a FEN which cannot be read are skipped.
+pub fn parse_pgn(content: &str) -> Vec<PgnGame> {
+    let mut games = Vec::new();
+    let mut current = GameText::default();
+    let mut in_movetext = false;
+    for line in content.lines() {
+        let line = line.trim();
+        if line.starts_with('[') {
+            if in_movetext {
+                games.extend(std::mem::take(&mut current).parse());
+                in_movetext = false;
+            }
+            let mut parts = line.trim_matches(['[', ']']).splitn(2, ' ');
+            let (name, value) = (parts.next().unwrap_or(""), parts.next().unwrap_or("").trim().trim_matches('"'));
+            match name {
+                "FEN" => current.fen = Some(value.to_string()),
+                "Result" => current.result = Some(value.to_string()),
+                _ => {}
+            }
+        } else if !line.is_empty() {
+            in_movetext = true;
+            current.movetext += line;
+            current.movetext.push('\n');
+        }
+    }
+    if !current.is_empty() {
+        games.extend(current.parse());
+    }
+    games
+}
+
+#[cfg(test)]
+mod pgn_tests {
+    use super::*;
+    use test_case::test_case;
+
+    #[test_case("rnbqkbnr/pppppppp/8/8/8/8/PPPPPPPP/RNBQKBNR w KQkq - 0 1", "Nf3", "g1f3"; "knight")]
+    #[test_case("rnbqkbnr/pppppppp/8/8/8/8/PPPPPPPP/RNBQKBNR w KQkq - 0 1", "e4!?", "e2e4"; "pawn with annotation")]
+    #[test_case("r3k2r/8/8/8/8/8/8/R3K2R w KQkq - 0 1", "O-O-O+", "e1c1"; "long castle with check")]
+    #[test_case("r3k2r/8/8/8/8/8/8/R3K2R b KQkq - 0 1", "0-0", "e8g8"; "short castle with zeros")]
+    #[test_case("3r3k/2P5/8/8/8/8/8/K7 w - - 0 1", "cxd8=N", "c7d8n"; "capture promotion")]
+    #[test_case("3r3k/2P5/8/8/8/8/8/K7 w - - 0 1", "c8Q+", "c7c8q"; "promotion without equal sign")]
+    #[test_case("k7/8/8/8/8/8/8/KR3R2 w - - 0 1", "Rbd1", "b1d1"; "file disambiguation")]
+    #[test_case("7k/8/8/R7/8/8/8/R3K3 w - - 0 1", "R1a3", "a1a3"; "rank disambiguation")]
+    fn parses_san(fen: &str, san: &str, expected: &str) {
+        let board = Board::from_str(fen).unwrap();
+        assert_eq!(parse_san(&board, san).map(|mv| mv.to_string()), Some(expected.to_string()));
+    }
+
+    #[test]
+    fn rejects_ambiguous_and_illegal_san() {
+        let board = Board::from_str("k7/8/8/8/8/8/8/KR3R2 w - - 0 1").unwrap();
+        assert_eq!(parse_san(&board, "Rd1"), None);
+        assert_eq!(parse_san(&board, "Ke4"), None);
+        assert_eq!(parse_san(&board, "Zz9"), None);
+    }
+
+    #[test]
+    fn reads_games() {
+        let pgn = r#"[Event "Test"]
+[Result "1-0"]
+
+1. e4 e5 {open game} 2. Nf3 (2. f4 exf4) Nc6 $1 3. Bb5 a6; Morphy
+4. Ba4 1-0
+
+[Event "Second"]
+[FEN "4k3/8/8/8/8/8/4P3/4K3 w - - 0 1"]
+[SetUp "1"]
+[Result "1/2-1/2"]
+
+1. e4 Kd7 2. Qh5 1/2-1/2
+"#;
+        let games = parse_pgn(pgn);
+        assert_eq!(games.len(), 2);
+        let moves: Vec<String> = games[0].moves.iter().map(|mv| mv.to_string()).collect();
+        assert_eq!(moves, vec!["e2e4", "e7e5", "g1f3", "b8c6", "f1b5", "a7a6", "b5a4"]);
+        assert_eq!(games[0].result, Some(1.0));
+        assert!(games[0].complete);
+        assert_eq!(games[1].start.to_string(), "4k3/8/8/8/8/8/4P3/4K3 w - - 0 1");
+        assert_eq!(games[1].moves.len(), 2);
+        assert_eq!(games[1].result, Some(0.5));
+        assert!(!games[1].complete);
+    }
+}
@@ -5,9 +5,15 @@ use std::process;
 use std::str::FromStr;
 use chess::Color::White;
 use Color::Black;
+use crate::features::eval_params::{eval_params, with_eval_params};
+use crate::features::evaluation::{eval_breakdown, moves_to_mate, EvalBreakdown, Score};
+use crate::features::time_management::Clock;
 use crate::io::options::Options;
+use crate::io::pgn::parse_result;
 use crate::io::uci::Position::{FEN, START};
 use crate::minmax_engine::MinMaxEngine;
+use crate::tools::bench::{bench_report, DEFAULT_DEPTH};
+use crate::tools::perft::perft_report;
 
 pub struct State {
     pub engine: Box<dyn Engine>,
@@ -31,40 +37,94 @@ struct ParseResult {
     moves: Vec<ChessMove>,
 }
 
+pub struct GoCommand {
+    pub clock: Clock,
+    pub search_moves: Vec<ChessMove>,
+    pub mate: Option<usize>,
+}
+
+const GO_KEYWORDS: [&str; 12] = [
+    "searchmoves", "ponder", "wtime", "btime", "winc", "binc",
+    "movestogo", "depth", "nodes", "mate", "movetime", "infinite",
+];
+
 pub fn handle_uci(uci: &String, state: &mut State) -> UciResult {
     let tokens: Vec<&str> = uci.split(' ').collect();
-    let mut time: Option<u64> = get_time(&tokens, state.next_color);
     match tokens[0] {
         "uci" => start(),
         "isready" => is_ready(state),
         "ucinewgame" => restart(state),
-        "go" => go(state, time.unwrap()),
+        "go" => go(state, tokens),
         "stop" => stop(state),
         "position" => update(state, tokens),
         "setoption" => set_option(state, tokens),
+        "eval" if tokens.get(1) == Some(&"trace") => trace(state),
         "eval" => evaluate(state),
+        "d" => display(state),
+        "result" => game_result(state, tokens),
+        "bench" => bench(state, tokens),
+        "exportparams" => export_params(tokens),
         "quit" => quit(),
         &_ => UciResult::with("Unknown command |".to_string() + uci + "|")
     }
 }
 
-fn get_time(tokens: &Vec<&str>, next_color: Color) -> Option<u64> {
-    for i in (1..tokens.len()).step_by(2) {
-        if tokens[i] == "movetime" {
-            return Some(tokens[i + 1].parse().unwrap());
-        }
-        if tokens[i] == "wtime" && next_color == White {
-            return Some(tokens[i + 1].parse().unwrap());
-        }
-        if tokens[i] == "btime" && next_color == Black {
-            return Some(tokens[i + 1].parse().unwrap());
+fn parse_go_tokens(tokens: Vec<&str>, next_color: Color) -> std::result::Result<GoCommand, String> {
+    let mut clock = Clock::default();
+    let mut search_moves = Vec::new();
+    let mut mate = None;
+    let mut i = 1;
+    while i < tokens.len() {
+        let value = tokens.get(i + 1).and_then(|value| value.parse::<u64>().ok());
+        match tokens[i] {
+            "searchmoves" => {
+                while i + 1 < tokens.len() && !GO_KEYWORDS.contains(&tokens[i + 1]) {
+                    i += 1;
+                    match ChessMove::from_str(tokens[i]) {
+                        Ok(mv) => search_moves.push(mv),
+                        Err(_) => return Err("Illegal move in searchmoves |".to_string() + tokens[i] + "|"),
+                    }
+                }
+            }
+            "mate" => mate = value.map(|moves| moves as usize).filter(|moves| *moves > 0),
+            "movetime" => clock.move_time = value,
+            "movestogo" => clock.moves_to_go = value,
+            "wtime" if next_color == White => clock.time = value,
+            "btime" if next_color == Black => clock.time = value,
+            "winc" if next_color == White => clock.increment = value.unwrap_or(0),
+            "binc" if next_color == Black => clock.increment = value.unwrap_or(0),
+            _ => {}
         }
+        i += 1;
+    }
+    if !clock.is_set() && mate.is_none() {
+        return Err("Missing time in go".to_string());
     }
-    None
+    Ok(GoCommand { clock, search_moves, mate })
 }
 
 fn start() -> UciResult {
-    UciResult::with("id name rdzawa_bestia\nuciok".to_string())
+    #[cfg(feature = "nnue")]
+    let nnue_options = "option name UseNNUE type check default false\n".to_string()
+        + "option name EvalFile type string default "
+        + crate::features::nnue::DEFAULT_EVAL_FILE
+        + "\n";
+    #[cfg(not(feature = "nnue"))]
+    let nnue_options = String::new();
+    UciResult::with(
+        "id name rdzawa_bestia\n".to_string()
+            + "option name MultiPV type spin default 1 min 1 max 256\n"
+            + "option name Move Overhead type spin default 30 min 0 max 5000\n"
+            + "option name Params type string default <empty>\n"
+            + "option name SyzygyPath type string default <empty>\n"
+            + "option name SyzygyProbeLimit type spin default 7 min 0 max 7\n"
+            + "option name BookVariety type combo default Best var Best var Weighted var Uniform\n"
+            + "option name BookVarietyThreshold type spin default 50 min 0 max 100\n"
+            + "option name BookSeed type spin default 0 min 0 max 2147483647\n"
+            + "option name BookLearning type check default false\n"
+            + &*nnue_options
+            + "uciok",
+    )
 }
 
 fn is_ready(state: &mut State) -> UciResult {
@@ -81,12 +141,36 @@ fn restart(state: &mut State) -> UciResult {
     UciResult::empty()
 }
 
-fn go(state: &mut State, time: u64) -> UciResult {
-    state.engine.start(time);
+fn go(state: &mut State, tokens: Vec<&str>) -> UciResult {
+    if tokens.get(1) == Some(&"perft") {
+        return match tokens.get(2).and_then(|depth| depth.parse().ok()) {
+            Some(depth) => UciResult::with(perft_report(&state.engine.position(), depth)),
+            None => UciResult::with("Missing depth in go perft".to_string()),
+        };
+    }
+    let command = match parse_go_tokens(tokens, state.next_color) {
+        Ok(command) => command,
+        Err(msg) => return UciResult::with(msg),
+    };
+    let pos = state.engine.position();
+    for mv in command.search_moves.iter() {
+        if !pos.legal(*mv) {
+            return UciResult::with("Illegal move in searchmoves |".to_string() + &*mv.to_string() + "|");
+        }
+    }
+    state.engine.start(command);
     state.next_color = swap_color(state.next_color);
     UciResult::empty()
 }
 
+fn bench(state: &State, tokens: Vec<&str>) -> UciResult {
+    match tokens.get(1).map(|depth| depth.parse()) {
+        None => UciResult::with(bench_report(&state.options, DEFAULT_DEPTH)),
+        Some(Ok(depth)) => UciResult::with(bench_report(&state.options, depth)),
+        Some(Err(_)) => UciResult::with("Invalid depth in bench |".to_string() + tokens[1] + "|"),
+    }
+}
+
 fn stop(state: &mut State) -> UciResult {
     state.engine.stop();
     UciResult::empty()
@@ -104,26 +188,90 @@ fn update(state: &mut State, tokens: Vec<&str>) -> UciResult {
 }
 
 fn set_option(state: &mut State, tokens: Vec<&str>) -> UciResult {
-    if tokens.len() > 2 {
-        if tokens[1] == "name" {
-            let key = tokens[2];
-            if tokens.len() > 4 {
-                if tokens[3] == "value" {
-                    let value = tokens[4];
-                    state.options.add_value(key.to_string(), value.to_string());
-                }
-            } else {
-                state.options.add_flag(key.to_string());
+    if tokens.len() > 2 && tokens[1] == "name" {
+        // option names, like `Move Overhead`, can contain spaces
+        match tokens.iter().position(|token| *token == "value") {
+            Some(value_index) if value_index > 2 && value_index + 1 < tokens.len() => {
+                let key = tokens[2..value_index].join(" ");
+                let value = tokens[value_index + 1..].join(" ");
+                state.options.add_value(key, value);
             }
+            Some(_) => {}
+            None => state.options.add_flag(tokens[2..].join(" ")),
         }
     }
     UciResult::empty()
 }
 
+// `result 1-0`, sent after a game, before `ucinewgame`
+fn game_result(state: &mut State, tokens: Vec<&str>) -> UciResult {
+    match tokens.get(1).and_then(|result| parse_result(result)) {
+        Some(result) => {
+            state.engine.learn(result);
+            UciResult::empty()
+        }
+        None => UciResult::with("Unknown result |".to_string() + &*tokens[1..].join(" ") + "|"),
+    }
+}
+
 fn evaluate(state: &State) -> UciResult {
     UciResult::with("eval ".to_string() + &*state.engine.evaluate().to_string())
 }
 
+fn trace(state: &State) -> UciResult {
+    let breakdown = with_eval_params(|params| eval_breakdown(&state.engine.position(), params));
+    let mut msg = format_breakdown(&breakdown);
+    // finished games and insufficient material are not scored by the terms
+    let final_score = state.engine.evaluate();
+    if final_score != breakdown.side_to_move_score {
+        msg += &*("\nFinal score (game over or draw): ".to_string() + &*final_score.to_string());
+    }
+    UciResult::with(msg)
+}
+
+fn display(state: &State) -> UciResult {
+    let fen = state.engine.position().to_string();
+    UciResult::with("Fen: ".to_string() + &*fen + "\n" + &*trace(state).msg.unwrap())
+}
+
+fn format_score(score: Score) -> String {
+    format!("{:>6} {:>6}", score.mg, score.eg)
+}
+
+fn format_breakdown(breakdown: &EvalBreakdown) -> String {
+    let mut table = format!("{:>12} | {:>13} | {:>13} | {:>13}\n", "Term", "White MG EG", "Black MG EG", "Total MG EG");
+    for term in breakdown.terms.iter() {
+        table += &*format!(
+            "{:>12} | {} | {} | {}\n",
+            term.name,
+            format_score(term.white),
+            format_score(term.black),
+            format_score(term.total()),
+        );
+    }
+    table += &*format!("Phase: {} (24 is middlegame, 0 endgame)\n", breakdown.phase);
+    match breakdown.endgame {
+        Some(name) => table += &*format!("Endgame: {} (replaces the terms)\n", name),
+        None => table += &*format!("Endgame scale: {}/64\n", breakdown.scale),
+    }
+    table += &*format!("Total (white): {}\n", breakdown.score);
+    table += &*format!("Score (side to move): {}", breakdown.side_to_move_score);
+    table
+}
+
+// prints the evaluation parameters in use, or writes them to the given file
+fn export_params(tokens: Vec<&str>) -> UciResult {
+    let params = eval_params();
+    if tokens.len() < 2 {
+        return UciResult::with(params.to_json().dump());
+    }
+    let path = tokens[1..].join(" ");
+    match params.save(&path) {
+        Ok(()) => UciResult::with("Parameters written to |".to_string() + &*path + "|"),
+        Err(msg) => UciResult::with(msg),
+    }
+}
+
 fn parse_update_tokens(tokens: Vec<&str>) -> ParseResult {
     let mut i = tokens.len() - 1;
     let mut moves = Vec::new();
@@ -153,6 +301,13 @@ pub fn move_to_uci(mv: ChessMove) -> String {
     String::from("bestmove ").add(mv.to_string().as_str())
 }
 
+pub fn score_to_uci(score: i32) -> String {
+    match moves_to_mate(score) {
+        Some(moves) => String::from("mate ") + &*moves.to_string(),
+        None => String::from("cp ") + &*score.to_string(),
+    }
+}
+
 fn swap_color(color: Color) -> Color {
     return match color {
         White => Black,
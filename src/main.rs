@@ -8,8 +8,14 @@ mod minmax_engine;
 mod features;
 mod io;
 mod random_engine;
+mod tools;
 
 fn main() {
+    let args: Vec<String> = std::env::args().skip(1).collect();
+    if !args.is_empty() {
+        std::process::exit(tools::run(&args));
+    }
+
     let mut input = String::new();
     let mut state = State::new();
     let stdin = std::io::stdin();
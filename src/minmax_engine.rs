@@ -1,19 +1,24 @@
 use crate::engine::Engine;
 use crate::features::board_utils::{is_insufficient_material, status};
-use crate::features::evaluation::eval;
+use crate::features::eval_params::{set_eval_params, EvalParams};
+use crate::features::evaluation::{eval, MATE_PLY_PENALTY, MATE_SCORE};
 use crate::features::killer_moves::KillerMoves;
-use crate::features::opening_book::OpeningBook;
+use crate::features::opening_book::{BookSelection, BookVariety, OpeningBook, DEFAULT_VARIETY_THRESHOLD};
 use crate::features::quiescence::quiescence;
+#[cfg(feature = "nnue")]
+use crate::features::nnue::{Network, NnueState, DEFAULT_EVAL_FILE};
 use crate::features::null_move_pruning::null_move;
-use crate::features::time_management::default_time_manager;
+use crate::features::syzygy::{wdl_score, Tablebase, Wdl, MAX_PIECES};
+use crate::features::time_management::{compute_limits, TimeLimits, TimeManager, DEFAULT_MOVE_OVERHEAD};
 use crate::io::output::send_info;
 use crate::io::output::send_move;
 use arrayvec::ArrayVec;
 use chess::{Board, BoardStatus, ChessMove, Color, MoveGen};
+use std::cmp::Reverse;
 use std::ops::Add;
 use std::str::FromStr;
 use std::time::{Duration, Instant};
-use crate::io::uci::Position;
+use crate::io::uci::{score_to_uci, GoCommand, Position};
 use crate::features::transposition_table::{EntryType, TranspositionTable};
 use crate::io::options::Options;
 
@@ -23,28 +28,61 @@ pub struct Result {
     pub(crate) computed: bool,
 }
 
+struct PvLine {
+    score: i32,
+    chosen_move: ChessMove,
+}
+
 pub struct MinMaxEngine {
     pub pos: Board,
     pub killer_moves: ArrayVec<KillerMoves<{ Self::KILLER_MOVES_SIZE }>, { Self::MAX_DEPTH }>,
     pub evaluations_cnt: i32,
     pub book: OpeningBook,
+    // book weights are changed by the game results when the `BookLearning` option is set
+    pub book_learning: bool,
     pub transposition_table: TranspositionTable,
+    pub multi_pv: usize,
+    // root moves allowed by `go searchmoves`, all moves when empty
+    pub search_moves: Vec<ChessMove>,
+    // root moves skipped by negamax, used to search the next PV slot
+    pub excluded_root_moves: Vec<ChessMove>,
+    // root move searched first, best move of the slot in the previous iteration
+    pub root_hint: Option<ChessMove>,
+    // disables pruning which is unsound when proving mates
+    pub mate_search: bool,
+    pub move_overhead: u64,
+    // network evaluation used instead of `eval` when the `UseNNUE` option is set
+    #[cfg(feature = "nnue")]
+    pub nnue: Option<NnueState>,
+    // Syzygy tables of the `SyzygyPath` option, probed with at most `tb_probe_limit` pieces
+    pub tablebase: Option<Tablebase>,
+    pub tb_probe_limit: usize,
+    pub tb_hits: u64,
+    // nodes after which the search is aborted, no limit when None
+    pub node_limit: Option<u64>,
 }
 
 impl Engine for MinMaxEngine {
-    fn start(&mut self, time: u64) {
-        send_move(self.find_best_move(time))
+    fn start(&mut self, command: GoCommand) {
+        self.search_moves = command.search_moves;
+        let limits = compute_limits(&command.clock, self.move_overhead);
+        let mv = match command.mate {
+            Some(moves) => self.find_mate_move(moves, limits.map(|limits| limits.hard)),
+            None => self.find_best_move(limits.unwrap()),
+        };
+        self.search_moves.clear();
+        send_move(mv)
     }
 
     fn stop(&mut self) {
-        send_move(self.find_best_move(0))
+        send_move(self.find_best_move(TimeLimits { soft: 0, hard: 0 }))
     }
 
     fn update(&mut self, fen: Position, moves: Vec<ChessMove>) {
         match fen {
             Position::FEN(fen) => {
                 self.pos = Board::from_str(&fen).unwrap();
-                self.book = OpeningBook::empty();
+                self.book = self.book.clone().after_fen();
                 for mv in moves {
                     self.pos = self.pos.make_move_new(mv)
                 }
@@ -77,31 +115,174 @@ impl Engine for MinMaxEngine {
 
         let board_status = status(&self.pos, any_legal_move, insufficient_material);
 
+        #[cfg(feature = "nnue")]
+        if let (Some(nnue), BoardStatus::Ongoing) = (&self.nnue, board_status) {
+            return nnue.evaluate_board(&self.pos);
+        }
         if self.pos.side_to_move() == Color::White {
             eval(&self.pos, board_status, 0)
         } else {
             -eval(&self.pos, board_status, 0)
         }
     }
+
+    fn position(&self) -> Board {
+        self.pos
+    }
+
+    fn learn(&mut self, white_result: f64) {
+        if !self.book_learning {
+            return;
+        }
+        match self.book.learn(white_result) {
+            Ok(learned) => send_info(format!("string book learned from {} moves", learned)),
+            Err(err) => send_info(err + ", book not learned"),
+        }
+    }
 }
 
 impl MinMaxEngine {
     const MAX_DEPTH: usize = 30;
     const KILLER_MOVES_SIZE: usize = 2;
+    const MATE_SEARCH_MAX_TIME: u64 = 24 * 60 * 60 * 1000;
     pub fn new(pos: Board, options: &Options) -> Self {
         let mut km = ArrayVec::<_, { Self::MAX_DEPTH }>::new();
         for _ in 0..Self::MAX_DEPTH {
             km.push(KillerMoves::<{ Self::KILLER_MOVES_SIZE }>::new());
         }
+        set_eval_params(Self::load_eval_params(options));
         MinMaxEngine {
             pos: pos,
             killer_moves: km,
             evaluations_cnt: 0,
-            book: OpeningBook::new(options.get_value("openings".to_string()).unwrap_or(&"book.json".to_string())),
+            book: Self::load_book(options),
+            book_learning: options.get_value("BookLearning".to_string()).map(|value| value.as_str()) == Some("true"),
             transposition_table: TranspositionTable::new(),
+            multi_pv: options
+                .get_value("MultiPV".to_string())
+                .and_then(|value| value.parse().ok())
+                .unwrap_or(1)
+                .max(1),
+            search_moves: Vec::new(),
+            excluded_root_moves: Vec::new(),
+            root_hint: None,
+            mate_search: false,
+            move_overhead: options
+                .get_value("Move Overhead".to_string())
+                .and_then(|value| value.parse().ok())
+                .unwrap_or(DEFAULT_MOVE_OVERHEAD),
+            #[cfg(feature = "nnue")]
+            nnue: Self::load_nnue(options),
+            tablebase: Self::load_tablebase(options),
+            tb_probe_limit: options
+                .get_value("SyzygyProbeLimit".to_string())
+                .and_then(|value| value.parse().ok())
+                .unwrap_or(MAX_PIECES),
+            tb_hits: 0,
+            node_limit: None,
+        }
+    }
+
+    fn load_book(options: &Options) -> OpeningBook {
+        let threshold = options
+            .get_value("BookVarietyThreshold".to_string())
+            .and_then(|value| value.parse().ok())
+            .unwrap_or(DEFAULT_VARIETY_THRESHOLD);
+        let variety = options
+            .get_value("BookVariety".to_string())
+            .and_then(|value| BookVariety::parse(value, threshold))
+            .unwrap_or(BookVariety::Best);
+        // 0 picks a different seed for every run
+        let seed = options
+            .get_value("BookSeed".to_string())
+            .and_then(|value| value.parse().ok())
+            .filter(|seed| *seed != 0);
+        let path = options.get_value("openings".to_string()).cloned().unwrap_or("book.json".to_string());
+        OpeningBook::new(&path, BookSelection::new(variety, seed))
+    }
+
+    fn load_tablebase(options: &Options) -> Option<Tablebase> {
+        let path = options.get_value("SyzygyPath".to_string()).filter(|path| !path.is_empty() && *path != "<empty>")?;
+        Tablebase::open(path)
+            .inspect(|tablebase| send_info(format!("string found Syzygy tables up to {} pieces", tablebase.max_pieces())))
+            .map_err(|err| send_info(err + ", tablebases disabled"))
+            .ok()
+    }
+
+    /// Tablebase result of `pos` for the side to move when it has few enough pieces.
+    pub fn probe_tablebase(&mut self, pos: &Board) -> Option<Wdl> {
+        if pos.combined().popcnt() as usize > self.tb_probe_limit {
+            return None;
+        }
+        let wdl = self.tablebase.as_ref()?.probe_wdl(pos)?;
+        self.tb_hits += 1;
+        Some(wdl)
+    }
+
+    #[cfg(feature = "nnue")]
+    fn load_nnue(options: &Options) -> Option<NnueState> {
+        if options.get_value("UseNNUE".to_string()).map(|value| value.as_str()) != Some("true") {
+            return None;
+        }
+        let path = options.get_value("EvalFile".to_string()).map(|path| path.as_str()).unwrap_or(DEFAULT_EVAL_FILE);
+        match Network::load(path) {
+            Ok(network) => Some(NnueState::new(network)),
+            Err(err) => {
+                send_info(err + ", using classical evaluation");
+                None
+            }
+        }
+    }
+
+    /// Side to move relative evaluation of `pos`, the position at `total_depth` of the searched line.
+    pub fn evaluate_node(&self, pos: &Board, board_status: BoardStatus, total_depth: usize) -> i32 {
+        #[cfg(feature = "nnue")]
+        if let (Some(nnue), BoardStatus::Ongoing) = (&self.nnue, board_status) {
+            let score = nnue.evaluate(total_depth, pos.side_to_move());
+            debug_assert_eq!(score, nnue.evaluate_board(pos));
+            return score;
+        }
+        if pos.side_to_move() == Color::White {
+            eval(pos, board_status, total_depth)
+        } else {
+            -eval(pos, board_status, total_depth)
         }
     }
 
+    /// Has to be called when a search starts at `pos`.
+    #[cfg_attr(not(feature = "nnue"), allow(unused_variables))]
+    pub fn nnue_set_root(&mut self, pos: &Board) {
+        #[cfg(feature = "nnue")]
+        if let Some(nnue) = self.nnue.as_mut() {
+            nnue.set_root(pos);
+        }
+    }
+
+    /// Has to be called after a move (or a null move) from `before` to `after`, searched at `total_depth`.
+    #[cfg_attr(not(feature = "nnue"), allow(unused_variables))]
+    pub fn nnue_push(&mut self, before: &Board, after: &Board, total_depth: usize) {
+        #[cfg(feature = "nnue")]
+        if let Some(nnue) = self.nnue.as_mut() {
+            nnue.push(before, after, total_depth);
+        }
+    }
+
+    fn load_eval_params(options: &Options) -> EvalParams {
+        match options.get_value("Params".to_string()).filter(|path| !path.is_empty() && *path != "<empty>") {
+            Some(path) => EvalParams::load(path).unwrap_or_else(|err| {
+                send_info(err + ", using default parameters");
+                EvalParams::default()
+            }),
+            None => EvalParams::default(),
+        }
+    }
+
+    // the time is checked every 512 nodes
+    pub fn should_stop(&self, end_time: Instant) -> bool {
+        ((self.evaluations_cnt & 511) == 0 && end_time <= Instant::now())
+            || self.node_limit.is_some_and(|limit| self.evaluations_cnt as u64 >= limit)
+    }
+
     pub fn negamax(
         &mut self,
         pos: Board,
@@ -113,7 +294,7 @@ impl MinMaxEngine {
         end_time: Instant,
         is_last_null_move: bool
     ) -> Result {
-        if (self.evaluations_cnt & 511) == 0 && end_time <= Instant::now() {
+        if self.should_stop(end_time) {
             return Result {
                 score: alpha,
                 chosen_move: None,
@@ -121,7 +302,19 @@ impl MinMaxEngine {
             };
         }
 
-        let transposition_entry = self.transposition_table.find(&pos);
+        if total_depth == 0 {
+            self.nnue_set_root(&pos);
+        }
+
+        // root results with restricted moves are not valid for the whole position
+        let is_restricted_root = total_depth == 0
+            && (!self.excluded_root_moves.is_empty() || !self.search_moves.is_empty());
+
+        let transposition_entry = if is_restricted_root {
+            None
+        } else {
+            self.transposition_table.find(&pos)
+        };
         if transposition_entry.is_some() {
             let entry = transposition_entry.unwrap();
             if entry.depth >= depth {
@@ -146,11 +339,7 @@ impl MinMaxEngine {
         if board_status != BoardStatus::Ongoing {
             self.evaluations_cnt += 1;
 
-            let evl = if pos.side_to_move() == Color::White {
-                eval(&pos, board_status, total_depth)
-            } else {
-                -eval(&pos, board_status, total_depth)
-            };
+            let evl = self.evaluate_node(&pos, board_status, total_depth);
             self.transposition_table.insert(&pos, evl, None, depth, EntryType::EXACT);
             return Result {
                 score: evl,
@@ -159,14 +348,26 @@ impl MinMaxEngine {
             };
         }
 
+        // the root is left to the search, so it returns a move
+        if total_depth > 0 && !self.mate_search {
+            if let Some(wdl) = self.probe_tablebase(&pos) {
+                return Result {
+                    score: wdl_score(wdl, total_depth),
+                    chosen_move: None,
+                    computed: true,
+                };
+            }
+        }
+
         if depth == 0 {
             return quiescence(self, pos, qdepth, total_depth, alpha, beta, end_time);
         }
 
-        
-        let nm_result =  null_move(self, pos, depth, qdepth, total_depth, beta, end_time, is_last_null_move);
-        if nm_result.prunned {
-            return Result {score: beta, chosen_move: nm_result.chosen_move, computed: true};
+        if !self.mate_search {
+            let nm_result =  null_move(self, pos, depth, qdepth, total_depth, beta, end_time, is_last_null_move);
+            if nm_result.prunned {
+                return Result {score: beta, chosen_move: nm_result.chosen_move, computed: true};
+            }
         }
 
         let km_min_value = 1e6;
@@ -174,7 +375,11 @@ impl MinMaxEngine {
         // move ordering (killer moves first)
         let mut move_order = moves_generator
             .into_iter()
+            .filter(|mv| total_depth > 0 || self.is_root_move_allowed(mv))
             .map(|mv: ChessMove| {
+                if total_depth == 0 && Some(mv) == self.root_hint {
+                    return (2.0 * km_min_value, mv);
+                }
                 for i in 0..self.killer_moves[depth].size {
                     if mv == self.killer_moves[depth].moves[i] {
                         return (km_min_value + km_size - i as f32, mv);
@@ -192,6 +397,7 @@ impl MinMaxEngine {
         let mut new_pos = pos.clone();
         for (value, next_move) in move_order {
             pos.make_move(next_move, &mut new_pos);
+            self.nnue_push(&pos, &new_pos, total_depth + 1);
 
             let mut result: Result = self.negamax(
                 new_pos,
@@ -214,7 +420,9 @@ impl MinMaxEngine {
             }
 
             if result.score >= beta {
-                self.transposition_table.insert(&pos, beta, Some(best_move.clone()), depth, EntryType::LOWER);
+                if !is_restricted_root {
+                    self.transposition_table.insert(&pos, beta, Some(best_move.clone()), depth, EntryType::LOWER);
+                }
                 return Result {
                     score: beta,
                     chosen_move: Some(best_move),
@@ -231,7 +439,9 @@ impl MinMaxEngine {
                 }
             }
         }
-        self.transposition_table.insert(&pos, alpha, Some(best_move.clone()), depth, EntryType::UPPER);
+        if !is_restricted_root {
+            self.transposition_table.insert(&pos, alpha, Some(best_move.clone()), depth, EntryType::UPPER);
+        }
         return Result {
             score: alpha,
             chosen_move: Some(best_move),
@@ -239,8 +449,8 @@ impl MinMaxEngine {
         };
     }
 
-    fn find_best_move(&mut self, time: u64) -> ChessMove {
-        let book_result = self.book.clone().try_get_best();
+    fn find_best_move(&mut self, limits: TimeLimits) -> ChessMove {
+        let book_result = self.book.clone().try_get_best(&self.pos, &self.search_moves);
         self.book = book_result.book;
         if book_result.mv.is_some() {
             let mov = book_result.mv.unwrap();
@@ -248,125 +458,245 @@ impl MinMaxEngine {
             return mov;
         }
 
+        self.tb_hits = 0;
+        if let Some(mv) = self.find_tablebase_move() {
+            self.pos = self.pos.make_move_new(mv);
+            return mv;
+        }
+
         let mut depth = 1;
-        let mut estimation = 0;
-        let delta = 30; // 0.3 of the pawn
-        let pos_inf = 1e9 as i32;
-        let neg_inf = -1e9 as i32;
+        let root_moves = MoveGen::new_legal(&self.pos)
+            .filter(|mv| self.is_root_move_allowed(mv))
+            .collect::<Vec<ChessMove>>();
+        let slots = self.multi_pv.min(root_moves.len()).max(1);
+        let mut lines: Vec<PvLine> = Vec::new();
         // let mut best_score = -1e9;
-        let mut best_move: Option<ChessMove> = MoveGen::new_legal(&self.pos).next();
-        let end_time = Instant::now().add(Duration::from_millis(default_time_manager(time)));
+        let mut best_move: Option<ChessMove> = root_moves.first().copied();
+        let start_time = Instant::now();
+        let end_time = start_time.add(Duration::from_millis(limits.hard));
+        let mut time_manager = TimeManager::new(limits, root_moves.len());
 
-        while depth < Self::MAX_DEPTH {
+        'iterations: while depth < Self::MAX_DEPTH {
+            let iteration_start_time = Instant::now();
+            let elapsed = iteration_start_time.duration_since(start_time).as_millis() as u64;
+            if depth > 1 && !time_manager.should_start_iteration(elapsed) {
+                depth -= 1;
+                break;
+            }
             send_info(String::from("Depth:") + &*depth.to_string());
-            let alpha: i32 = estimation - delta;
-            let beta: i32 = estimation + delta;
-            let qdepth = 2 * depth;
-
-            let mut result;
-            if depth < 3 {
-                result = self.negamax(
-                    self.pos.clone(),
-                    depth,
-                    qdepth,
-                    0,
-                    neg_inf,
-                    pos_inf,
-                    end_time,
-                    false
-                );
-            } else {
-                result = self.negamax(self.pos.clone(), depth, qdepth, 0, alpha, beta, end_time, false);
-
-                if result.score >= beta {
-                    result = self.negamax(
-                        self.pos.clone(),
-                        depth,
-                        qdepth,
-                        0,
-                        result.score,
-                        pos_inf,
-                        end_time,
-                        false
-                    );
-                } else if result.score <= alpha {
-                    result = self.negamax(
-                        self.pos.clone(),
-                        depth,
-                        qdepth,
-                        0,
-                        neg_inf,
-                        result.score,
-                        end_time,
-                        false
-                    );
+            let mut iteration_lines: Vec<PvLine> = Vec::new();
+            self.excluded_root_moves.clear();
+
+            for slot in 0..slots {
+                let previous_line = lines.get(slot);
+                self.root_hint = previous_line.map(|line| line.chosen_move);
+                let estimation = previous_line.map(|line| line.score).unwrap_or(0);
+
+                let result = self.aspiration_search(depth, estimation, end_time);
+                send_info(String::from("Score ") + &*result.score.to_string());
+                if !result.computed || result.chosen_move.is_none() {
+                    depth -= 1;
+                    break 'iterations;
                 }
 
-                if result.score <= alpha || result.score >= beta {
-                    result = self.negamax(
-                        self.pos.clone(),
+                let chosen_move = result.chosen_move.unwrap();
+                self.excluded_root_moves.push(chosen_move);
+                iteration_lines.push(PvLine {
+                    score: result.score,
+                    chosen_move,
+                });
+            }
+
+            iteration_lines.sort_by_key(|line| Reverse(line.score));
+            if slots > 1 {
+                for (k, line) in iteration_lines.iter().enumerate() {
+                    send_info(format!(
+                        "multipv {} depth {} score {} pv {}",
+                        k + 1,
                         depth,
-                        qdepth,
-                        0,
-                        neg_inf,
-                        pos_inf,
-                        end_time,
-                        false
-                    );
+                        score_to_uci(line.score),
+                        line.chosen_move
+                    ));
                 }
             }
-
-            send_info(String::from("Score ") + &*result.score.to_string());
-            if result.computed == false {
-                depth -= 1;
-                break;
+            best_move = Some(iteration_lines[0].chosen_move);
+            time_manager.update(
+                iteration_lines[0].chosen_move,
+                iteration_lines[0].score,
+                iteration_start_time.elapsed().as_millis() as u64,
+            );
+            if self.tablebase.is_some() {
+                send_info(format!("depth {} tbhits {}", depth, self.tb_hits));
             }
-            estimation = result.score;
-            best_move = result.chosen_move;
+            lines = iteration_lines;
             depth += 1;
         }
+        self.excluded_root_moves.clear();
+        self.root_hint = None;
         send_info(String::from("Final depth:") + &*depth.to_string());
         let chosen_move = best_move.unwrap();
         self.pos = self.pos.make_move_new(chosen_move);
         // eval(&self.pos, true);
         chosen_move.clone()
     }
+
+    /// Iterative deepening up to the depth or the time in milliseconds, without the book and the tablebase,
+    /// returns the result of the last completed iteration.
+    pub fn search_depth(&mut self, depth: usize, time: Option<u64>) -> Result {
+        let end_time = Instant::now().add(Duration::from_millis(time.unwrap_or(Self::MATE_SEARCH_MAX_TIME)));
+        let mut result = Result { score: 0, chosen_move: None, computed: false };
+        for iteration in 1..=depth.min(Self::MAX_DEPTH - 1) {
+            self.root_hint = result.chosen_move;
+            let iteration_result = self.aspiration_search(iteration, result.score, end_time);
+            if !iteration_result.computed || iteration_result.chosen_move.is_none() {
+                break;
+            }
+            result = iteration_result;
+        }
+        self.root_hint = None;
+        result
+    }
+
+    // move keeping the tablebase result at the root, without a search
+    fn find_tablebase_move(&mut self) -> Option<ChessMove> {
+        if self.pos.combined().popcnt() as usize > self.tb_probe_limit {
+            return None;
+        }
+        let root = self.tablebase.as_ref()?.probe_root(&self.pos, |mv| self.is_root_move_allowed(mv))?;
+        self.tb_hits += 1;
+        send_info(format!(
+            "depth 0 score {} tbhits {} pv {}",
+            score_to_uci(wdl_score(root.wdl, 0)),
+            self.tb_hits,
+            root.mv
+        ));
+        Some(root.mv)
+    }
+
+    fn find_mate_move(&mut self, moves: usize, time: Option<u64>) -> ChessMove {
+        let end_time = Instant::now().add(Duration::from_millis(time.unwrap_or(Self::MATE_SEARCH_MAX_TIME)));
+        let chosen_move = self
+            .find_mate(moves, end_time)
+            .and_then(|result| result.chosen_move)
+            .unwrap_or_else(|| {
+                MoveGen::new_legal(&self.pos)
+                    .find(|mv| self.is_root_move_allowed(mv))
+                    .unwrap()
+            });
+        self.pos = self.pos.make_move_new(chosen_move);
+        chosen_move
+    }
+
+    // iterative deepening up to the depth which proves or rules out a mate in `moves`,
+    // the window only accepts mates found within the searched depth
+    fn find_mate(&mut self, moves: usize, end_time: Instant) -> Option<Result> {
+        let max_depth = (2 * moves - 1).min(Self::MAX_DEPTH - 1);
+        let mut mate = None;
+        self.mate_search = true;
+        for depth in (1..=max_depth).step_by(2) {
+            let mate_bound = MATE_SCORE - MATE_PLY_PENALTY * depth as i32;
+            // quiescence has to be disabled!
+            let result = self.negamax(self.pos, depth, 0, 0, mate_bound - 1, MATE_SCORE, end_time, false);
+            if !result.computed {
+                break;
+            }
+            if result.score >= mate_bound {
+                send_info(format!("depth {} score {}", depth, score_to_uci(result.score)));
+                mate = Some(result);
+                break;
+            }
+            if depth == max_depth {
+                send_info(format!("depth {} string no mate in {}", depth, moves));
+            }
+        }
+        self.mate_search = false;
+        mate
+    }
+
+    fn is_root_move_allowed(&self, mv: &ChessMove) -> bool {
+        (self.search_moves.is_empty() || self.search_moves.contains(mv))
+            && !self.excluded_root_moves.contains(mv)
+    }
+
+    fn aspiration_search(&mut self, depth: usize, estimation: i32, end_time: Instant) -> Result {
+        let delta = 30; // 0.3 of the pawn
+        let pos_inf = 1e9 as i32;
+        let neg_inf = -1e9 as i32;
+        let alpha: i32 = estimation - delta;
+        let beta: i32 = estimation + delta;
+        let qdepth = 2 * depth;
+
+        if depth < 3 {
+            return self.negamax(
+                self.pos.clone(),
+                depth,
+                qdepth,
+                0,
+                neg_inf,
+                pos_inf,
+                end_time,
+                false
+            );
+        }
+
+        let mut result = self.negamax(self.pos.clone(), depth, qdepth, 0, alpha, beta, end_time, false);
+
+        if result.score >= beta {
+            result = self.negamax(
+                self.pos.clone(),
+                depth,
+                qdepth,
+                0,
+                result.score,
+                pos_inf,
+                end_time,
+                false
+            );
+        } else if result.score <= alpha {
+            result = self.negamax(
+                self.pos.clone(),
+                depth,
+                qdepth,
+                0,
+                neg_inf,
+                result.score,
+                end_time,
+                false
+            );
+        }
+
+        if result.score <= alpha || result.score >= beta {
+            result = self.negamax(
+                self.pos.clone(),
+                depth,
+                qdepth,
+                0,
+                neg_inf,
+                pos_inf,
+                end_time,
+                false
+            );
+        }
+        result
+    }
 }
 
-// cargo flamegraph --unit-test -- mod_minmax_tests::minmax_depth8_inital_position
 #[cfg(test)]
 mod mod_minmax_tests {
     use super::*;
     use std::str::FromStr;
 
+    #[cfg(feature = "nnue")]
     #[test]
-    fn minmax_depth8_inital_position() {
-        let pos = Board::default();
+    fn search_with_nnue() {
+        use crate::features::nnue::Network;
+        let pos = Board::from_str("r3k2r/pPppqpb1/bn2pnp1/2pPN3/1p2P3/2N2Q1p/PPPBBPPP/R3K2R w KQkq c6 0 1").unwrap();
         let mut engine = MinMaxEngine::new(pos, &Options::new());
-        let start_time = Instant::now();
-        let max_time = start_time.add(Duration::from_secs(60 * 10));
-        let depth = 8;
-        let result = engine.negamax(pos, depth, 2 * depth, 0, -1e9 as i32, 1e9 as i32, max_time, false);
-        let duration = Instant::now().duration_since(start_time);
-
-        println!("best move: {:?}", result.chosen_move);
-        let m = result.chosen_move.unwrap();
-        println!(
-            "{:?} {:?}",
-            m.get_source().get_file(),
-            m.get_source().get_rank()
-        );
-        println!(
-            "{:?} {:?}",
-            m.get_dest().get_file(),
-            m.get_dest().get_rank()
-        );
-
-        send_info(String::from("Score ") + &*result.score.to_string());
-        println!("Evaluation_cnt={}", engine.evaluations_cnt);
-
-        let evaluations_per_second = engine.evaluations_cnt as f32 / duration.as_secs_f32();
-        println!("Evaluations per second = {}", evaluations_per_second);
+        engine.nnue = Some(NnueState::new(Network::random(32, 1)));
+        let end_time = Instant::now().add(Duration::from_secs(600));
+        // in debug builds every network evaluation is compared with a refresh of the accumulator
+        let result = engine.negamax(pos, 3, 4, 0, -1e9 as i32, 1e9 as i32, end_time, false);
+        assert!(result.computed && result.chosen_move.is_some());
     }
 
     #[test]
@@ -377,6 +707,68 @@ mod mod_minmax_tests {
             .unwrap();
         quiescence(&mut engine, pos, 10, 0, -1e9 as i32, 1e9 as i32, end_time);
     }
+
+    #[test]
+    fn excluded_root_moves_are_skipped() {
+        let pos = Board::from_str("r1b2b1r/pp3Qp1/2nkn2p/3ppP1p/P1p5/1NP1NB2/1PP1PPR1/1K1R3q w - - 0 1")
+            .unwrap();
+        let mut engine = MinMaxEngine::new(pos, &Options::new());
+        let end_time = Instant::now().add(Duration::from_secs(60 * 10));
+
+        let first = engine.negamax(pos, 1, 0, 0, -1e9 as i32, 1e9 as i32, end_time, false);
+        assert!(first.score > 1e8 as i32);
+
+        let mate = first.chosen_move.unwrap();
+        engine.excluded_root_moves.push(mate);
+        let second = engine.negamax(pos, 1, 0, 0, -1e9 as i32, 1e9 as i32, end_time, false);
+        assert_ne!(second.chosen_move, Some(mate));
+        assert!(second.score < first.score);
+    }
+
+    #[test]
+    fn search_moves_restrict_root() {
+        let pos = Board::from_str("r1b2b1r/pp3Qp1/2nkn2p/3ppP1p/P1p5/1NP1NB2/1PP1PPR1/1K1R3q w - - 0 1")
+            .unwrap();
+        let mut engine = MinMaxEngine::new(pos, &Options::new());
+        let end_time = Instant::now().add(Duration::from_secs(60 * 10));
+        let allowed = vec![
+            ChessMove::from_str("a4a5").unwrap(),
+            ChessMove::from_str("b1a2").unwrap(),
+        ];
+
+        engine.search_moves = allowed.clone();
+        let result = engine.negamax(pos, 2, 0, 0, -1e9 as i32, 1e9 as i32, end_time, false);
+        assert!(allowed.contains(&result.chosen_move.unwrap()));
+    }
+
+    #[test]
+    fn tablebase_results_are_used() {
+        use crate::features::syzygy::{write_krk_fixture, TB_PLY_PENALTY, TB_WIN_SCORE};
+        let dir = std::env::temp_dir().join(format!("rdzawa_engine_syzygy_{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        write_krk_fixture(&dir);
+        let mut options = Options::new();
+        options.add_value("SyzygyPath".to_string(), dir.to_str().unwrap().to_string());
+        let end_time = Instant::now().add(Duration::from_secs(60 * 10));
+
+        let pos = Board::from_str("8/8/8/4k3/8/8/8/R3K3 w - - 0 1").unwrap();
+        let mut engine = MinMaxEngine::new(pos, &options);
+        let result = engine.negamax(pos, 2, 0, 0, -1e9 as i32, 1e9 as i32, end_time, false);
+        assert_eq!(result.score, TB_WIN_SCORE - TB_PLY_PENALTY);
+        assert_eq!(score_to_uci(result.score), "cp 19999");
+        assert!(engine.tb_hits > 0);
+
+        engine.pos = Board::from_str("7k/8/6K1/8/8/8/8/R7 w - - 0 1").unwrap();
+        let mv = engine.find_best_move(TimeLimits { soft: 1000, hard: 1000 });
+        assert_eq!(mv, ChessMove::from_str("a1a8").unwrap());
+        assert_eq!(engine.tb_hits, 1);
+
+        engine.tb_probe_limit = 2;
+        engine.tb_hits = 0;
+        engine.negamax(pos, 2, 0, 0, -1e9 as i32, 1e9 as i32, end_time, false);
+        assert_eq!(engine.tb_hits, 0);
+        std::fs::remove_dir_all(dir).unwrap();
+    }
 }
 
 mod checkmate_tests {
@@ -437,5 +829,20 @@ mod checkmate_tests {
                 assert!(result.score > 1e8 as i32);
             }
         }
+
+        let mut engine = MinMaxEngine::new(board, &Options::new());
+        let max_time = Instant::now().add(Duration::from_secs(60 * 10));
+        let result = engine.find_mate(moves_to_mate, max_time).unwrap();
+        let mate = crate::features::evaluation::moves_to_mate(result.score);
+        assert_eq!(mate, Some(moves_to_mate as i32));
+        assert!(!engine.mate_search);
+    }
+
+    #[test]
+    fn mate_search_rules_out_shorter_mate() {
+        let board = Board::from_str("r4r1k/1R1R2p1/7p/8/8/3Q1Ppq/P7/6K1 w - - 0 1").unwrap();
+        let mut engine = MinMaxEngine::new(board, &Options::new());
+        let max_time = Instant::now().add(Duration::from_secs(60 * 10));
+        assert!(engine.find_mate(3, max_time).is_none());
     }
 }
@@ -2,14 +2,14 @@ use chess::{Board, ChessMove, MoveGen};
 use crate::engine::Engine;
 use crate::io::output::send_move;
 use rand::seq::SliceRandom;
-use crate::io::uci::Position;
+use crate::io::uci::{GoCommand, Position};
 
 pub struct RandomEngine {
     pub pos: Board,
 }
 
 impl Engine for RandomEngine {
-    fn start(&mut self, _time: u64) {
+    fn start(&mut self, _command: GoCommand) {
         send_move(self.next_move())
     }
 
@@ -28,6 +28,12 @@ impl Engine for RandomEngine {
     fn evaluate(&self) -> i32 {
         0
     }
+
+    fn position(&self) -> Board {
+        self.pos
+    }
+
+    fn learn(&mut self, _white_result: f64) {}
 }
 
 impl RandomEngine {
@@ -0,0 +1,129 @@
+use crate::io::options::Options;
+use crate::minmax_engine::MinMaxEngine;
+use chess::Board;
+use std::str::FromStr;
+use std::time::Instant;
+
+// cargo flamegraph -- bench 4
+
+pub const DEFAULT_DEPTH: usize = 3;
+
+const USAGE: &str = "usage: bench [depth]";
+
+/// Middlegames, endgames and tactical positions searched by the bench.
+pub const BENCH_FENS: [&str; 40] = [
+    "rnbqkbnr/pppppppp/8/8/8/8/PPPPPPPP/RNBQKBNR w KQkq - 0 1",
+    "r3k2r/p1ppqpb1/bn2pnp1/3PN3/1p2P3/2N2Q1p/PPPBBPPP/R3K2R w KQkq - 0 1",
+    "8/2p5/3p4/KP5r/1R3p1k/8/4P1P1/8 w - - 0 1",
+    "r3k2r/2pb1ppp/2pp1q2/p7/1nP1B3/1P2P3/P2N1PPP/R2QK2R w KQkq a6 0 14",
+    "4rrk1/2p1b1p1/p1p3q1/4p3/2P2n1p/1P1NR2P/PB3PP1/3R1QK1 b - - 2 24",
+    "r3qbrk/6p1/2b2pPp/p3pP1Q/PpPpP2P/3P1B2/2PB3K/R5R1 w - - 16 42",
+    "6k1/1R3p2/6p1/2Bp3p/3P2q1/P7/1P2rQ1K/5R2 b - - 4 44",
+    "8/8/1p2k1p1/3p3p/1p1P1P1P/1P2PK2/8/8 w - - 3 54",
+    "7r/2p3k1/1p1p1qp1/1P1Bp3/p1P2r1P/P7/4R3/Q4RK1 w - - 0 36",
+    "r1bq1rk1/pp2b1pp/n1pp1n2/3P1p2/2P1p3/2N1P2N/PP2BPPP/R1BQ1RK1 b - - 2 10",
+    "3r3k/2r4p/1p1b3q/p4P2/P2Pp3/1B2P3/3BQ1RP/6K1 w - - 3 87",
+    "2r4r/1p4k1/1Pnp4/3Qb1pq/8/4BpPp/5P2/2RR1BK1 w - - 0 42",
+    "4q1bk/6b1/7p/p1p4p/PNPpP2P/KN4P1/3Q4/4R3 b - - 0 37",
+    "2q3r1/1r2pk2/pp3pp1/2pP3p/P1Pb1BbP/1P4Q1/R3NPP1/4R1K1 w - - 2 34",
+    "1r2r2k/1b4q1/pp5p/2pPp1p1/P3Pn2/1P1B1Q1P/2R3P1/4BR1K b - - 1 37",
+    "r3kbbr/pp1n1p1P/3ppnp1/q5N1/1P1pP3/P1N1B3/2P1QP2/R3KB1R b KQkq b3 0 17",
+    "8/6pk/2b1Rp2/3r4/1R1B2PP/P5K1/8/2r5 b - - 16 42",
+    "1r4k1/4ppb1/2n1b1qp/pB4p1/1n1BP1P1/7P/2PNQPK1/3RN3 w - - 8 29",
+    "8/p2B4/PkP5/4p1pK/4Pb1p/5P2/8/8 w - - 29 68",
+    "3r4/ppq1ppkp/4bnp1/2pN4/2P1P3/1P4P1/PQ3PBP/R4K2 b - - 2 20",
+    "5rr1/4n2k/4q2P/P1P2n2/3B1p2/4pP2/2N1P3/1RR1K2Q w - - 1 49",
+    "1r5k/2pq2p1/3p3p/p1pP4/4QP2/PP1R3P/6PK/8 w - - 1 51",
+    "q5k1/5ppp/1r3bn1/1B6/P1N2P2/BQ2P1P1/5K1P/8 b - - 2 34",
+    "r1b2k1r/5n2/p4q2/1ppn1Pp1/3pp1p1/NP2P3/P1PPBK2/1RQN2R1 b - - 0 22",
+    "r1bqk2r/pppp1ppp/5n2/4b3/4P3/P1N5/1PP2PPP/R1BQKB1R w KQkq - 0 5",
+    "r1bqr1k1/pp1p1ppp/2p5/8/3N1Q2/P2BB3/1PP2PPP/R3K2n b Q - 1 12",
+    "r1bq2k1/p4r1p/1pp2pp1/3p4/1P1B3Q/P2B1N2/2P3PP/4R1K1 b - - 2 19",
+    "r4qk1/6r1/1p4p1/2ppBbN1/1p5Q/P7/2P3PP/5RK1 w - - 2 25",
+    "r7/6k1/1p6/2pp1p2/7Q/8/p1P2K1P/8 w - - 0 32",
+    "r3k2r/ppp1pp1p/2nqb1pn/3p4/4P3/2PP4/PP1NBPPP/R2QK1NR w KQkq - 1 5",
+    "3r1rk1/1pp1pn1p/p1n1q1p1/3p4/Q3P3/2P5/PP1NBPPP/4RRK1 w - - 0 12",
+    "5rk1/1pp1pn1p/p3Brp1/8/1n6/5N2/PP3PPP/2R2RK1 w - - 2 20",
+    "8/1p2pk1p/p1p1r1p1/3n4/8/5R2/PP3PPP/4R1K1 b - - 3 27",
+    "8/4pk2/1p1r2p1/p1p4p/Pn5P/3R4/1P3PP1/4RK2 w - - 1 33",
+    "8/8/1p1kp1p1/p1pr1n1p/P6P/1R4P1/1P3PK1/1R6 b - - 15 45",
+    "8/8/3P3k/8/1p6/8/1P6/1K3n2 b - - 0 1",
+    "8/R7/2q5/8/6k1/8/1P5p/K6R w - - 0 124",
+    "6k1/3b3r/1p1p4/p1n2p2/1PPNpP1q/P3Q1p1/1R1RB1P1/5K2 b - - 0 1",
+    "r2r1n2/pp2bk2/2p1p2p/3q4/3PN1QP/2P3R1/P4PP1/5RK1 w - - 0 1",
+    "8/8/8/8/5kp1/P7/8/1K1N4 w - - 0 1",
+];
+
+/// Nodes searched in every bench position, each searched by a new engine with an empty transposition table.
+pub fn bench(options: &Options, depth: usize) -> Vec<u64> {
+    let mut options = options.clone();
+    // the bench measures the search, not the book
+    options.add_value("openings".to_string(), String::new());
+    BENCH_FENS
+        .iter()
+        .map(|fen| {
+            let mut engine = MinMaxEngine::new(Board::from_str(fen).unwrap(), &options);
+            engine.search_depth(depth, None);
+            engine.evaluations_cnt as u64
+        })
+        .collect()
+}
+
+/// Nodes of every position followed by the total, the time and the nodes per second,
+/// the total is the signature of the search.
+pub fn bench_report(options: &Options, depth: usize) -> String {
+    let start = Instant::now();
+    let nodes = bench(options, depth);
+    let elapsed = start.elapsed();
+    let total: u64 = nodes.iter().sum();
+    let mut report: String = BENCH_FENS
+        .iter()
+        .zip(nodes.iter())
+        .enumerate()
+        .map(|(i, (fen, nodes))| format!("Position {}/{}: {}: {}\n", i + 1, BENCH_FENS.len(), fen, nodes))
+        .collect();
+    report += &*format!(
+        "\nDepth: {}\nNodes searched: {}\nTime: {} ms, {} nps",
+        depth,
+        total,
+        elapsed.as_millis(),
+        (total as f64 / elapsed.as_secs_f64().max(1e-6)) as u64
+    );
+    report
+}
+
+pub fn run(args: &[String]) -> Result<(), String> {
+    let depth = match args.first() {
+        Some(depth) => depth.parse().map_err(|_| USAGE.to_string())?,
+        None => DEFAULT_DEPTH,
+    };
+    if args.len() > 1 {
+        return Err(USAGE.to_string());
+    }
+    println!("{}", bench_report(&Options::new(), depth));
+    Ok(())
+}
+
+#[cfg(test)]
+mod bench_tests {
+    use super::*;
+    use chess::BoardStatus;
+
+    #[test]
+    fn positions_are_playable() {
+        for fen in BENCH_FENS {
+            let board = Board::from_str(fen).unwrap();
+            assert_eq!(board.status(), BoardStatus::Ongoing, "{}", fen);
+        }
+    }
+
+    // signature of the search, to be updated on purpose with every change of the searched nodes
+    const SIGNATURE: u64 = 1_294_433;
+
+    #[test]
+    fn node_count_is_reproducible() {
+        let nodes = bench(&Options::new(), DEFAULT_DEPTH);
+        assert!(nodes.iter().all(|nodes| *nodes > 0));
+        assert_eq!(nodes.iter().sum::<u64>(), SIGNATURE);
+    }
+}
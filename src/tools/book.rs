@@ -0,0 +1,69 @@
+use crate::features::position_book::PositionBook;
+use crate::tools::{book_build, book_check};
+use std::fs;
+
+const USAGE: &str = "usage: book convert <tree.json> <positions.json>\n       book build <games.pgn>... [options]\n       book check <book.json>";
+
+/// Converts the JSON tree of moves into the book keyed by position.
+fn convert(args: &[String]) -> Result<(), String> {
+    if args.len() != 2 {
+        return Err(USAGE.to_string());
+    }
+    let (input, output) = (&args[0], &args[1]);
+    let content = fs::read_to_string(input).map_err(|err| "Cannot read |".to_string() + input + "|: " + &*err.to_string())?;
+    let tree = json::parse(&content).map_err(|err| "Cannot parse |".to_string() + input + "|: " + &*err.to_string())?;
+    if PositionBook::is_position_book(&tree) {
+        return Err("|".to_string() + input + "| is already keyed by position");
+    }
+    let book = PositionBook::from_tree(&tree);
+    book.save(output)?;
+    println!("{} positions written to {}", book.len(), output);
+    Ok(())
+}
+
+pub fn run(args: &[String]) -> Result<(), String> {
+    match args.first().map(|command| command.as_str()) {
+        Some("convert") => convert(&args[1..]),
+        Some("build") => book_build::run(&args[1..]),
+        Some("check") => book_check::run(&args[1..]),
+        _ => Err(USAGE.to_string()),
+    }
+}
+
+#[cfg(test)]
+mod book_tool_tests {
+    use super::*;
+    use crate::features::opening_book::{BookSelection, OpeningBook};
+    use chess::{Board, ChessMove};
+    use std::str::FromStr;
+
+    #[test]
+    fn converted_book_is_used_after_transposition_and_fen() {
+        let dir = std::env::temp_dir().join(format!("rdzawa_book_convert_{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        let tree = dir.join("tree.json").to_str().unwrap().to_string();
+        let positions = dir.join("positions.json").to_str().unwrap().to_string();
+        fs::write(
+            &tree,
+            r#"{"best": "d2d4", "d2d4": {"best": "g8f6", "g8f6": {"best": "c2c4", "c2c4": {"best": "e7e6"}}}}"#,
+        )
+        .unwrap();
+
+        run(&["convert".to_string(), tree.clone(), positions.clone()]).unwrap();
+        let book = OpeningBook::new(&positions, BookSelection::best());
+
+        // 1. c4 Nf6 2. d4 is not in the tree, but transposes into it
+        let transposed = ["c2c4", "g8f6", "d2d4"]
+            .iter()
+            .fold(Board::default(), |board, mv| board.make_move_new(ChessMove::from_str(mv).unwrap()));
+        let result = book.clone().try_get_best(&transposed, &[]);
+        assert_eq!(result.mv, Some(ChessMove::from_str("e7e6").unwrap()));
+
+        let from_fen = Board::from_str("rnbqkb1r/pppppppp/5n2/8/3P4/8/PPP1PPPP/RNBQKBNR w KQkq - 1 2").unwrap();
+        let result = book.after_fen().try_get_best(&from_fen, &[]);
+        assert_eq!(result.mv, Some(ChessMove::from_str("c2c4").unwrap()));
+
+        assert!(run(&["convert".to_string(), positions.clone(), tree.clone()]).is_err());
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
@@ -0,0 +1,323 @@
+use crate::features::polyglot::{encode_move, polyglot_key, PolyglotBook, PolyglotEntry};
+use crate::features::position_book::{TREE_BEST_KEY, TREE_WEIGHT_KEY};
+use crate::io::pgn::{parse_pgn, PgnGame};
+use chess::{Board, ChessMove, Color};
+use json::JsonValue;
+use std::collections::HashMap;
+use std::fs;
+
+const DEFAULT_DEPTH: usize = 20;
+const DEFAULT_MIN_COUNT: u32 = 2;
+
+const USAGE: &str = "usage: book build <games.pgn>... [--tree <book.json>] [--polyglot <book.bin>] \
+[--depth <plies>] [--min-count <n>] [--min-score <percent>]";
+
+/// Games and points of a move, a win counts 2 points and a draw 1 for the side playing it.
+#[derive(Clone, Copy, Default)]
+struct MoveStats {
+    count: u32,
+    scored: u32,
+    points: u32,
+}
+
+impl MoveStats {
+    // percentage of the points of the games with a known result
+    fn score(&self) -> Option<f64> {
+        (self.scored > 0).then(|| 50.0 * self.points as f64 / self.scored as f64)
+    }
+}
+
+struct PositionStats {
+    board: Board,
+    moves: Vec<(ChessMove, MoveStats)>,
+}
+
+/// Moves kept in the book, the score only filters moves with known results.
+pub struct BookFilter {
+    pub min_count: u32,
+    pub min_score: f64,
+}
+
+impl BookFilter {
+    fn keeps(&self, stats: &MoveStats) -> bool {
+        stats.count >= self.min_count && stats.score().is_none_or(|score| score >= self.min_score)
+    }
+}
+
+/// Played moves of the positions of a game collection, keyed by the Polyglot key.
+#[derive(Default)]
+pub struct BookStats {
+    positions: HashMap<u64, PositionStats>,
+}
+
+impl BookStats {
+    /// Counts the moves of the first `depth` plies of the game.
+    pub fn add_game(&mut self, game: &PgnGame, depth: usize) {
+        let mut board = game.start;
+        for mv in game.moves.iter().take(depth) {
+            let position = self
+                .positions
+                .entry(polyglot_key(&board))
+                .or_insert_with(|| PositionStats { board, moves: Vec::new() });
+            let index = match position.moves.iter().position(|(known, _)| known == mv) {
+                Some(index) => index,
+                None => {
+                    position.moves.push((*mv, MoveStats::default()));
+                    position.moves.len() - 1
+                }
+            };
+            let stats = &mut position.moves[index].1;
+            stats.count += 1;
+            if let Some(result) = game.result {
+                let points = if board.side_to_move() == Color::White { result } else { 1.0 - result };
+                stats.scored += 1;
+                stats.points += (2.0 * points) as u32;
+            }
+            board = board.make_move_new(*mv);
+        }
+    }
+
+    /// Moves of the position kept by the filter with their counts, the most played first.
+    pub fn book_moves(&self, board: &Board, filter: &BookFilter) -> Vec<(ChessMove, u32)> {
+        let Some(position) = self.positions.get(&polyglot_key(board)) else {
+            return Vec::new();
+        };
+        let mut moves: Vec<(ChessMove, u32)> = position
+            .moves
+            .iter()
+            .filter(|(_, stats)| filter.keeps(stats))
+            .map(|(mv, stats)| (*mv, stats.count))
+            .collect();
+        moves.sort_by(|(_, a), (_, b)| b.cmp(a));
+        moves
+    }
+
+    /// Number of positions with book moves and number of their moves.
+    pub fn size(&self, filter: &BookFilter) -> (usize, usize) {
+        self.positions
+            .values()
+            .map(|position| self.book_moves(&position.board, filter).len())
+            .filter(|moves| *moves > 0)
+            .fold((0, 0), |(positions, moves), count| (positions + 1, moves + count))
+    }
+
+    /// JSON tree of moves from the initial position, the most played move is `best`.
+    pub fn to_tree(&self, depth: usize, filter: &BookFilter) -> JsonValue {
+        self.tree_node(&Board::default(), depth, filter)
+    }
+
+    fn tree_node(&self, board: &Board, depth: usize, filter: &BookFilter) -> JsonValue {
+        let mut node = JsonValue::new_object();
+        let moves = if depth > 0 { self.book_moves(board, filter) } else { Vec::new() };
+        if let Some((best, _)) = moves.first() {
+            node[TREE_BEST_KEY] = best.to_string().into();
+        }
+        for (mv, count) in moves {
+            let mut child = self.tree_node(&board.make_move_new(mv), depth - 1, filter);
+            child[TREE_WEIGHT_KEY] = count.into();
+            node[mv.to_string().as_str()] = child;
+        }
+        node
+    }
+
+    pub fn to_polyglot(&self, filter: &BookFilter) -> PolyglotBook {
+        let entries = self
+            .positions
+            .iter()
+            .flat_map(|(key, position)| {
+                self.book_moves(&position.board, filter).into_iter().map(|(mv, count)| PolyglotEntry {
+                    key: *key,
+                    mv: encode_move(&position.board, mv),
+                    weight: count.min(u16::MAX as u32) as u16,
+                    learn: 0,
+                })
+            })
+            .collect();
+        PolyglotBook::from_entries(entries)
+    }
+}
+
+struct BuildArgs {
+    pgn_files: Vec<String>,
+    tree: Option<String>,
+    polyglot: Option<String>,
+    depth: usize,
+    filter: BookFilter,
+}
+
+fn parse_args(args: &[String]) -> Result<BuildArgs, String> {
+    let mut build_args = BuildArgs {
+        pgn_files: Vec::new(),
+        tree: None,
+        polyglot: None,
+        depth: DEFAULT_DEPTH,
+        filter: BookFilter { min_count: DEFAULT_MIN_COUNT, min_score: 0.0 },
+    };
+    let mut i = 0;
+    while i < args.len() {
+        if !args[i].starts_with("--") {
+            build_args.pgn_files.push(args[i].clone());
+            i += 1;
+            continue;
+        }
+        let value = args.get(i + 1).ok_or(USAGE.to_string())?;
+        match args[i].as_str() {
+            "--tree" => build_args.tree = Some(value.clone()),
+            "--polyglot" => build_args.polyglot = Some(value.clone()),
+            "--depth" => build_args.depth = value.parse().map_err(|_| USAGE.to_string())?,
+            "--min-count" => build_args.filter.min_count = value.parse().map_err(|_| USAGE.to_string())?,
+            "--min-score" => build_args.filter.min_score = value.parse().map_err(|_| USAGE.to_string())?,
+            _ => return Err(USAGE.to_string()),
+        }
+        i += 2;
+    }
+    if build_args.pgn_files.is_empty() || (build_args.tree.is_none() && build_args.polyglot.is_none()) {
+        return Err(USAGE.to_string());
+    }
+    Ok(build_args)
+}
+
+/// Builds a book from PGN files, as a JSON tree, a Polyglot book or both.
+pub fn run(args: &[String]) -> Result<(), String> {
+    let args = parse_args(args)?;
+    let mut stats = BookStats::default();
+    let (mut games, mut incomplete) = (0, 0);
+    for path in args.pgn_files.iter() {
+        let content = fs::read_to_string(path).map_err(|err| "Cannot read |".to_string() + path + "|: " + &*err.to_string())?;
+        for game in parse_pgn(&content) {
+            games += 1;
+            incomplete += !game.complete as usize;
+            stats.add_game(&game, args.depth);
+        }
+    }
+    println!("Read {} games, {} stopped at a move which cannot be read", games, incomplete);
+
+    let (positions, moves) = stats.size(&args.filter);
+    println!("Book has {} positions and {} moves", positions, moves);
+    if let Some(path) = &args.tree {
+        fs::write(path, stats.to_tree(args.depth, &args.filter).pretty(2))
+            .map_err(|err| "Cannot write |".to_string() + path + "|: " + &*err.to_string())?;
+        println!("Tree written to {}", path);
+    }
+    if let Some(path) = &args.polyglot {
+        let book = stats.to_polyglot(&args.filter);
+        book.save(path)?;
+        println!("Polyglot book with {} entries written to {}", book.len(), path);
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod book_build_tests {
+    use super::*;
+    use crate::features::opening_book::{BookSelection, OpeningBook};
+    use std::str::FromStr;
+
+    const GAMES: &str = r#"[Result "1-0"]
+1. e4 e5 2. Nf3 Nc6 1-0
+
+[Result "1/2-1/2"]
+1. e4 e5 2. Nf3 Nf6 1/2-1/2
+
+[Result "0-1"]
+1. e4 c5 2. Nf3 d6 0-1
+
+[Result "0-1"]
+1. d4 d5 2. c4 0-1
+
+[Result "0-1"]
+1. d4 d5 2. c4 0-1
+
+[Result "1-0"]
+1. Nf3 d5 2. d4 1-0
+"#;
+
+    fn mv(mv: &str) -> ChessMove {
+        ChessMove::from_str(mv).unwrap()
+    }
+
+    fn stats() -> BookStats {
+        let mut stats = BookStats::default();
+        for game in parse_pgn(GAMES) {
+            stats.add_game(&game, 3);
+        }
+        stats
+    }
+
+    #[test]
+    fn filters_moves_by_count_and_score() {
+        let stats = stats();
+        let all = BookFilter { min_count: 1, min_score: 0.0 };
+        assert_eq!(stats.book_moves(&Board::default(), &all), vec![(mv("e2e4"), 3), (mv("d2d4"), 2), (mv("g1f3"), 1)]);
+        let frequent = BookFilter { min_count: 2, min_score: 0.0 };
+        assert_eq!(stats.book_moves(&Board::default(), &frequent), vec![(mv("e2e4"), 3), (mv("d2d4"), 2)]);
+        // 1. d4 lost both games, 1. e4 scored 1.5 of 3
+        let scoring = BookFilter { min_count: 1, min_score: 50.0 };
+        assert_eq!(stats.book_moves(&Board::default(), &scoring), vec![(mv("e2e4"), 3), (mv("g1f3"), 1)]);
+        // moves after the depth are not counted
+        let after_nf3 = ["e2e4", "e7e5", "g1f3"].iter().fold(Board::default(), |board, m| board.make_move_new(mv(m)));
+        assert!(stats.book_moves(&after_nf3, &all).is_empty());
+    }
+
+    #[test]
+    fn writes_tree_and_polyglot_books() {
+        let dir = std::env::temp_dir().join(format!("rdzawa_book_build_{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        let path = |name: &str| dir.join(name).to_str().unwrap().to_string();
+        fs::write(path("games.pgn"), GAMES).unwrap();
+        let args: Vec<String> = ["games.pgn", "--tree", "tree.json", "--polyglot", "book.bin", "--depth", "2", "--min-count", "1"]
+            .iter()
+            .map(|arg| if arg.contains('.') { path(arg) } else { arg.to_string() })
+            .collect();
+        run(&args).unwrap();
+
+        let tree = json::parse(&fs::read_to_string(path("tree.json")).unwrap()).unwrap();
+        assert_eq!(tree["best"], "e2e4");
+        assert_eq!(tree["e2e4"]["weight"], 3);
+        assert_eq!(tree["e2e4"]["best"], "e7e5");
+        assert_eq!(tree["e2e4"]["c7c5"]["weight"], 1);
+        assert!(!tree["e2e4"]["e7e5"].has_key("g1f3"));
+        // the book stops at the depth
+        assert_eq!(tree["g1f3"]["best"], "d7d5");
+        assert!(!tree["g1f3"]["d7d5"].has_key("best"));
+
+        let polyglot = PolyglotBook::load(&path("book.bin")).unwrap();
+        assert_eq!(polyglot.moves(&Board::default()), vec![(mv("e2e4"), 3), (mv("d2d4"), 2), (mv("g1f3"), 1)]);
+        let book = OpeningBook::new(&path("book.bin"), BookSelection::best());
+        assert_eq!(book.try_get_best(&Board::default(), &[]).mv, Some(mv("e2e4")));
+
+        assert!(run(&[path("games.pgn")]).is_err());
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn polyglot_books_use_the_keys_of_the_specification() {
+        // key, move and weight of the entries, e2e4 and e7e5 as written by other Polyglot tools
+        const START: (u64, u16) = (0x463B96181691FC9C, 0x031C);
+        const AFTER_E4: (u64, u16) = (0x823C9B50FD114196, 0x0D24);
+        let path = std::env::temp_dir().join(format!("rdzawa_book_build_keys_{}.bin", std::process::id()));
+        let path = path.to_str().unwrap().to_string();
+
+        let mut stats = BookStats::default();
+        for game in parse_pgn("1. e4 e5 *\n") {
+            stats.add_game(&game, 2);
+        }
+        stats.to_polyglot(&BookFilter { min_count: 1, min_score: 0.0 }).save(&path).unwrap();
+        let data = fs::read(&path).unwrap();
+        let entries: Vec<(u64, u16)> = data
+            .chunks(16)
+            .map(|entry| (u64::from_be_bytes(entry[..8].try_into().unwrap()), u16::from_be_bytes([entry[8], entry[9]])))
+            .collect();
+        assert_eq!(entries, vec![START, AFTER_E4]);
+
+        let external: Vec<u8> = [(START, 7u16), (AFTER_E4, 3u16)]
+            .iter()
+            .flat_map(|((key, mv), weight)| [&key.to_be_bytes()[..], &mv.to_be_bytes(), &weight.to_be_bytes(), &[0; 4]].concat())
+            .collect();
+        fs::write(&path, external).unwrap();
+        let book = PolyglotBook::load(&path).unwrap();
+        assert_eq!(book.moves(&Board::default()), vec![(mv("e2e4"), 7)]);
+        assert_eq!(book.moves(&Board::default().make_move_new(mv("e2e4"))), vec![(mv("e7e5"), 3)]);
+        fs::remove_file(&path).unwrap();
+    }
+}
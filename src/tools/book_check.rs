@@ -0,0 +1,233 @@
+use crate::features::polyglot::polyglot_key;
+use crate::features::position_book::{parse_move, PositionBook, POSITIONS_KEY, TREE_BEST_KEY, TREE_VALUES_KEY, TREE_WEIGHT_KEY};
+use chess::Board;
+use json::JsonValue;
+use std::collections::HashMap;
+use std::fs;
+use std::str::FromStr;
+
+const USAGE: &str = "usage: book check <book.json>";
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Problem {
+    // a move which cannot be played in the position
+    IllegalMove,
+    // `best` is a legal move without a child, though the position has other children
+    MissingChild,
+    // a position of the book without any playable move
+    DeadEnd,
+    // a position reached again by another line of the tree
+    Transposition,
+    // a key of a book keyed by position which is not a FEN
+    InvalidPosition,
+}
+
+impl Problem {
+    fn name(&self) -> &'static str {
+        match self {
+            Problem::IllegalMove => "illegal move",
+            Problem::MissingChild => "best move without child",
+            Problem::DeadEnd => "dead end",
+            Problem::Transposition => "transposition",
+            Problem::InvalidPosition => "invalid position",
+        }
+    }
+
+    // problems which take the engine out of book, the others are only reported
+    fn is_error(&self) -> bool {
+        matches!(self, Problem::IllegalMove | Problem::DeadEnd | Problem::InvalidPosition)
+    }
+}
+
+#[derive(Default)]
+pub struct BookReport {
+    pub problems: Vec<(Problem, String)>,
+    pub nodes: usize,
+    pub max_depth: usize,
+    // positions with children and the number of their children
+    pub inner_nodes: usize,
+    pub children: usize,
+    // first line reaching every position
+    lines: HashMap<u64, String>,
+}
+
+fn line_name(line: &[String]) -> String {
+    if line.is_empty() {
+        return "root".to_string();
+    }
+    line.join(" ")
+}
+
+impl BookReport {
+    pub fn count(&self, problem: Problem) -> usize {
+        self.problems.iter().filter(|(kind, _)| *kind == problem).count()
+    }
+
+    pub fn errors(&self) -> usize {
+        self.problems.iter().filter(|(kind, _)| kind.is_error()).count()
+    }
+
+    /// Checks the JSON tree of moves from the initial position.
+    pub fn check_tree(tree: &JsonValue) -> Self {
+        let mut report = BookReport::default();
+        report.walk(&Board::default(), tree, &mut Vec::new());
+        report
+    }
+
+    fn walk(&mut self, board: &Board, node: &JsonValue, line: &mut Vec<String>) {
+        self.nodes += 1;
+        self.max_depth = self.max_depth.max(line.len());
+        let name = line_name(line);
+        match self.lines.get(&polyglot_key(board)) {
+            Some(first) => self.problems.push((Problem::Transposition, name.clone() + " = " + first)),
+            None => {
+                self.lines.insert(polyglot_key(board), name.clone());
+            }
+        }
+
+        let mut playable = 0;
+        let mut children = Vec::new();
+        for (key, child) in node.entries() {
+            if [TREE_BEST_KEY, TREE_WEIGHT_KEY, TREE_VALUES_KEY].contains(&key) {
+                continue;
+            }
+            match parse_move(board, key) {
+                Some(mv) => children.push((mv, key, child)),
+                None => self.problems.push((Problem::IllegalMove, name.clone() + ": " + key)),
+            }
+        }
+        playable += children.len();
+        if node.has_key(TREE_BEST_KEY) {
+            let best = node[TREE_BEST_KEY].as_str().unwrap_or("");
+            match parse_move(board, best) {
+                Some(mv) if !children.iter().any(|(child, _, _)| *child == mv) => {
+                    playable += 1;
+                    if !children.is_empty() {
+                        self.problems.push((Problem::MissingChild, name.clone() + ": " + best));
+                    }
+                }
+                Some(_) => {}
+                None => self.problems.push((Problem::IllegalMove, name.clone() + ": best " + &*node[TREE_BEST_KEY].dump())),
+            }
+        }
+        if playable == 0 {
+            self.problems.push((Problem::DeadEnd, name));
+        }
+        if !children.is_empty() {
+            self.inner_nodes += 1;
+            self.children += children.len();
+        }
+        for (mv, key, child) in children {
+            line.push(key.to_string());
+            self.walk(&board.make_move_new(mv), child, line);
+            line.pop();
+        }
+    }
+
+    /// Checks the positions and moves of a book keyed by position.
+    pub fn check_positions(json: &JsonValue) -> Self {
+        let mut report = BookReport::default();
+        for (fen, node) in json[POSITIONS_KEY].entries() {
+            report.nodes += 1;
+            let Ok(board) = Board::from_str(&(fen.to_string() + " 0 1")) else {
+                report.problems.push((Problem::InvalidPosition, fen.to_string()));
+                continue;
+            };
+            if let Some(first) = report.lines.insert(polyglot_key(&board), fen.to_string()) {
+                report.problems.push((Problem::Transposition, fen.to_string() + " = " + &*first));
+            }
+            let mut moves: Vec<&str> = node["moves"]
+                .members()
+                .map(|mv| mv.as_str().or(mv["move"].as_str()).unwrap_or(""))
+                .collect();
+            moves.extend(node[TREE_BEST_KEY].as_str());
+            let legal = moves.iter().filter(|mv| parse_move(&board, mv).is_some()).count();
+            for mv in moves.iter().filter(|mv| parse_move(&board, mv).is_none()) {
+                report.problems.push((Problem::IllegalMove, fen.to_string() + ": " + mv));
+            }
+            if legal == 0 {
+                report.problems.push((Problem::DeadEnd, fen.to_string()));
+            } else {
+                report.inner_nodes += 1;
+                report.children += node["moves"].len();
+            }
+        }
+        report
+    }
+}
+
+pub fn run(args: &[String]) -> Result<(), String> {
+    if args.len() != 1 {
+        return Err(USAGE.to_string());
+    }
+    let path = &args[0];
+    let content = fs::read_to_string(path).map_err(|err| "Cannot read |".to_string() + path + "|: " + &*err.to_string())?;
+    let json = json::parse(&content).map_err(|err| "Cannot parse |".to_string() + path + "|: " + &*err.to_string())?;
+    let report = if PositionBook::is_position_book(&json) {
+        BookReport::check_positions(&json)
+    } else {
+        BookReport::check_tree(&json)
+    };
+
+    for (problem, place) in report.problems.iter() {
+        println!("{}: {}", problem.name(), place);
+    }
+    println!(
+        "{} positions, {} unique, max depth {}, average branching {:.2}",
+        report.nodes,
+        report.lines.len(),
+        report.max_depth,
+        report.children as f64 / report.inner_nodes.max(1) as f64
+    );
+    for problem in [Problem::IllegalMove, Problem::MissingChild, Problem::DeadEnd, Problem::Transposition, Problem::InvalidPosition] {
+        println!("{}: {}", problem.name(), report.count(problem));
+    }
+    match report.errors() {
+        0 => Ok(()),
+        errors => Err(format!("{} errors in |{}|", errors, path)),
+    }
+}
+
+#[cfg(test)]
+mod book_check_tests {
+    use super::*;
+
+    #[test]
+    fn reports_problems_of_a_tree() {
+        let tree = json::parse(
+            r#"{"best": "e2e4", "values": {"max_depth": 2},
+                "e2e4": {"best": "e7e5", "c7c5": {"best": "g1f3"}, "e7e6": {"best": "d2d4", "d2d4": {"best": "d7d5"}}},
+                "d2d4": {"best": "e7e6", "e7e6": {"best": "e2e5", "e2e4": {}}},
+                "e2e5": {"best": "a7a6"}}"#,
+        )
+        .unwrap();
+        let report = BookReport::check_tree(&tree);
+        assert_eq!(report.count(Problem::IllegalMove), 2, "{:?}", report.problems);
+        assert!(report.problems.contains(&(Problem::IllegalMove, "root: e2e5".to_string())));
+        assert!(report.problems.contains(&(Problem::IllegalMove, "d2d4 e7e6: best \"e2e5\"".to_string())));
+        assert_eq!(report.count(Problem::MissingChild), 1);
+        assert!(report.problems.contains(&(Problem::MissingChild, "e2e4: e7e5".to_string())));
+        assert!(report.problems.contains(&(Problem::DeadEnd, "d2d4 e7e6 e2e4".to_string())));
+        assert!(report.problems.contains(&(Problem::Transposition, "d2d4 e7e6 e2e4 = e2e4 e7e6 d2d4".to_string())));
+        assert_eq!(report.errors(), 3);
+        assert_eq!(report.nodes, 8);
+        assert_eq!(report.max_depth, 3);
+        assert_eq!((report.inner_nodes, report.children), (5, 7));
+    }
+
+    #[test]
+    fn reports_problems_of_positions() {
+        let json = json::parse(
+            r#"{"positions": {
+                "rnbqkbnr/pppppppp/8/8/8/8/PPPPPPPP/RNBQKBNR w KQkq -": {"best": "e2e4", "moves": ["e2e4", {"move": "e2e5"}]},
+                "rnbqkbnr/pppppppp/8/8/4P3/8/PPPP1PPP/RNBQKBNR b KQkq -": {"best": "e2e4"},
+                "not a fen": {"best": "e2e4"}}}"#,
+        )
+        .unwrap();
+        let report = BookReport::check_positions(&json);
+        assert_eq!(report.count(Problem::IllegalMove), 2);
+        assert_eq!(report.count(Problem::DeadEnd), 1);
+        assert_eq!(report.count(Problem::InvalidPosition), 1);
+        assert_eq!(report.errors(), 4);
+    }
+}
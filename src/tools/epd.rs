@@ -0,0 +1,166 @@
+use crate::io::epd::{parse_epd, EpdPosition};
+use crate::io::options::Options;
+use crate::minmax_engine::MinMaxEngine;
+use chess::{Board, ChessMove};
+use std::fs;
+use std::time::Instant;
+
+const DEFAULT_TIME: u64 = 1000;
+
+const USAGE: &str = "usage: epd <suite.epd> [--time <ms>] [--depth <plies>] [--nodes <n>]";
+
+/// Limits of the search of every position, the search stops at the first one reached.
+pub struct SearchLimits {
+    pub time: Option<u64>,
+    pub depth: Option<usize>,
+    pub nodes: Option<u64>,
+}
+
+impl SearchLimits {
+    pub fn new() -> Self {
+        SearchLimits { time: None, depth: None, nodes: None }
+    }
+
+    /// Reads a `--time`, `--depth` or `--nodes` flag, None for other flags and invalid values.
+    pub fn parse(&mut self, flag: &str, value: &str) -> Option<()> {
+        match flag {
+            "--time" => self.time = Some(value.parse().ok()?),
+            "--depth" => self.depth = Some(value.parse().ok()?),
+            "--nodes" => self.nodes = Some(value.parse().ok()?),
+            _ => return None,
+        }
+        Some(())
+    }
+
+    /// Without any limit every position is searched for the default time.
+    pub fn or_default_time(mut self) -> Self {
+        if self.time.is_none() && self.depth.is_none() && self.nodes.is_none() {
+            self.time = Some(DEFAULT_TIME);
+        }
+        self
+    }
+}
+
+pub struct EpdResult {
+    pub mv: Option<ChessMove>,
+    pub solved: bool,
+    pub nodes: u64,
+    pub time: u64,
+}
+
+/// Best move of the position and the searched nodes, searched by a new engine without the book.
+pub fn search_move(board: Board, limits: &SearchLimits) -> (Option<ChessMove>, u64) {
+    let mut options = Options::new();
+    options.add_value("openings".to_string(), String::new());
+    let mut engine = MinMaxEngine::new(board, &options);
+    engine.node_limit = limits.nodes;
+    let mv = engine.search_depth(limits.depth.unwrap_or(usize::MAX), limits.time).chosen_move;
+    (mv, engine.evaluations_cnt as u64)
+}
+
+pub fn solve(position: &EpdPosition, limits: &SearchLimits) -> EpdResult {
+    let start = Instant::now();
+    let (mv, nodes) = search_move(position.board, limits);
+    EpdResult {
+        mv,
+        solved: mv.is_some_and(|mv| position.is_solved_by(mv)),
+        nodes,
+        time: start.elapsed().as_millis() as u64,
+    }
+}
+
+fn parse_args(args: &[String]) -> Result<(String, SearchLimits), String> {
+    let mut path = None;
+    let mut limits = SearchLimits::new();
+    let mut i = 0;
+    while i < args.len() {
+        if !args[i].starts_with("--") {
+            if path.replace(args[i].clone()).is_some() {
+                return Err(USAGE.to_string());
+            }
+            i += 1;
+            continue;
+        }
+        let value = args.get(i + 1).ok_or(USAGE.to_string())?;
+        limits.parse(&args[i], value).ok_or(USAGE.to_string())?;
+        i += 2;
+    }
+    Ok((path.ok_or(USAGE.to_string())?, limits.or_default_time()))
+}
+
+fn moves_text(moves: &[ChessMove]) -> String {
+    match moves.is_empty() {
+        true => "-".to_string(),
+        false => moves.iter().map(|mv| mv.to_string()).collect::<Vec<String>>().join(" "),
+    }
+}
+
+/// Searches the positions of an EPD suite, prints a tab separated line for every position and the totals.
+pub fn run(args: &[String]) -> Result<(), String> {
+    let (path, limits) = parse_args(args)?;
+    let content = fs::read_to_string(&path).map_err(|err| "Cannot read |".to_string() + &*path + "|: " + &*err.to_string())?;
+    let mut positions = Vec::new();
+    for (i, line) in content.lines().enumerate().filter(|(_, line)| !line.trim().is_empty()) {
+        match parse_epd(line) {
+            Ok(position) => positions.push((i + 1, position)),
+            Err(err) => eprintln!("Skipped line {}: {}", i + 1, err),
+        }
+    }
+    if positions.is_empty() {
+        return Err("No positions in |".to_string() + &*path + "|");
+    }
+
+    println!("id\tresult\tmove\tbm\tam\tnodes\ttime");
+    let (mut solved, mut nodes, mut time) = (0, 0, 0);
+    for (line, position) in positions.iter() {
+        let result = solve(position, &limits);
+        solved += result.solved as usize;
+        nodes += result.nodes;
+        time += result.time;
+        println!(
+            "{}\t{}\t{}\t{}\t{}\t{}\t{}",
+            position.id.clone().unwrap_or(format!("line {}", line)),
+            if result.solved { "solved" } else { "failed" },
+            result.mv.map(|mv| mv.to_string()).unwrap_or("-".to_string()),
+            moves_text(&position.best),
+            moves_text(&position.avoid),
+            result.nodes,
+            result.time
+        );
+    }
+    println!("total\t{}/{}\t-\t-\t-\t{}\t{}", solved, positions.len(), nodes, time);
+    Ok(())
+}
+
+#[cfg(test)]
+mod epd_runner_tests {
+    use super::*;
+
+    #[test]
+    fn solves_a_mate_in_one() {
+        let position = parse_epd("6k1/5ppp/8/8/8/8/5PPP/R5K1 w - - bm Ra8#; id \"back rank\";").unwrap();
+        let result = solve(&position, &SearchLimits { depth: Some(2), ..SearchLimits::new() });
+        assert!(result.solved);
+        let avoided = parse_epd("6k1/5ppp/8/8/8/8/5PPP/R5K1 w - - am Ra8#;").unwrap();
+        assert!(!solve(&avoided, &SearchLimits { depth: Some(2), ..SearchLimits::new() }).solved);
+    }
+
+    #[test]
+    fn stops_at_the_node_limit() {
+        let position = parse_epd("r3k2r/p1ppqpb1/bn2pnp1/3PN3/1p2P3/2N2Q1p/PPPBBPPP/R3K2R w KQkq - bm e2a6;").unwrap();
+        let result = solve(&position, &SearchLimits { nodes: Some(20_000), ..SearchLimits::new() });
+        assert!(result.nodes < 25_000, "{}", result.nodes);
+        assert!(result.mv.is_some());
+    }
+
+    #[test]
+    fn reads_limits() {
+        let args = |args: &[&str]| parse_args(&args.iter().map(|arg| arg.to_string()).collect::<Vec<String>>());
+        let (path, limits) = args(&["wac.epd"]).unwrap();
+        assert_eq!((path.as_str(), limits.time, limits.depth, limits.nodes), ("wac.epd", Some(DEFAULT_TIME), None, None));
+        let (_, limits) = args(&["wac.epd", "--depth", "6", "--nodes", "1000"]).unwrap();
+        assert_eq!((limits.time, limits.depth, limits.nodes), (None, Some(6), Some(1000)));
+        assert!(args(&["--time", "100"]).is_err());
+        assert!(args(&["wac.epd", "--time"]).is_err());
+    }
+}
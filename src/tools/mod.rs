@@ -0,0 +1,30 @@
+pub mod bench;
+pub mod book;
+pub mod book_build;
+pub mod book_check;
+pub mod epd;
+pub mod perft;
+pub mod puzzles;
+pub mod tune;
+
+const USAGE: &str = "usage: rdzawa_bestia [tune|book|perft|bench|epd|puzzles] ...";
+
+/// Runs an offline tool selected by the first command line argument, returns the exit code.
+pub fn run(args: &[String]) -> i32 {
+    let result = match args[0].as_str() {
+        "tune" => tune::run(&args[1..]),
+        "book" => book::run(&args[1..]),
+        "perft" => perft::run(&args[1..]),
+        "bench" => bench::run(&args[1..]),
+        "epd" => epd::run(&args[1..]),
+        "puzzles" => puzzles::run(&args[1..]),
+        _ => Err(USAGE.to_string()),
+    };
+    match result {
+        Ok(()) => 0,
+        Err(msg) => {
+            eprintln!("{}", msg);
+            1
+        }
+    }
+}
@@ -0,0 +1,86 @@
+use chess::{Board, ChessMove, MoveGen};
+use std::str::FromStr;
+use std::time::Instant;
+
+const USAGE: &str = "usage: perft <depth> [fen]";
+
+/// Number of leaf nodes of the legal move tree of the given depth.
+pub fn perft(board: &Board, depth: usize) -> u64 {
+    let moves = MoveGen::new_legal(board);
+    match depth {
+        0 => 1,
+        // the leaves are counted without playing them
+        1 => moves.len() as u64,
+        _ => moves.map(|mv| perft(&board.make_move_new(mv), depth - 1)).sum(),
+    }
+}
+
+/// Leaf nodes under every legal move of the position.
+pub fn divide(board: &Board, depth: usize) -> Vec<(ChessMove, u64)> {
+    MoveGen::new_legal(board)
+        .map(|mv| (mv, perft(&board.make_move_new(mv), depth.saturating_sub(1))))
+        .collect()
+}
+
+/// Divide of the position followed by the total, the time and the nodes per second.
+pub fn perft_report(board: &Board, depth: usize) -> String {
+    let start = Instant::now();
+    let moves = divide(board, depth.max(1));
+    let elapsed = start.elapsed();
+    let nodes: u64 = moves.iter().map(|(_, nodes)| nodes).sum();
+    let mut report: String = moves.iter().map(|(mv, nodes)| format!("{}: {}\n", mv, nodes)).collect();
+    report += &*format!(
+        "\nNodes searched: {}\nTime: {} ms, {} nps",
+        nodes,
+        elapsed.as_millis(),
+        (nodes as f64 / elapsed.as_secs_f64().max(1e-6)) as u64
+    );
+    report
+}
+
+pub fn run(args: &[String]) -> Result<(), String> {
+    let depth: usize = args.first().and_then(|depth| depth.parse().ok()).ok_or(USAGE.to_string())?;
+    let board = match args.len() {
+        1 => Board::default(),
+        _ => Board::from_str(&args[1..].join(" ")).map_err(|_| "Invalid FEN |".to_string() + &*args[1..].join(" ") + "|")?,
+    };
+    println!("{}", perft_report(&board, depth));
+    Ok(())
+}
+
+#[cfg(test)]
+mod perft_tests {
+    use super::*;
+    use test_case::test_case;
+
+    const START: &str = "rnbqkbnr/pppppppp/8/8/8/8/PPPPPPPP/RNBQKBNR w KQkq - 0 1";
+    const KIWIPETE: &str = "r3k2r/p1ppqpb1/bn2pnp1/3PN3/1p2P3/2N2Q1p/PPPBBPPP/R3K2R w KQkq - 0 1";
+    const POSITION_3: &str = "8/2p5/3p4/KP5r/1R3p1k/8/4P1P1/8 w - - 0 1";
+    const POSITION_4: &str = "r3k2r/Pppp1ppp/1b3nbN/nP6/BBP1P3/q4N2/Pp1P2PP/R2Q1RK1 w kq - 0 1";
+    const POSITION_5: &str = "rnbq1k1r/pp1Pbppp/2p5/8/2B5/8/PPP1NnPP/RNBQK2R w KQ - 1 8";
+    const POSITION_6: &str = "r4rk1/1pp1qppp/p1np1n2/2b1p1B1/2B1P1b1/P1NP1N2/1PP1QPPP/R4RK1 w - - 0 10";
+
+    #[test_case(START, &[20, 400, 8902, 197281]; "start position")]
+    #[test_case(KIWIPETE, &[48, 2039, 97862]; "kiwipete")]
+    #[test_case(POSITION_3, &[14, 191, 2812, 43238]; "position 3")]
+    #[test_case(POSITION_4, &[6, 264, 9467]; "position 4")]
+    #[test_case(POSITION_5, &[44, 1486, 62379]; "position 5")]
+    #[test_case(POSITION_6, &[46, 2079, 89890]; "position 6")]
+    fn counts_leaf_nodes(fen: &str, expected: &[u64]) {
+        let board = Board::from_str(fen).unwrap();
+        for (depth, nodes) in expected.iter().enumerate() {
+            assert_eq!(perft(&board, depth + 1), *nodes, "depth {}", depth + 1);
+        }
+    }
+
+    #[test]
+    fn divide_adds_up_to_perft() {
+        let board = Board::from_str(KIWIPETE).unwrap();
+        let moves = divide(&board, 2);
+        assert_eq!(moves.len(), 48);
+        assert_eq!(moves.iter().map(|(_, nodes)| nodes).sum::<u64>(), 2039);
+        let castle = moves.iter().find(|(mv, _)| mv.to_string() == "e1g1").unwrap();
+        assert_eq!(castle.1, 43);
+        assert!(perft_report(&board, 2).contains("Nodes searched: 2039"));
+    }
+}
@@ -0,0 +1,254 @@
+use crate::tools::epd::{search_move, SearchLimits};
+use chess::{Board, BoardStatus, ChessMove};
+use std::collections::BTreeMap;
+use std::fs;
+use std::str::FromStr;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::thread;
+
+const RATING_BUCKET: u32 = 200;
+
+const USAGE: &str = "usage: puzzles <puzzles.csv> [--limit <n>] [--threads <n>] [--time <ms>] [--depth <plies>] [--nodes <n>]";
+
+/// Puzzle of the Lichess database, the first move is played by the opponent before the solution.
+pub struct Puzzle {
+    pub id: String,
+    pub board: Board,
+    pub moves: Vec<ChessMove>,
+    pub rating: u32,
+    pub themes: Vec<String>,
+}
+
+/// Line of the Lichess CSV: `PuzzleId,FEN,Moves,Rating,RatingDeviation,Popularity,NbPlays,Themes,...`.
+pub fn parse_puzzle(line: &str) -> Result<Puzzle, String> {
+    let fields: Vec<&str> = line.split(',').collect();
+    if fields.len() < 8 {
+        return Err("Missing fields in |".to_string() + line + "|");
+    }
+    let board = Board::from_str(fields[1]).map_err(|_| "Invalid position |".to_string() + fields[1] + "|")?;
+    let mut moves = Vec::new();
+    let mut position = board;
+    for mv in fields[2].split_whitespace() {
+        let mv = ChessMove::from_str(mv)
+            .ok()
+            .filter(|mv| position.legal(*mv))
+            .ok_or("Illegal move |".to_string() + mv + "| in puzzle " + fields[0])?;
+        moves.push(mv);
+        position = position.make_move_new(mv);
+    }
+    if moves.len() < 2 {
+        return Err("Missing solution in puzzle ".to_string() + fields[0]);
+    }
+    Ok(Puzzle {
+        id: fields[0].to_string(),
+        board,
+        moves,
+        rating: fields[3].parse().map_err(|_| "Invalid rating |".to_string() + fields[3] + "| in puzzle " + fields[0])?,
+        themes: fields[7].split_whitespace().map(|theme| theme.to_string()).collect(),
+    })
+}
+
+/// Plays the setup move and the replies of the opponent, the engine has to find every move of the
+/// solution, any checkmate is accepted as in Lichess.
+pub fn solve_puzzle(puzzle: &Puzzle, limits: &SearchLimits) -> bool {
+    let mut board = puzzle.board.make_move_new(puzzle.moves[0]);
+    for (i, expected) in puzzle.moves.iter().enumerate().skip(1) {
+        if i % 2 == 1 {
+            let Some(mv) = search_move(board, limits).0 else {
+                return false;
+            };
+            if board.make_move_new(mv).status() == BoardStatus::Checkmate {
+                return true;
+            }
+            if mv != *expected {
+                return false;
+            }
+        }
+        board = board.make_move_new(*expected);
+    }
+    true
+}
+
+#[derive(Clone, Copy, Default)]
+pub struct Accuracy {
+    pub solved: usize,
+    pub total: usize,
+}
+
+impl Accuracy {
+    fn add(&mut self, solved: bool) {
+        self.solved += solved as usize;
+        self.total += 1;
+    }
+
+    fn percent(&self) -> f64 {
+        100.0 * self.solved as f64 / self.total.max(1) as f64
+    }
+}
+
+/// Solved puzzles in total, by theme and by rating bucket.
+#[derive(Default)]
+pub struct PuzzleReport {
+    pub total: Accuracy,
+    pub themes: BTreeMap<String, Accuracy>,
+    // keyed by the lowest rating of the bucket
+    pub ratings: BTreeMap<u32, Accuracy>,
+    pub failed: Vec<String>,
+}
+
+impl PuzzleReport {
+    pub fn add(&mut self, puzzle: &Puzzle, solved: bool) {
+        self.total.add(solved);
+        if !solved {
+            self.failed.push(puzzle.id.clone());
+        }
+        for theme in puzzle.themes.iter() {
+            self.themes.entry(theme.clone()).or_default().add(solved);
+        }
+        self.ratings.entry(puzzle.rating / RATING_BUCKET * RATING_BUCKET).or_default().add(solved);
+    }
+
+    /// Tab separated lines of the group, the name, the solved and all puzzles and the accuracy.
+    pub fn lines(&self) -> Vec<String> {
+        let line = |group: &str, name: &str, accuracy: &Accuracy| {
+            format!("{}\t{}\t{}\t{}\t{:.1}", group, name, accuracy.solved, accuracy.total, accuracy.percent())
+        };
+        let mut lines = vec!["group\tname\tsolved\ttotal\taccuracy".to_string()];
+        lines.extend(self.themes.iter().map(|(theme, accuracy)| line("theme", theme, accuracy)));
+        lines.extend(self.ratings.iter().map(|(rating, accuracy)| {
+            line("rating", &format!("{}-{}", rating, rating + RATING_BUCKET - 1), accuracy)
+        }));
+        lines.push(line("total", "all", &self.total));
+        lines
+    }
+}
+
+/// Solves the puzzles on the threads, every thread takes the next unsolved puzzle.
+pub fn solve_puzzles(puzzles: &[Puzzle], limits: &SearchLimits, threads: usize) -> PuzzleReport {
+    let next = AtomicUsize::new(0);
+    let mut results: Vec<(usize, bool)> = thread::scope(|scope| {
+        let handles: Vec<_> = (0..threads.max(1))
+            .map(|_| {
+                scope.spawn(|| {
+                    let mut results = Vec::new();
+                    loop {
+                        let i = next.fetch_add(1, Ordering::Relaxed);
+                        let Some(puzzle) = puzzles.get(i) else {
+                            return results;
+                        };
+                        results.push((i, solve_puzzle(puzzle, limits)));
+                    }
+                })
+            })
+            .collect();
+        handles.into_iter().flat_map(|handle| handle.join().unwrap()).collect()
+    });
+    // failed puzzles in the order of the file
+    results.sort_by_key(|(i, _)| *i);
+    let mut report = PuzzleReport::default();
+    for (i, solved) in results {
+        report.add(&puzzles[i], solved);
+    }
+    report
+}
+
+struct PuzzleArgs {
+    path: String,
+    limit: Option<usize>,
+    threads: usize,
+    limits: SearchLimits,
+}
+
+fn parse_args(args: &[String]) -> Result<PuzzleArgs, String> {
+    let mut path = None;
+    let mut puzzle_args = PuzzleArgs { path: String::new(), limit: None, threads: 1, limits: SearchLimits::new() };
+    let mut i = 0;
+    while i < args.len() {
+        if !args[i].starts_with("--") {
+            if path.replace(args[i].clone()).is_some() {
+                return Err(USAGE.to_string());
+            }
+            i += 1;
+            continue;
+        }
+        let value = args.get(i + 1).ok_or(USAGE.to_string())?;
+        match args[i].as_str() {
+            "--limit" => puzzle_args.limit = Some(value.parse().map_err(|_| USAGE.to_string())?),
+            "--threads" => puzzle_args.threads = value.parse().map_err(|_| USAGE.to_string())?,
+            flag => puzzle_args.limits.parse(flag, value).ok_or(USAGE.to_string())?,
+        }
+        i += 2;
+    }
+    puzzle_args.path = path.ok_or(USAGE.to_string())?;
+    puzzle_args.limits = puzzle_args.limits.or_default_time();
+    Ok(puzzle_args)
+}
+
+/// Solves the puzzles of a Lichess CSV and prints the accuracy by theme, by rating and in total.
+pub fn run(args: &[String]) -> Result<(), String> {
+    let args = parse_args(args)?;
+    let content = fs::read_to_string(&args.path).map_err(|err| "Cannot read |".to_string() + &*args.path + "|: " + &*err.to_string())?;
+    let mut puzzles = Vec::new();
+    for (i, line) in content.lines().enumerate() {
+        if args.limit.is_some_and(|limit| puzzles.len() >= limit) {
+            break;
+        }
+        if line.trim().is_empty() || line.starts_with("PuzzleId") {
+            continue;
+        }
+        match parse_puzzle(line) {
+            Ok(puzzle) => puzzles.push(puzzle),
+            Err(err) => eprintln!("Skipped line {}: {}", i + 1, err),
+        }
+    }
+    if puzzles.is_empty() {
+        return Err("No puzzles in |".to_string() + &*args.path + "|");
+    }
+
+    let report = solve_puzzles(&puzzles, &args.limits, args.threads);
+    if !report.failed.is_empty() {
+        eprintln!("Failed puzzles: {}", report.failed.join(" "));
+    }
+    for line in report.lines() {
+        println!("{}", line);
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod puzzles_tests {
+    use super::*;
+
+    const MATE_IN_2: &str = "00sHx,q3k1nr/1pp1nQpp/3p4/1P2p3/4P3/B1PP1b2/B5PP/5K2 b k - 0 17,e8d7 a2e6 d7d8 f7f8,1760,80,83,72,\
+mate mateIn2 middlegame short,https://lichess.org/yyznGmXs/black#34,Italian_Game Italian_Game_Classical_Variation";
+    // the solution starts with a quiet bishop move instead of the check
+    const WRONG: &str = "wrong,q3k1nr/1pp1nQpp/3p4/1P2p3/4P3/B1PP1b2/B5PP/5K2 b k - 0 17,e8d7 a2b1,1320,80,83,72,middlegame short,,";
+
+    #[test]
+    fn reads_puzzles() {
+        let puzzle = parse_puzzle(MATE_IN_2).unwrap();
+        assert_eq!(puzzle.id, "00sHx");
+        assert_eq!(puzzle.moves.len(), 4);
+        assert_eq!(puzzle.rating, 1760);
+        assert_eq!(puzzle.themes, vec!["mate", "mateIn2", "middlegame", "short"]);
+        assert!(parse_puzzle("x,q3k1nr/1pp1nQpp/3p4/1P2p3/4P3/B1PP1b2/B5PP/5K2 b k - 0 17,e8d7 a2a3,1500,0,0,0,,").is_err());
+        assert!(parse_puzzle("x,q3k1nr/1pp1nQpp/3p4/1P2p3/4P3/B1PP1b2/B5PP/5K2 b k - 0 17,e8d7").is_err());
+    }
+
+    #[test]
+    fn reports_accuracy_by_theme_and_rating() {
+        let puzzles = vec![parse_puzzle(MATE_IN_2).unwrap(), parse_puzzle(WRONG).unwrap()];
+        let limits = SearchLimits { depth: Some(4), ..SearchLimits::new() };
+        assert!(solve_puzzle(&puzzles[0], &limits));
+        assert!(!solve_puzzle(&puzzles[1], &limits));
+
+        let report = solve_puzzles(&puzzles, &limits, 2);
+        let lines = report.lines();
+        assert!(lines.contains(&"theme\tmateIn2\t1\t1\t100.0".to_string()), "{:?}", lines);
+        assert!(lines.contains(&"theme\tmiddlegame\t1\t2\t50.0".to_string()));
+        assert!(lines.contains(&"rating\t1200-1399\t0\t1\t0.0".to_string()));
+        assert!(lines.contains(&"rating\t1600-1799\t1\t1\t100.0".to_string()));
+        assert_eq!(lines.last().unwrap(), "total\tall\t1\t2\t50.0");
+        assert_eq!(report.failed, vec!["wrong"]);
+    }
+}
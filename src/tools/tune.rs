@@ -0,0 +1,331 @@
+use crate::features::eval_params::{set_eval_params, EvalParams};
+use crate::features::quiescence::quiescence;
+use crate::io::options::Options;
+use crate::minmax_engine::MinMaxEngine;
+use chess::{Board, Color};
+use std::fs;
+use std::str::FromStr;
+use std::sync::mpsc::{self, Receiver, Sender};
+use std::thread::{self, Scope};
+use std::time::{Duration, Instant};
+
+const QUIESCENCE_DEPTH: usize = 8;
+const DEFAULT_ITERATIONS: usize = 50;
+// range and number of steps of the golden section search of the sigmoid scaling
+const MIN_K: f64 = 0.05;
+const MAX_K: f64 = 5.0;
+const K_SEARCH_STEPS: usize = 40;
+
+const USAGE: &str = "usage: tune <dataset> <output.json> [--params <start.json>] [--iterations <n>] [--threads <n>]";
+
+/// Position with the game result from the white point of view: 1 win, 0.5 draw, 0 loss.
+pub struct LabelledPosition {
+    pub board: Board,
+    pub result: f64,
+}
+
+fn parse_result(label: &str) -> Option<f64> {
+    if label.contains("1/2-1/2") {
+        return Some(0.5);
+    }
+    if label.contains("1-0") {
+        return Some(1.0);
+    }
+    if label.contains("0-1") {
+        return Some(0.0);
+    }
+    let last = label
+        .rsplit(|c: char| c.is_whitespace() || "[];|,\"".contains(c))
+        .find(|token| !token.is_empty())?;
+    match last {
+        "win" => Some(1.0),
+        "draw" => Some(0.5),
+        "loss" => Some(0.0),
+        value => value.parse::<f64>().ok().filter(|result| (0.0..=1.0).contains(result)),
+    }
+}
+
+/// Parses an EPD line with a result opcode, like `... c9 "1-0";`, or a FEN followed by
+/// the result as `1.0`, `[0.5]`, `| 0` or `win`/`draw`/`loss`.
+pub fn parse_labelled_position(line: &str) -> Option<LabelledPosition> {
+    let tokens: Vec<&str> = line.split_whitespace().collect();
+    if tokens.len() < 5 || tokens[0].starts_with('#') {
+        return None;
+    }
+    let board = Board::from_str(&(tokens[..4].join(" ") + " 0 1")).ok()?;
+    let mut label = &tokens[4..];
+    // skip the move counters of a full FEN
+    if label.len() >= 2 && label[0].parse::<u32>().is_ok() && label[1].parse::<u32>().is_ok() {
+        label = &label[2..];
+    }
+    let result = parse_result(&label.join(" "))?;
+    Some(LabelledPosition { board, result })
+}
+
+pub fn load_dataset(path: &str) -> Result<Vec<LabelledPosition>, String> {
+    let content = fs::read_to_string(path).map_err(|err| "Cannot read |".to_string() + path + "|: " + &*err.to_string())?;
+    Ok(content.lines().filter_map(parse_labelled_position).collect())
+}
+
+fn sigmoid(score: i32, k: f64) -> f64 {
+    1.0 / (1.0 + 10f64.powf(-k * score as f64 / 400.0))
+}
+
+// white relative quiescence score of the position
+fn quiescence_score(engine: &mut MinMaxEngine, position: &LabelledPosition, end_time: Instant) -> i32 {
+    let score = quiescence(engine, position.board, QUIESCENCE_DEPTH, 0, -i32::MAX, i32::MAX, end_time).score;
+    if position.board.side_to_move() == Color::White {
+        score
+    } else {
+        -score
+    }
+}
+
+// channels of a thread which scores its chunk of the positions for every received parameters
+struct Worker {
+    params: Sender<EvalParams>,
+    scores: Receiver<Vec<i32>>,
+}
+
+impl Worker {
+    // the engine lives as long as the thread, the parameters and the pawn table are per thread
+    fn spawn<'scope, 'env>(scope: &'scope Scope<'scope, 'env>, chunk: &'env [LabelledPosition]) -> Worker {
+        let (params_sender, params_receiver) = mpsc::channel::<EvalParams>();
+        let (scores_sender, scores_receiver) = mpsc::channel();
+        scope.spawn(move || {
+            let mut options = Options::new();
+            options.add_value("openings".to_string(), "".to_string());
+            let mut engine = MinMaxEngine::new(Board::default(), &options);
+            let end_time = Instant::now() + Duration::from_secs(365 * 24 * 60 * 60);
+            for params in params_receiver {
+                set_eval_params(params);
+                engine.transposition_table.restart();
+                let scores = chunk.iter().map(|position| quiescence_score(&mut engine, position, end_time)).collect();
+                if scores_sender.send(scores).is_err() {
+                    return;
+                }
+            }
+        });
+        Worker { params: params_sender, scores: scores_receiver }
+    }
+}
+
+fn mean_error(positions: &[LabelledPosition], scores: &[i32], k: f64) -> f64 {
+    let total: f64 = positions
+        .iter()
+        .zip(scores)
+        .map(|(position, score)| (position.result - sigmoid(*score, k)).powi(2))
+        .sum();
+    total / positions.len().max(1) as f64
+}
+
+/// Sigmoid scaling which fits the scores best, found by a golden section search.
+pub fn find_k(positions: &[LabelledPosition], scores: &[i32]) -> f64 {
+    let ratio = (5f64.sqrt() - 1.0) / 2.0;
+    let (mut low, mut high) = (MIN_K, MAX_K);
+    for _ in 0..K_SEARCH_STEPS {
+        let left = high - ratio * (high - low);
+        let right = low + ratio * (high - low);
+        if mean_error(positions, scores, left) < mean_error(positions, scores, right) {
+            high = right;
+        } else {
+            low = left;
+        }
+    }
+    (low + high) / 2.0
+}
+
+/// Texel tuner, its threads stop when it is dropped at the end of the scope.
+pub struct Tuner<'a> {
+    positions: &'a [LabelledPosition],
+    workers: Vec<Worker>,
+    k: f64,
+}
+
+impl<'a> Tuner<'a> {
+    pub fn new<'scope>(
+        scope: &'scope Scope<'scope, 'a>,
+        positions: &'a [LabelledPosition],
+        params: &EvalParams,
+        threads: usize,
+    ) -> Self {
+        let chunk_size = positions.len().div_ceil(threads.max(1)).max(1);
+        let workers = positions.chunks(chunk_size).map(|chunk| Worker::spawn(scope, chunk)).collect();
+        let mut tuner = Tuner { positions, workers, k: 1.0 };
+        tuner.k = find_k(positions, &tuner.quiescence_scores(params));
+        tuner
+    }
+
+    pub fn k(&self) -> f64 {
+        self.k
+    }
+
+    /// White relative quiescence scores of the positions using the given parameters.
+    fn quiescence_scores(&self, params: &EvalParams) -> Vec<i32> {
+        for worker in self.workers.iter() {
+            worker.params.send(params.clone()).unwrap();
+        }
+        self.workers.iter().flat_map(|worker| worker.scores.recv().unwrap()).collect()
+    }
+
+    pub fn error(&self, params: &EvalParams) -> f64 {
+        mean_error(self.positions, &self.quiescence_scores(params), self.k)
+    }
+
+    /// One pass of the Texel local search: every weight is moved by one in the direction
+    /// which lowers the error, if any. Returns the error after the pass.
+    pub fn local_search_pass(&self, params: &mut EvalParams, mut best_error: f64) -> f64 {
+        let mut weights = params.weights();
+        for i in 0..weights.len() {
+            for delta in [1, -1] {
+                weights[i] += delta;
+                params.set_weights(&weights);
+                let error = self.error(params);
+                if error < best_error {
+                    best_error = error;
+                    break;
+                }
+                weights[i] -= delta;
+            }
+        }
+        params.set_weights(&weights);
+        best_error
+    }
+}
+
+struct TuneArgs {
+    dataset: String,
+    output: String,
+    params: Option<String>,
+    iterations: usize,
+    threads: usize,
+}
+
+fn parse_args(args: &[String]) -> Result<TuneArgs, String> {
+    if args.len() < 2 {
+        return Err(USAGE.to_string());
+    }
+    let mut tune_args = TuneArgs {
+        dataset: args[0].clone(),
+        output: args[1].clone(),
+        params: None,
+        iterations: DEFAULT_ITERATIONS,
+        threads: thread::available_parallelism().map(|threads| threads.get()).unwrap_or(1),
+    };
+    let mut i = 2;
+    while i < args.len() {
+        let value = args.get(i + 1).ok_or(USAGE.to_string())?;
+        match args[i].as_str() {
+            "--params" => tune_args.params = Some(value.clone()),
+            "--iterations" => tune_args.iterations = value.parse().map_err(|_| USAGE.to_string())?,
+            "--threads" => tune_args.threads = value.parse().map_err(|_| USAGE.to_string())?,
+            _ => return Err(USAGE.to_string()),
+        }
+        i += 2;
+    }
+    Ok(tune_args)
+}
+
+pub fn run(args: &[String]) -> Result<(), String> {
+    let args = parse_args(args)?;
+    let positions = load_dataset(&args.dataset)?;
+    if positions.is_empty() {
+        return Err("No labelled positions in |".to_string() + &*args.dataset + "|");
+    }
+    let mut params = match &args.params {
+        Some(path) => EvalParams::load(path)?,
+        None => EvalParams::default(),
+    };
+    println!("Loaded {} positions", positions.len());
+
+    thread::scope(|scope| {
+        let tuner = Tuner::new(scope, &positions, &params, args.threads);
+        let mut error = tuner.error(&params);
+        println!("K {:.4}, initial error {:.6}", tuner.k(), error);
+
+        for iteration in 1..=args.iterations {
+            let start = Instant::now();
+            let new_error = tuner.local_search_pass(&mut params, error);
+            // saved after every pass, so a long run can be interrupted
+            params.save(&args.output)?;
+            println!(
+                "Iteration {} error {:.6} in {:.1}s",
+                iteration,
+                new_error,
+                start.elapsed().as_secs_f64()
+            );
+            if new_error >= error {
+                break;
+            }
+            error = new_error;
+        }
+        Ok::<(), String>(())
+    })?;
+    println!("Parameters written to {}", args.output);
+    Ok(())
+}
+
+#[cfg(test)]
+mod tune_tests {
+    use super::*;
+    use test_case::test_case;
+
+    #[test_case("4k3/8/8/8/8/8/8/4KQ2 w - - c9 \"1-0\";", 1.0; "epd win")]
+    #[test_case("4k3/8/8/8/8/8/8/4KQ2 w - - c9 \"1/2-1/2\";", 0.5; "epd draw")]
+    #[test_case("4k3/8/8/8/8/8/8/4KQ2 w - - 0 1 [0.0]", 0.0; "fen with bracket")]
+    #[test_case("4k3/8/8/8/8/8/8/4KQ2 w - - 0 1 | 0.5", 0.5; "fen with separator")]
+    #[test_case("4k3/8/8/8/8/8/8/4KQ2 b - - 12 40; 1", 1.0; "fen with semicolon")]
+    #[test_case("4k3/8/8/8/8/8/8/4KQ2 w - - loss", 0.0; "epd with word")]
+    fn parses_labelled_positions(line: &str, result: f64) {
+        let position = parse_labelled_position(line).unwrap();
+        assert_eq!(position.result, result);
+        assert_eq!(position.board.king_square(Color::White), chess::Square::E1);
+    }
+
+    #[test_case(""; "empty")]
+    #[test_case("# comment"; "comment")]
+    #[test_case("4k3/8/8/8/8/8/8/4KQ2 w - - 0 1"; "no result")]
+    #[test_case("4k3/8/8/8/8/8/8/4KQ2 w - - 0 1 [2.0]"; "result out of range")]
+    fn skips_invalid_lines(line: &str) {
+        assert!(parse_labelled_position(line).is_none());
+    }
+
+    fn dataset() -> Vec<LabelledPosition> {
+        [
+            "4k3/8/8/8/8/8/8/3QK3 w - - [1.0]",
+            "3qk3/8/8/8/8/8/8/4K3 w - - [0.0]",
+            "4k3/pppppppp/8/8/8/8/PPPPPPPP/4K3 w - - [0.5]",
+            "4k3/8/8/8/8/8/PPPPP3/4K3 b - - [1.0]",
+            "4k3/ppp5/8/8/8/8/8/4K3 w - - [0.0]",
+        ]
+        .iter()
+        .filter_map(|line| parse_labelled_position(line))
+        .collect()
+    }
+
+    #[test]
+    fn fitted_k_is_in_range() {
+        let positions = dataset();
+        thread::scope(|scope| {
+            let tuner = Tuner::new(scope, &positions, &EvalParams::default(), 2);
+            let scores = tuner.quiescence_scores(&EvalParams::default());
+            let k = find_k(&positions, &scores);
+            assert!(k > MIN_K && k < MAX_K);
+            assert_eq!(k, tuner.k());
+            assert!(mean_error(&positions, &scores, k) <= mean_error(&positions, &scores, 1.0) + 1e-9);
+        });
+    }
+
+    #[test]
+    fn local_search_does_not_increase_error() {
+        let positions = dataset();
+        let mut params = EvalParams::default();
+        thread::scope(|scope| {
+            let tuner = Tuner::new(scope, &positions, &params, 2);
+            let error = tuner.error(&params);
+            let tuned_error = tuner.local_search_pass(&mut params, error);
+            assert!(tuned_error <= error);
+            assert!((tuner.error(&params) - tuned_error).abs() < 1e-12);
+        });
+    }
+}